@@ -1,12 +1,17 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::collections::hash_map::DefaultHasher;
+use globset::{Glob, GlobSetBuilder};
+use std::collections::HashSet;
 use std::fs::File;
-use std::hash::Hasher;
-use std::io::{self, BufWriter};
+use std::io::BufWriter;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
-use steamws::bsp::BSPHeader;
-use steamws::bsp::{lump_indices::LumpIndex, BSPReader};
+use steamws::bsp::check::Severity;
+use steamws::bsp::dependencies::{BSPDependencyKind, BSPDependencyStatus};
+use steamws::bsp::diff::{self, ChangeKind};
+use steamws::bsp::lump_file::LumpFile;
+use steamws::bsp::remap::PathRemap;
+use steamws::bsp::{lighting, lump_indices::LumpIndex, lzma, strip, BSPReader};
+use steamws::mesh;
+use steamws::search_path::{normalize_path, SearchPath};
 use strum::IntoEnumIterator;
 
 use clap::{Args, Parser, Subcommand};
@@ -26,6 +31,86 @@ enum SubCommand {
     #[command(alias = "ls-pak")]
     ListPackedFiles(ListPackedFilesCommand),
 
+    /// Lists content referenced by the map
+    ///
+    /// Includes materials, models, sounds, skybox and cubemap textures,
+    /// along with their own dependencies (e.g. textures used by materials).
+    /// Each dependency is marked as packed (in the pakfile), found
+    /// (in a content root) or missing.
+    #[command(alias = "deps")]
+    Dependencies(DependenciesCommand),
+
+    /// Lists materials used by map brushes, with the number of faces using each
+    ///
+    /// Tool textures and materials on nodraw faces are flagged
+    Materials(MaterialsCommand),
+
+    /// Packs custom content used by the map into its pakfile
+    ///
+    /// Dependencies found in a content root (folder or .gma) are added
+    /// to the pakfile. Dependencies that are already packed or found in
+    /// a base content root are skipped.
+    Autopack(AutopackCommand),
+
+    /// Renames materials and models referenced by the map
+    ///
+    /// Rewrites brush materials, static prop models and entity "model" keys
+    /// starting with given path prefixes, e.g. `--materials oldteam=newteam`
+    /// renames materials/oldteam/wall to materials/newteam/wall
+    Remap(RemapCommand),
+
+    /// Reconstructs a Hammer .vmf from map brushes and entities
+    ///
+    /// Displacements, overlays and static props are not recovered
+    ToVmf(ToVmfCommand),
+
+    /// Exports world geometry (including displacements) as a mesh
+    ///
+    /// Format is picked from output extension: .gltf, .glb or .obj.
+    /// Coordinates are converted to Y up, but kept in Source units
+    ExportMesh(ExportMeshCommand),
+
+    /// Exports face lightmaps as PNG atlases
+    ///
+    /// Only the first lightstyle of each face is exported
+    Lightmaps(LightmapsCommand),
+
+    /// Lists cubemap samples and whether their textures are in the pakfile
+    ///
+    /// Cubemap textures are packed into the map by running buildcubemaps
+    Cubemaps(CubemapsCommand),
+
+    /// Extracts lumps into .lmp files and applies .lmp files back into the map
+    ///
+    /// Lump files are only valid for the map revision they were made from
+    Lump(LumpCommand),
+
+    /// Inspects precomputed visibility (PVS) between clusters
+    Vis(VisCommand),
+
+    /// Removes data that isn't needed to play the map, to make it smaller to download
+    ///
+    /// Prints the number of bytes saved in each lump
+    Strip(StripCommand),
+
+    /// Compares two versions of a map
+    ///
+    /// Lists changed lumps, entities (matched by hammer id), packed files and static props
+    Diff(DiffCommand),
+
+    /// Validates lump bounds and contents to detect corrupt or truncated maps
+    ///
+    /// Exits with a non-zero code if errors are found
+    Check(CheckCommand),
+
+    /// Compresses lumps with LZMA, like newer Source branches (e.g. CS:GO) do
+    ///
+    /// Note that older branches can't load maps with compressed lumps
+    Compress(CompressCommand),
+
+    /// Decompresses LZMA compressed lumps
+    Decompress(CompressCommand),
+
     /// Separates input bsp into bsp with entity lump removed and lump file with just the entity lump, WIP!
     ExtractEntityLump(ExtractEntityLumpCommand),
 }
@@ -37,7 +122,7 @@ struct InfoCommand {
 
     /// Print information about lumps
     /// Includes length, offset, ident
-    /// Also calculates and print lump hashes (using arbitrary hashing algorithm, just for comparison purposes)
+    /// Also calculates and prints SHA-256 hashes of (decompressed) lump contents
     #[arg{short, long}]
     lumps: bool,
 }
@@ -51,6 +136,270 @@ struct ListPackedFilesCommand {
     include_size: bool,
 }
 
+#[derive(Args)]
+struct DependenciesCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Folder containing "materials", "models" etc. or a .gma to look up dependencies from.
+    /// Can be given multiple times; earlier roots take precedence
+    #[arg(long)]
+    content_root: Vec<PathBuf>,
+
+    /// Only print missing dependencies
+    #[arg(long)]
+    missing: bool,
+
+    /// Print dependencies in graphviz format
+    #[arg(long)]
+    dot: bool,
+}
+
+#[derive(Args)]
+struct MaterialsCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Omit tool textures
+    #[arg(long)]
+    no_tools: bool,
+}
+
+#[derive(Args)]
+struct AutopackCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output bsp
+    output: PathBuf,
+
+    /// Folder or .gma containing custom content to pack.
+    /// Can be given multiple times; earlier roots take precedence
+    #[arg(long)]
+    content_root: Vec<PathBuf>,
+
+    /// Folder or .gma containing content players already have (e.g. extracted game content).
+    /// Dependencies found from these are not packed
+    #[arg(long)]
+    base_root: Vec<PathBuf>,
+
+    /// Prints what the command would pack if executed without
+    /// this flag
+    #[arg(long, short = 'n')]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct RemapCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output bsp
+    output: PathBuf,
+
+    /// Material path prefix to rename, as old=new. Paths are relative to "materials".
+    /// Can be given multiple times
+    #[arg(long, value_parser = parse_remap)]
+    materials: Vec<PathRemap>,
+
+    /// Model path prefix to rename, as old=new. Paths are relative to "models".
+    /// Can be given multiple times
+    #[arg(long, value_parser = parse_remap)]
+    models: Vec<PathRemap>,
+}
+
+fn parse_remap(s: &str) -> Result<PathRemap, String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok(PathRemap::new(from, to)),
+        _ => Err(format!("expected old=new, got {:?}", s)),
+    }
+}
+
+#[derive(Args)]
+struct ToVmfCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output vmf
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct ExportMeshCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output .gltf, .glb or .obj
+    output: PathBuf,
+
+    /// Include skybox faces
+    #[arg(long)]
+    include_sky: bool,
+}
+
+#[derive(Args)]
+struct LightmapsCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Folder to write lightmap_<n>.png atlases and placements.json to
+    output: PathBuf,
+
+    /// Export HDR lightmaps instead of LDR ones
+    #[arg(long)]
+    hdr: bool,
+
+    /// Width and height of atlases
+    #[arg(long, default_value_t = 1024)]
+    atlas_size: u32,
+}
+
+#[derive(Args)]
+struct CubemapsCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Only print cubemaps whose textures are missing from the pakfile
+    #[arg(long)]
+    missing: bool,
+}
+
+#[derive(Args)]
+struct LumpCommand {
+    #[command(subcommand)]
+    subcmd: LumpSubCommand,
+}
+
+#[derive(Subcommand)]
+enum LumpSubCommand {
+    /// Writes a lump into a .lmp file
+    Extract(LumpExtractCommand),
+
+    /// Replaces a lump of the map with the contents of a .lmp file
+    Apply(LumpApplyCommand),
+}
+
+#[derive(Args)]
+struct LumpExtractCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Lump index or name, e.g. 0 or entities
+    lump: LumpIndex,
+
+    /// Lump file. By default <input without extension>_l_0.lmp
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct LumpApplyCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Lump file
+    lump_file: PathBuf,
+
+    /// Output bsp. By default the source bsp is overwritten
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct VisCommand {
+    #[command(subcommand)]
+    subcmd: VisSubCommand,
+}
+
+#[derive(Subcommand)]
+enum VisSubCommand {
+    /// Prints cluster count, average visible leaves and the worst clusters
+    Stats(VisStatsCommand),
+
+    /// Lists clusters potentially visible from a point
+    Query(VisQueryCommand),
+}
+
+#[derive(Args)]
+struct VisStatsCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Number of clusters seeing the most leaves to print
+    #[arg(long, default_value_t = 10)]
+    worst: usize,
+}
+
+#[derive(Args)]
+struct VisQueryCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    #[arg(allow_hyphen_values = true)]
+    x: f32,
+
+    #[arg(allow_hyphen_values = true)]
+    y: f32,
+
+    #[arg(allow_hyphen_values = true)]
+    z: f32,
+}
+
+#[derive(Args)]
+struct StripCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output bsp
+    output: PathBuf,
+
+    /// Remove pakfile files matching given glob, e.g. "**.txt". Can be given multiple times
+    #[arg(long)]
+    pak_exclude: Vec<String>,
+
+    /// Remove original faces, which are only used by vrad
+    #[arg(long)]
+    original_faces: bool,
+
+    /// Remove collision models of non-solid brush entities (e.g. func_illusionary)
+    #[arg(long)]
+    physcollide: bool,
+
+    /// Remove LDR lighting, if the map has HDR lighting
+    #[arg(long, conflicts_with = "drop_hdr_lighting")]
+    drop_ldr_lighting: bool,
+
+    /// Remove HDR lighting, if the map has LDR lighting
+    #[arg(long)]
+    drop_hdr_lighting: bool,
+}
+
+#[derive(Args)]
+struct DiffCommand {
+    /// Old bsp
+    a: PathBuf,
+
+    /// New bsp
+    b: PathBuf,
+}
+
+#[derive(Args)]
+struct CheckCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct CompressCommand {
+    /// Source bsp
+    input: PathBuf,
+
+    /// Output bsp
+    output: PathBuf,
+}
+
 #[derive(Args)]
 struct ExtractEntityLumpCommand {
     /// Source bsp
@@ -74,6 +423,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let bsp_reader = BSPReader::from_reader(reader)?;
 
             println!("map version = {}", bsp_reader.header().version);
+            println!("engine branch = {}", bsp_reader.header().engine_branch());
             println!("map revision = {}", bsp_reader.header().map_revision);
 
             if t.lumps {
@@ -81,14 +431,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("===");
 
                 for lump_index in LumpIndex::iter() {
-                    let mut hasher = DefaultHasher::new();
-                    hasher.write(bsp.lump_slice(lump_index));
-                    let hash = hasher.finish();
+                    let hash = bsp.lump_hash(lump_index)?;
 
                     let lump = &bsp.header.lumps[lump_index as usize];
+                    let compression = match lzma::read_header(bsp.lump_slice(lump_index)?) {
+                        Ok(header) => format!("lzma(actual_len={})", header.actual_size),
+                        Err(_) => "none".to_string(),
+                    };
                     println!(
-                        "lump #{}: off={} len={} ident={:?} hash={:x}",
-                        lump_index as usize, lump.off, lump.len, lump.ident, hash
+                        "lump #{}: off={} len={} ident={:?} compression={} hash={}",
+                        lump_index as usize, lump.off, lump.len, lump.ident, compression, hash
                     );
                 }
             }
@@ -99,10 +451,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let path = Path::new(&t.input);
             let file = File::open(path)?;
             let reader = BufReader::new(file);
-            let bsp_reader = BSPReader::from_reader(reader)?;
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
 
-            let mut archive =
-                zip::ZipArchive::new(bsp_reader.into_reader_for_lump(LumpIndex::LUMP_PAKFILE)?)?;
+            let mut archive = match bsp.pakfile()? {
+                Some(archive) => archive,
+                None => return Ok(()),
+            };
             for i in 0..archive.len() {
                 let file = archive.by_index(i)?;
                 let outpath = file.enclosed_name().unwrap();
@@ -118,6 +472,430 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             Ok(())
         }
+        SubCommand::Dependencies(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let mut search_path = SearchPath::new();
+            for root in &t.content_root {
+                search_path.add(root)?;
+            }
+
+            let deps = bsp.dependencies(path, &search_path)?;
+
+            if t.dot {
+                println!("{}", deps.dot());
+            } else {
+                for dep in deps.flatten() {
+                    if dep.case_differs() {
+                        eprintln!("Warning: {} differs in case on disk", dep.path);
+                    }
+                    if let Some(error) = &dep.error {
+                        eprintln!("Warning: {} can't be read: {}", dep.path, error);
+                    }
+                    if t.missing && dep.status != BSPDependencyStatus::Missing {
+                        continue;
+                    }
+                    println!("{}\t{}", dep.status, dep.path);
+                }
+            }
+
+            Ok(())
+        }
+        SubCommand::Materials(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let mut materials = bsp.materials()?;
+            materials.sort_by(|a, b| b.face_count.cmp(&a.face_count).then(a.name.cmp(&b.name)));
+
+            for material in materials {
+                if t.no_tools && material.is_tool() {
+                    continue;
+                }
+
+                let mut flags = vec![];
+                if material.is_tool() {
+                    flags.push("tool");
+                }
+                if material.is_nodraw() {
+                    flags.push("nodraw");
+                }
+
+                let [r, g, b] = material.reflectivity;
+                println!(
+                    "{}\t{}x{}\t{:.2} {:.2} {:.2}\t{}{}",
+                    material.face_count,
+                    material.width,
+                    material.height,
+                    r,
+                    g,
+                    b,
+                    material.name,
+                    if flags.is_empty() {
+                        String::new()
+                    } else {
+                        format!("\t[{}]", flags.join(", "))
+                    }
+                );
+            }
+
+            Ok(())
+        }
+        SubCommand::Autopack(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let mut bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            // base roots go first so that anything they provide is never packed
+            let mut search_path = SearchPath::new();
+            for root in t.base_root.iter().chain(&t.content_root) {
+                search_path.add(root)?;
+            }
+
+            if t.dry_run {
+                println!("Would pack the following files (dry run): ");
+                println!();
+            }
+
+            let mut files = vec![];
+            let mut size = 0;
+            for dep in bsp.dependencies(path, &search_path)?.flatten() {
+                if let Some(error) = &dep.error {
+                    eprintln!("Warning: {} can't be read: {}", dep.path, error);
+                }
+                match &dep.status {
+                    BSPDependencyStatus::Found(location) if dep.kind != BSPDependencyKind::Map => {
+                        let is_base = search_path
+                            .root_index(location)
+                            .is_none_or(|i| i < t.base_root.len());
+                        if is_base {
+                            continue;
+                        }
+
+                        let contents = search_path.read(location)?;
+                        println!(
+                            "{} ({})",
+                            dep.path,
+                            steamws::human_readable_size(contents.len() as u64)
+                        );
+                        size += contents.len() as u64;
+                        files.push((dep.path, contents));
+                    }
+                    BSPDependencyStatus::Missing => {
+                        eprintln!("Warning: {} not found from any content root", dep.path);
+                    }
+                    _ => {}
+                }
+            }
+
+            println!();
+            println!(
+                "Totaling {} files, {} in size",
+                files.len(),
+                steamws::human_readable_size(size)
+            );
+
+            if !t.dry_run {
+                bsp.add_to_pakfile(files)?;
+
+                let out_file = File::create(t.output)?;
+                let mut writer = BufWriter::new(out_file);
+                bsp.write(&mut writer)?;
+            }
+
+            Ok(())
+        }
+        SubCommand::Remap(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let mut bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let materials = bsp.remap_materials(&t.materials)?;
+            let static_props = bsp.remap_static_prop_models(&t.models)?;
+            let entities = bsp.remap_entity_models(&t.models)?;
+            println!("Renamed {} materials", materials);
+            println!("Renamed {} static prop models", static_props);
+            println!("Renamed {} entity models", entities);
+
+            let out_file = File::create(t.output)?;
+            let mut writer = BufWriter::new(out_file);
+            bsp.write(&mut writer)?;
+
+            Ok(())
+        }
+        SubCommand::ToVmf(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            std::fs::write(&t.output, bsp.to_vmf()?)?;
+
+            Ok(())
+        }
+        SubCommand::ExportMesh(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let mesh = bsp.world_mesh(t.include_sky)?;
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let triangle_count = mesh.triangle_count();
+            let material_count = mesh.primitives.len();
+            mesh::write_file(&mesh::Scene::from_mesh(mesh, &name), &t.output)?;
+
+            println!(
+                "Exported {} triangles using {} materials",
+                triangle_count, material_count
+            );
+
+            Ok(())
+        }
+        SubCommand::Lightmaps(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let lightmaps = bsp.face_lightmaps(t.hdr)?;
+            let (atlases, placements) = lighting::pack_atlases(&lightmaps, t.atlas_size);
+            if placements.len() < lightmaps.len() {
+                eprintln!(
+                    "Warning: skipped {} lightmaps larger than the atlas",
+                    lightmaps.len() - placements.len()
+                );
+            }
+
+            std::fs::create_dir_all(&t.output)?;
+            for (i, atlas) in atlases.iter().enumerate() {
+                atlas.save(t.output.join(format!("lightmap_{}.png", i)))?;
+            }
+            std::fs::write(
+                t.output.join("placements.json"),
+                serde_json::to_string_pretty(&placements)?,
+            )?;
+            println!(
+                "Exported {} face lightmaps into {} atlases",
+                placements.len(),
+                atlases.len()
+            );
+
+            Ok(())
+        }
+        SubCommand::Cubemaps(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let map_name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let packed: HashSet<String> = match bsp.pakfile()? {
+                Some(archive) => archive.file_names().map(normalize_path).collect(),
+                None => HashSet::new(),
+            };
+
+            for cubemap in bsp.cubemaps()? {
+                let texture = cubemap.texture_path(&map_name);
+                let is_packed = packed.contains(&texture);
+                if t.missing && is_packed {
+                    continue;
+                }
+
+                let [x, y, z] = cubemap.origin;
+                let size = match cubemap.size_pixels() {
+                    Some(size) => size.to_string(),
+                    None if cubemap.size <= 0 => "default".to_string(),
+                    None => "invalid".to_string(),
+                };
+                println!(
+                    "{} {} {}\t{}\t{}\t{}",
+                    x,
+                    y,
+                    z,
+                    size,
+                    if is_packed { "packed" } else { "missing" },
+                    texture
+                );
+            }
+
+            Ok(())
+        }
+        SubCommand::Lump(t) => lump(t),
+        SubCommand::Vis(t) => vis(t),
+        SubCommand::Strip(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let mut bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+            let lump_lens: Vec<u32> = bsp.header.lumps.iter().map(|l| l.len).collect();
+
+            if !t.pak_exclude.is_empty() {
+                let mut globs = GlobSetBuilder::new();
+                for pattern in &t.pak_exclude {
+                    globs.add(Glob::new(pattern)?);
+                }
+                let removed = bsp.remove_packed_files(&globs.build()?)?;
+                println!("Removed {} packed files", removed.len());
+            }
+            if t.original_faces {
+                bsp.clear_lump(LumpIndex::LUMP_ORIGINALFACES);
+            }
+            if t.physcollide {
+                let removed = bsp.strip_non_solid_physcollide()?;
+                println!("Removed {} collision models", removed);
+            }
+            if t.drop_ldr_lighting || t.drop_hdr_lighting {
+                let (drop, keep) = if t.drop_ldr_lighting {
+                    (strip::LDR_LIGHTING_LUMPS, LumpIndex::LUMP_LIGHTING_HDR)
+                } else {
+                    (strip::HDR_LIGHTING_LUMPS, LumpIndex::LUMP_LIGHTING)
+                };
+                if bsp.lump_slice(keep)?.is_empty() {
+                    return Err(format!(
+                        "map has no {:?}, refusing to remove the other lighting",
+                        keep
+                    )
+                    .into());
+                }
+                for lump_index in drop {
+                    bsp.clear_lump(*lump_index);
+                }
+            }
+
+            let mut saved_total = 0;
+            for lump_index in LumpIndex::iter() {
+                let before = lump_lens[lump_index as usize];
+                let after = bsp.header.lumps[lump_index as usize].len;
+                if after < before {
+                    println!("{:?}\t{} bytes saved", lump_index, before - after);
+                    saved_total += before - after;
+                }
+            }
+            println!("Total\t{} bytes saved", saved_total);
+
+            let file = File::create(&t.output)?;
+            let mut writer = BufWriter::new(file);
+            bsp.write(&mut writer)?;
+
+            Ok(())
+        }
+        SubCommand::Diff(t) => {
+            use colored::*;
+
+            let a = BSPReader::from_reader(BufReader::new(File::open(&t.a)?))?.into_buffered_bsp();
+            let b = BSPReader::from_reader(BufReader::new(File::open(&t.b)?))?.into_buffered_bsp();
+            let diff = diff::diff(&a, &b)?;
+
+            let marker = |kind: ChangeKind| match kind {
+                ChangeKind::Added => "+".green(),
+                ChangeKind::Removed => "-".red(),
+                ChangeKind::Modified => "~".yellow(),
+            };
+
+            if !diff.lumps.is_empty() {
+                println!("Lumps:");
+                for lump_index in &diff.lumps {
+                    println!("{} {:?}", marker(ChangeKind::Modified), lump_index);
+                }
+            }
+            if !diff.entities.is_empty() {
+                println!("Entities:");
+                for change in &diff.entities {
+                    println!("{} {}", marker(change.kind), change.description);
+                    for property in &change.properties {
+                        let text = match (&property.old, &property.new) {
+                            (Some(old), Some(new)) => {
+                                format!("    \"{}\" \"{}\" -> \"{}\"", property.key, old, new)
+                                    .yellow()
+                            }
+                            (Some(old), None) => {
+                                format!("    - \"{}\" \"{}\"", property.key, old).red()
+                            }
+                            (None, new) => format!(
+                                "    + \"{}\" \"{}\"",
+                                property.key,
+                                new.as_deref().unwrap_or_default()
+                            )
+                            .green(),
+                        };
+                        println!("{}", text);
+                    }
+                }
+            }
+            if !diff.packed_files.is_empty() {
+                println!("Packed files:");
+                for change in &diff.packed_files {
+                    println!("{} {}", marker(change.kind), change.path);
+                }
+            }
+            if !diff.removed_static_props.is_empty() || !diff.added_static_props.is_empty() {
+                println!("Static props:");
+                for prop in &diff.removed_static_props {
+                    println!("{} {}", marker(ChangeKind::Removed), prop);
+                }
+                for prop in &diff.added_static_props {
+                    println!("{} {}", marker(ChangeKind::Added), prop);
+                }
+            }
+            if diff.is_empty() {
+                println!("Maps are identical");
+            }
+
+            Ok(())
+        }
+        SubCommand::Check(t) => {
+            let path = Path::new(&t.input);
+            let file = File::open(path)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let report = bsp.check();
+            if t.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                for issue in &report.issues {
+                    let lump = match issue.lump {
+                        Some(lump) => format!("{:?}", lump),
+                        None => "-".to_string(),
+                    };
+                    println!("{:?}\t{}\t{}", issue.severity, lump, issue.message);
+                }
+                println!(
+                    "{} errors, {} warnings",
+                    report
+                        .issues
+                        .iter()
+                        .filter(|i| i.severity == Severity::Error)
+                        .count(),
+                    report
+                        .issues
+                        .iter()
+                        .filter(|i| i.severity == Severity::Warning)
+                        .count()
+                );
+            }
+
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        SubCommand::Compress(t) => set_compression(t, true),
+        SubCommand::Decompress(t) => set_compression(t, false),
         SubCommand::ExtractEntityLump(t) => {
             let path = Path::new(&t.input);
             let file = File::open(path)?;
@@ -133,28 +911,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
 
             // create a new slice where worldspawn (i.e. entity number one) is kept in the entity lump
-            let mut slice = bsp.lump_slice(LumpIndex::LUMP_ENTITIES);
-            let brace_pos = slice.iter().position(|b| *b == b'}');
+            let lump_file = bsp.lump_file(LumpIndex::LUMP_ENTITIES)?;
+            let brace_pos = lump_file.data.iter().position(|b| *b == b'}');
             let new_slice = match brace_pos {
-                Some(p) => slice[0..p + 1].to_owned(),
+                Some(p) => lump_file.data[0..p + 1].to_owned(),
                 None => Vec::new(),
             };
 
             // generate extracted lump file
-            let lump_header = &bsp.header.lumps[LumpIndex::LUMP_ENTITIES as usize];
-            let lump_file = File::create(lump_output)?;
-            let mut lump_writer = BufWriter::new(lump_file);
-
-            lump_writer.write_u32::<LittleEndian>(4 * 5)?; // lumpOffset
-            lump_writer.write_u32::<LittleEndian>(0)?; // lumpID
-            lump_writer.write_u32::<LittleEndian>(lump_header.version)?; // lumpVersion
-            lump_writer.write_u32::<LittleEndian>(lump_header.len)?; // lumpLength
-            lump_writer.write_u32::<LittleEndian>(bsp.header.map_revision)?; // mapRevision
-
-            io::copy(&mut slice, &mut lump_writer)?;
+            lump_file.write(&mut BufWriter::new(File::create(lump_output)?))?;
 
             // replace bsp entity lump with a stripped one
-            bsp.replace_lump(LumpIndex::LUMP_ENTITIES, new_slice);
+            bsp.replace_lump_data(LumpIndex::LUMP_ENTITIES, new_slice)?;
 
             let lump_file = File::create(t.output)?;
             let mut lump_writer = BufWriter::new(lump_file);
@@ -165,3 +933,140 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 }
+
+fn lump(t: LumpCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match t.subcmd {
+        LumpSubCommand::Extract(t) => {
+            let file = File::open(&t.input)?;
+            let reader = BufReader::new(file);
+            let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let lump_file = bsp.lump_file(t.lump)?;
+            let output = match t.output {
+                Some(output) => output,
+                None => {
+                    let stem = t.input.file_stem().unwrap_or_default().to_string_lossy();
+                    t.input.with_file_name(format!("{}_l_0.lmp", stem))
+                }
+            };
+            lump_file.write(&mut BufWriter::new(File::create(&output)?))?;
+
+            println!(
+                "Extracted {:?} ({} bytes) to {}",
+                t.lump,
+                lump_file.data.len(),
+                output.display()
+            );
+        }
+        LumpSubCommand::Apply(t) => {
+            let file = File::open(&t.input)?;
+            let reader = BufReader::new(file);
+            let mut bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+            let lump_file = LumpFile::read(&mut BufReader::new(File::open(&t.lump_file)?))?;
+            bsp.apply_lump_file(&lump_file)?;
+
+            let output = t.output.unwrap_or(t.input);
+            bsp.write(&mut BufWriter::new(File::create(&output)?))?;
+
+            println!(
+                "Applied {:?} ({} bytes) to {}",
+                lump_file.header.lump_index,
+                lump_file.data.len(),
+                output.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn vis(t: VisCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let input = match &t.subcmd {
+        VisSubCommand::Stats(t) => &t.input,
+        VisSubCommand::Query(t) => &t.input,
+    };
+    let file = File::open(input)?;
+    let reader = BufReader::new(file);
+    let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+    let vis = bsp.visibility()?;
+    if vis.cluster_count == 0 {
+        return Err("map has no visibility data (vvis was not run)".into());
+    }
+
+    match t.subcmd {
+        VisSubCommand::Stats(t) => {
+            let leafs = bsp.leafs()?;
+            let mut stats = bsp.cluster_stats(&leafs)?;
+            let clustered_leafs = leafs.iter().filter(|l| l.cluster >= 0).count();
+            let average =
+                stats.iter().map(|s| s.visible_leafs).sum::<usize>() as f64 / stats.len() as f64;
+
+            println!("Clusters: {}", vis.cluster_count);
+            println!("Leaves: {} ({} in clusters)", leafs.len(), clustered_leafs);
+            println!("Average visible leaves: {:.1}", average);
+
+            stats.sort_by_key(|s| std::cmp::Reverse(s.visible_leafs));
+            println!("Worst clusters:");
+            for s in stats.iter().take(t.worst) {
+                println!(
+                    "{}\t{} leaves\t{} clusters",
+                    s.cluster, s.visible_leafs, s.visible_clusters
+                );
+            }
+        }
+        VisSubCommand::Query(t) => {
+            let leafs = bsp.leafs()?;
+            let leaf = bsp
+                .leaf_at([t.x, t.y, t.z])?
+                .and_then(|i| leafs.get(i).map(|l| (i, l)));
+            let (index, leaf) = match leaf {
+                Some(leaf) => leaf,
+                None => return Err("point is outside the bsp tree".into()),
+            };
+            if leaf.cluster < 0 {
+                return Err(
+                    format!("point is in leaf {}, which is outside the world", index).into(),
+                );
+            }
+
+            let visible = vis.visible_clusters(leaf.cluster as usize);
+            println!("Leaf: {}", index);
+            println!("Cluster: {}", leaf.cluster);
+            println!("Visible clusters ({}):", visible.len());
+            for cluster in visible {
+                println!("{}", cluster);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn set_compression(t: CompressCommand, compress: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(&t.input);
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let mut bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+
+    let old_size = bsp.data_without_header.len() as u64;
+    if compress {
+        bsp.compress_lumps()?;
+    } else {
+        bsp.decompress_lumps()?;
+    }
+    let new_size = bsp.data_without_header.len() as u64;
+
+    println!(
+        "{} -> {}",
+        steamws::human_readable_size(old_size),
+        steamws::human_readable_size(new_size)
+    );
+
+    let out_file = File::create(&t.output)?;
+    let mut writer = BufWriter::new(out_file);
+    bsp.write(&mut writer)?;
+
+    Ok(())
+}
@@ -4,6 +4,8 @@ use std::vec::Vec;
 use std::collections::HashSet;
 
 use clap::{Parser, Subcommand, Args};
+use steamws::mdl::{MDLDependencyKind, MDLDependencyStatus};
+use steamws::search_path::SearchPath;
 
 #[derive(Parser)]
 #[command(author, about, version)]
@@ -18,9 +20,12 @@ enum SubCommand {
     ///
     /// Namely, this will return a list of paths that
     /// are required for the .mdl to function, including the
-    /// .mdl file itself.
+    /// .mdl file itself, its collision model and animation blocks,
+    /// models included with $includemodel and gib models.
     ///
-    /// The paths are returned relative to the working directory
+    /// Each dependency is marked as local (next to the model), found
+    /// (in a content root) or missing, followed by the folder or .gma
+    /// it was found in
     #[command(alias = "deps")]
     Dependencies(DependenciesCommand),
 
@@ -28,6 +33,64 @@ enum SubCommand {
     #[command()]
     Skins(SkinsCommand),
 
+    /// Lists body groups and the models they can be set to
+    #[command()]
+    Bodygroups(ListCommand),
+
+    /// Lists attachments with their bone, position and angles
+    #[command()]
+    Attachments(ListCommand),
+
+    /// Lists sequences with their activity, fps, frame count and looping flag
+    #[command()]
+    Sequences(ListCommand),
+
+    /// Lists hitboxes of every hitbox set
+    #[command()]
+    Hitboxes(ListCommand),
+
+    /// Prints the model header
+    ///
+    /// Includes name, version, bounds, physics properties, textures
+    /// and counts of bones, sequences, attachments etc. If the .vvd and .vtx
+    /// are next to the model, vertices and triangles per LOD are included
+    #[command()]
+    Info(InfoCommand),
+
+    /// Exports the model with its skeleton to glTF (.glb/.gltf) or OBJ
+    ///
+    /// Each body part becomes a node with a child node per model and
+    /// skins become material variants. Base textures are embedded if
+    /// they can be found next to the model
+    #[command()]
+    Export(ExportCommand),
+
+    /// Decompiles the model to SMDs and a QC
+    ///
+    /// Writes a reference SMD per body group model, a physics SMD if
+    /// there's a .phy next to the model, an SMD per animation and a QC
+    /// that compiles them back to the model
+    #[command()]
+    Decompile(DecompileCommand),
+
+    /// Replaces the folders the model's materials are looked up from
+    ///
+    /// Edits the $cdmaterials of the compiled model, e.g. after moving its
+    /// materials, without decompiling it
+    #[command()]
+    SetCdmaterials(SetCdmaterialsCommand),
+
+    /// Renames a material used by the model
+    #[command()]
+    RenameTexture(RenameTextureCommand),
+
+    /// Changes the name the model is known by in the engine
+    ///
+    /// Models copied to another path still have the name they were compiled
+    /// with, which makes the engine confuse them with the original
+    #[command()]
+    Rename(RenameCommand),
+
     /// Copies given .mdl with dependencies to target path
     ///
     /// Maintains the folder structure, including materials.
@@ -54,6 +117,16 @@ struct DependenciesCommand {
     #[arg(long)]
     skin: Option<u16>,
 
+    /// Folder containing "materials", "models" etc. or a .gma to look up dependencies
+    /// not found next to the model. Can be given multiple times; earlier roots take
+    /// precedence
+    #[arg(long)]
+    content_root: Vec<PathBuf>,
+
+    /// Only print missing dependencies
+    #[arg(long)]
+    missing: bool,
+
     /// Print dependencies in graphviz format
     /// 
     /// Example (OS X):
@@ -68,6 +141,88 @@ struct SkinsCommand {
     input: String,
 }
 
+#[derive(Args)]
+struct ListCommand {
+    /// Source mdl
+    input: String,
+
+    /// Print the list as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct InfoCommand {
+    /// Source mdl
+    input: String,
+}
+
+#[derive(Args)]
+struct ExportCommand {
+    /// Source mdl
+    input: String,
+
+    /// Output file: .glb, .gltf or .obj
+    output: PathBuf,
+
+    /// LOD to export
+    #[arg(long, default_value_t = 0)]
+    lod: usize,
+}
+
+#[derive(Args)]
+struct DecompileCommand {
+    /// Source mdl
+    input: String,
+
+    /// Folder for the QC and SMDs
+    output_folder: PathBuf,
+}
+
+#[derive(Args)]
+struct SetCdmaterialsCommand {
+    /// Mdl to edit
+    input: String,
+
+    /// Folders relative to "materials", e.g. "models/ourteam/"
+    #[arg(required = true)]
+    dirs: Vec<String>,
+
+    /// Where to write the edited mdl, defaults to overwriting the input
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RenameTextureCommand {
+    /// Mdl to edit
+    input: String,
+
+    /// Current material name, as listed by `mdl info`
+    from: String,
+
+    /// New material name
+    to: String,
+
+    /// Where to write the edited mdl, defaults to overwriting the input
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RenameCommand {
+    /// Mdl to edit
+    input: String,
+
+    /// New name, e.g. "ourteam/crate.mdl". Defaults to the path of the
+    /// output relative to its "models" folder
+    name: Option<String>,
+
+    /// Where to write the edited mdl, defaults to overwriting the input
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
 #[derive(Args)]
 struct CopyCommand {
     /// Source mdl
@@ -99,7 +254,8 @@ struct RemoveCommand {
     /// Also remove indirect dependencies.
     ///
     /// Indirect assets are the ones that other models may rely on,
-    /// such as materials and textures.
+    /// such as materials and textures. Included and gib models are
+    /// never removed, as they are often shared between models.
     #[arg(long, short)]
     all_deps: bool,
 
@@ -109,6 +265,63 @@ struct RemoveCommand {
     dry_run: bool,
 }
 
+/// Returns the model name for a path, i.e. the path relative to its "models" folder
+fn model_name(path: &Path) -> Option<String> {
+    let components: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let models = components
+        .iter()
+        .rposition(|c| c.eq_ignore_ascii_case("models"))?;
+    Some(components[models + 1..].join("/"))
+}
+
+/// Converts base textures of the model's materials to PNG, skipping ones that
+/// can't be found or decoded
+#[cfg(feature = "vtf")]
+fn base_textures(
+    mdl: &steamws::mdl::MDLFile,
+) -> Result<std::collections::HashMap<String, Vec<u8>>, Box<dyn std::error::Error>> {
+    let index = steamws::search_path::DirectoryIndex::new();
+    let convert = |vmt_path: &Path| -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let base_texture = match steamws::vmt::read(vmt_path)?.base_texture {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let vtf_path = format!("materials/{}.vtf", base_texture);
+        let vtf_path = match index.find(mdl.assets_path(), &vtf_path) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let mut bytes = fs::read(&vtf_path)?;
+        let vtf = steamws::vtf::from_bytes(&mut bytes)?;
+        let image = vtf.highres_image.decode(0)?;
+        let mut png = std::io::Cursor::new(vec![]);
+        image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+        Ok(Some(png.into_inner()))
+    };
+
+    let mut textures = std::collections::HashMap::new();
+    let names = mdl.skins_with_material_names()?;
+    let paths = mdl.skins_with_material_paths()?;
+    for (name, path) in names.iter().flatten().zip(paths.iter().flatten()) {
+        let path = match path {
+            Some(path) if !textures.contains_key(name) => path,
+            _ => continue,
+        };
+        match convert(path) {
+            Ok(Some(png)) => {
+                textures.insert(name.clone(), png);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: skipped texture of {}: {}", name, e),
+        }
+    }
+    Ok(textures)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts: Opts = Opts::parse();
 
@@ -117,17 +330,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let path = Path::new(&t.input);
 
             let mdl = steamws::mdl::MDLFile::open(path)?;
-            let mut deps = mdl.dependencies()?;
+            let mut search_path = SearchPath::new();
+            for root in &t.content_root {
+                search_path.add(root)?;
+            }
+            let mut deps = mdl.dependencies(&search_path)?;
 
             if let Some(skin) = t.skin {
-                let skin_mats = &mdl.skins_with_material_paths()[skin as usize];
-
-                let found_skin_mats: HashSet<&PathBuf> =
-                    skin_mats.iter()
-                    .filter_map(|s| s.as_ref())
+                let skins = mdl.skins_with_material_names()?;
+                let skin_mats = skins.get(skin as usize).ok_or(
+                    steamws::mdl::Error::IndexOutOfRange("skin", skin as usize, skins.len()),
+                )?;
+
+                let skin_mat_paths: HashSet<String> = skin_mats
+                    .iter()
+                    .flat_map(|name| mdl.material_paths(name))
                     .collect();
                 deps.filter_root_dependencies(|d| {
-                    matches!(d, steamws::mdl::MDLDependency::Material(p) if found_skin_mats.contains(p))
+                    d.kind == MDLDependencyKind::Material && skin_mat_paths.contains(&d.path)
                 })
             }
 
@@ -135,7 +355,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("{}", deps.dot());
             } else {
                 for dep in deps.flatten() {
-                    println!("{}", dep.path().to_str().unwrap());
+                    if let Some(file) = dep.file().filter(|_| dep.case_differs()) {
+                        eprintln!(
+                            "Warning: {} differs in case on disk: {}",
+                            dep.path,
+                            file.display()
+                        );
+                    }
+                    if let Some(error) = &dep.error {
+                        eprintln!("Warning: {} can't be read: {}", dep.path, error);
+                    }
+                    let root = match &dep.status {
+                        MDLDependencyStatus::Local(_) => Some(mdl.assets_path()),
+                        MDLDependencyStatus::Found(location) => search_path
+                            .root_index(location)
+                            .map(|i| search_path.roots()[i].path()),
+                        MDLDependencyStatus::Missing => None,
+                    };
+                    match root {
+                        Some(root) if !t.missing => {
+                            println!("{}\t{}\t{}", dep.status, dep.path, root.display())
+                        }
+                        None => println!("{}\t{}", dep.status, dep.path),
+                        _ => {}
+                    }
                 }
             }
 
@@ -145,7 +388,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let path = Path::new(&t.input);
 
             let mdl = steamws::mdl::MDLFile::open(path)?;
-            for (i, skin) in mdl.skins_with_material_paths().iter().enumerate() {
+            for (i, skin) in mdl.skins_with_material_paths()?.iter().enumerate() {
                 println!("Skin #{}", i);
                 for tex in skin {
                     println!("{:?}", tex);
@@ -155,6 +398,272 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             Ok(())
         }
+        SubCommand::Bodygroups(t) => {
+            let mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            let body_groups = mdl.body_groups();
+            if t.json {
+                println!("{}", serde_json::to_string_pretty(&body_groups)?);
+            } else {
+                for group in body_groups {
+                    println!(
+                        "{}\t{}\t{}",
+                        group.index,
+                        group.name,
+                        group.models.join(", ")
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        SubCommand::Attachments(t) => {
+            let mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            let attachments = mdl.attachments();
+            if t.json {
+                println!("{}", serde_json::to_string_pretty(&attachments)?);
+            } else {
+                for a in attachments {
+                    println!(
+                        "{}\t{}\t{}\t{} {} {}\t{} {} {}",
+                        a.index,
+                        a.name,
+                        a.bone,
+                        a.position[0],
+                        a.position[1],
+                        a.position[2],
+                        a.angles[0],
+                        a.angles[1],
+                        a.angles[2]
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        SubCommand::Sequences(t) => {
+            let mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            let sequences = mdl.sequences();
+            if t.json {
+                println!("{}", serde_json::to_string_pretty(&sequences)?);
+            } else {
+                for s in sequences {
+                    println!(
+                        "{}\t{}\t{}\t{} fps\t{} frames{}",
+                        s.index,
+                        s.name,
+                        s.activity,
+                        s.fps,
+                        s.frames,
+                        if s.looping { "\tloop" } else { "" }
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        SubCommand::Hitboxes(t) => {
+            let mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            let hitboxes = mdl.hitboxes();
+            if t.json {
+                println!("{}", serde_json::to_string_pretty(&hitboxes)?);
+            } else {
+                for h in hitboxes {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{} {} {}\t{} {} {}",
+                        h.set,
+                        h.bone,
+                        h.group,
+                        h.name,
+                        h.mins[0],
+                        h.mins[1],
+                        h.mins[2],
+                        h.maxs[0],
+                        h.maxs[1],
+                        h.maxs[2]
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        SubCommand::Info(t) => {
+            let path = Path::new(&t.input);
+
+            let mdl = steamws::mdl::MDLFile::open(path)?;
+            let header = mdl.header();
+            let vector = |v: [f32; 3]| format!("{} {} {}", v[0], v[1], v[2]);
+
+            println!("name = {}", header.name);
+            if let Some(header2) = header.header2.as_ref().filter(|h| !h.name.is_empty()) {
+                println!("long name = {}", header2.name);
+            }
+            println!("version = {}", header.version);
+            println!("checksum = {:08x}", header.checksum as u32);
+            println!("length = {}", header.length);
+            println!("flags = {:#x}", header.flags);
+            println!("eye position = {}", vector(header.eye_position));
+            println!("illum position = {}", vector(header.illum_position));
+            println!(
+                "hull = {} / {}",
+                vector(header.hull_min),
+                vector(header.hull_max)
+            );
+            println!(
+                "view bounds = {} / {}",
+                vector(header.view_bbmin),
+                vector(header.view_bbmax)
+            );
+            println!("mass = {}", header.mass);
+            println!("contents = {:#x}", header.contents);
+            println!("surface prop = {}", header.surface_prop);
+            println!("bones = {}", header.bones.len());
+            println!("bone controllers = {}", header.bone_controllers.len());
+            println!(
+                "hitboxes = {}",
+                header
+                    .hitbox_sets
+                    .iter()
+                    .map(|s| format!("{} ({})", s.name, s.hitboxes.len()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!("animations = {}", header.animations.len());
+            println!("sequences = {}", header.sequences.len());
+            println!("textures = {}", header.texture_names.join(", "));
+            println!("texture dirs = {}", header.texture_dirs.join(", "));
+            println!("skins = {}", header.skin_count);
+            println!(
+                "body parts = {}",
+                header
+                    .body_parts
+                    .iter()
+                    .map(|b| format!("{} ({})", b.name, b.models.len()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!("attachments = {}", header.attachments.len());
+            println!("flexes = {}", header.flex_descs.len());
+            println!("flex controllers = {}", header.flex_controllers.len());
+            println!(
+                "include models = {}",
+                header
+                    .include_models
+                    .iter()
+                    .map(|m| m.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            if header.anim_block_count > 0 {
+                println!(
+                    "anim blocks = {} ({})",
+                    header.anim_block_name, header.anim_block_count
+                );
+            }
+            if !header.keyvalues.is_empty() {
+                println!("keyvalues = {}", header.keyvalues);
+            }
+
+            match mdl.vvd() {
+                Ok(vvd) => {
+                    let lod_count =
+                        (vvd.lod_count.max(0) as usize).min(steamws::mdl::vvd::MAX_LODS);
+                    let counts: Vec<String> = vvd.lod_vertex_counts[..lod_count]
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect();
+                    println!("vertices = {}", counts.join(" "));
+                }
+                Err(steamws::mdl::Error::MissingFile(_)) => {}
+                Err(e) => println!("vertices = {}", e),
+            }
+            match mdl.vtx() {
+                Ok(vtx) => {
+                    let triangles: Vec<String> = (0..vtx.lod_count.max(0) as usize)
+                        .map(|lod| {
+                            vtx.body_parts
+                                .iter()
+                                .flat_map(|b| &b.models)
+                                .filter_map(|m| m.lods.get(lod))
+                                .flat_map(|l| &l.meshes)
+                                .flat_map(|m| &m.strip_groups)
+                                .map(|g| g.triangles().len())
+                                .sum::<usize>()
+                                .to_string()
+                        })
+                        .collect();
+                    println!("triangles = {}", triangles.join(" "));
+                }
+                Err(steamws::mdl::Error::MissingFile(_)) => {}
+                Err(e) => println!("triangles = {}", e),
+            }
+
+            Ok(())
+        }
+        SubCommand::Export(t) => {
+            let path = Path::new(&t.input);
+
+            let mdl = steamws::mdl::MDLFile::open(path)?;
+            #[allow(unused_mut)]
+            let mut scene = mdl.scene(t.lod)?;
+            #[cfg(feature = "vtf")]
+            {
+                scene.textures = base_textures(&mdl)?;
+            }
+            steamws::mesh::write_file(&scene, &t.output)?;
+
+            println!(
+                "Exported {} triangles, {} bones and {} textures",
+                scene.triangle_count(),
+                scene.joints.len(),
+                scene.textures.len()
+            );
+
+            Ok(())
+        }
+        SubCommand::Decompile(t) => {
+            let mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            for path in mdl.decompile(&t.output_folder)? {
+                println!("{}", path.display());
+            }
+
+            Ok(())
+        }
+        SubCommand::SetCdmaterials(t) => {
+            let mut mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            mdl.set_texture_dirs(&t.dirs)?;
+            mdl.save(t.output.as_deref().unwrap_or_else(|| Path::new(&t.input)))?;
+
+            println!("texture dirs = {}", mdl.header().texture_dirs.join(", "));
+            Ok(())
+        }
+        SubCommand::RenameTexture(t) => {
+            let mut mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            mdl.rename_texture(&t.from, &t.to)?;
+            mdl.save(t.output.as_deref().unwrap_or_else(|| Path::new(&t.input)))?;
+
+            println!("textures = {}", mdl.header().texture_names.join(", "));
+            Ok(())
+        }
+        SubCommand::Rename(t) => {
+            let output = t.output.as_deref().unwrap_or_else(|| Path::new(&t.input));
+            let name = match t.name.clone().or_else(|| model_name(output)) {
+                Some(name) => name,
+                None => {
+                    return Err(format!(
+                        "{} is not in a models folder, give a name",
+                        output.display()
+                    )
+                    .into())
+                }
+            };
+
+            let mut mdl = steamws::mdl::MDLFile::open(Path::new(&t.input))?;
+            mdl.set_name(&name)?;
+            mdl.save(output)?;
+
+            println!("name = {}", name);
+            Ok(())
+        }
         SubCommand::Copy(t) => {
             let path = Path::new(&t.input);
             let out_path = Path::new(&t.output_folder);
@@ -162,7 +671,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let mut copy_map: Vec<(PathBuf, PathBuf)> = vec![];
 
             let mdl = steamws::mdl::MDLFile::open(path)?;
-            let mut deps = mdl.dependencies()?;
+            let mut deps = mdl.dependencies(&SearchPath::new())?;
 
             if t.direct_deps {
                 deps.filter_root_edges(|e| {
@@ -171,21 +680,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
 
             if let Some(skin) = t.skin {
-                let skin_mats = &mdl.skins_with_material_paths()[skin as usize];
-
-                let found_skin_mats: HashSet<&PathBuf> =
-                    skin_mats.iter()
-                    .filter_map(|s| s.as_ref())
+                let skins = mdl.skins_with_material_names()?;
+                let skin_mats = skins.get(skin as usize).ok_or(
+                    steamws::mdl::Error::IndexOutOfRange("skin", skin as usize, skins.len()),
+                )?;
+
+                let skin_mat_paths: HashSet<String> = skin_mats
+                    .iter()
+                    .flat_map(|name| mdl.material_paths(name))
                     .collect();
                 deps.filter_root_dependencies(|d| {
-                    matches!(d, steamws::mdl::MDLDependency::Material(p) if found_skin_mats.contains(p))
+                    d.kind == MDLDependencyKind::Material && skin_mat_paths.contains(&d.path)
                 })
             }
 
-            let assets_path = mdl.assets_path();
             for dep in &deps.flatten() {
-                let bare_dep = dep.path().strip_prefix(assets_path)?.to_path_buf();
-                copy_map.push((dep.path().to_path_buf(), out_path.join(bare_dep)));
+                match dep.file() {
+                    Some(file) => copy_map.push((file.to_path_buf(), out_path.join(&dep.path))),
+                    None => eprintln!("Warning: {} not found", dep.path),
+                }
             }
 
             if t.dry_run {
@@ -231,21 +744,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let mut size = 0;
 
-            let mut deps = mdl.dependencies()?;
+            let mut deps = mdl.dependencies(&SearchPath::new())?;
 
-            if !t.all_deps {
+            if t.all_deps {
+                deps.filter_root_dependencies(|d| {
+                    !matches!(
+                        d.kind,
+                        MDLDependencyKind::IncludedModel | MDLDependencyKind::Gib
+                    )
+                });
+            } else {
                 deps.filter_root_edges(|e| {
                     matches!(e, steamws::mdl::MDLDependencyType::Direct)
                 });
             }
 
-            for dep in &deps.flatten() {
-                println!("{}", dep.path().display());
+            for file in deps.flatten().iter().filter_map(|dep| dep.file()) {
+                println!("{}", file.display());
 
                 if !t.dry_run {
-                    fs::remove_file(dep.path())?;
+                    fs::remove_file(file)?;
                 } else {
-                    size += fs::metadata(dep.path()).ok().map(|m| m.len()).unwrap_or(0);
+                    size += fs::metadata(file).ok().map(|m| m.len()).unwrap_or(0);
                 }
             }
 
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use steamws::bsp::BSPReader;
+use steamws::nav;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(author, about, version)]
+struct Opts {
+    #[command(subcommand)]
+    subcmd: SubCommand,
+}
+
+#[derive(Subcommand)]
+enum SubCommand {
+    /// Prints version, places and counts of areas, ladders, hiding spots and encounter paths
+    Info(InfoCommand),
+
+    /// Validates a nav mesh, optionally against its map
+    ///
+    /// Reports connections to missing areas, areas that can't be reached from
+    /// the rest of the mesh and, if a map is found, whether the mesh is out of
+    /// date or has areas outside the world. Exits with a non-zero code if
+    /// problems are found
+    Check(CheckCommand),
+}
+
+#[derive(Args)]
+struct InfoCommand {
+    /// Source nav
+    input: PathBuf,
+}
+
+#[derive(Args)]
+struct CheckCommand {
+    /// Source nav
+    input: PathBuf,
+
+    /// Map the nav belongs to. By default the .bsp next to the nav is used, if any
+    #[arg(long)]
+    bsp: Option<PathBuf>,
+
+    /// Don't fail if some areas can't be reached from the rest of the mesh
+    #[arg(long)]
+    allow_disconnected: bool,
+}
+
+fn read_nav(path: &Path) -> Result<nav::NavMesh, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    Ok(nav::read(&mut reader)?)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opts: Opts = Opts::parse();
+
+    match opts.subcmd {
+        SubCommand::Info(t) => {
+            let mesh = read_nav(&t.input)?;
+
+            println!("version = {}", mesh.version);
+            println!("subversion = {}", mesh.subversion);
+            if let Some(bsp_size) = mesh.bsp_size {
+                println!("bsp size = {}", bsp_size);
+            }
+            println!("analyzed = {}", mesh.analyzed);
+            println!("areas = {}", mesh.areas.len());
+            println!("ladders = {}", mesh.ladders.len());
+            println!(
+                "hiding spots = {}",
+                mesh.areas
+                    .iter()
+                    .map(|a| a.hiding_spots.len())
+                    .sum::<usize>()
+            );
+            println!(
+                "encounter paths = {}",
+                mesh.areas
+                    .iter()
+                    .map(|a| a.encounter_paths.len())
+                    .sum::<usize>()
+            );
+            println!("places = {}", mesh.places.join(", "));
+
+            Ok(())
+        }
+        SubCommand::Check(t) => {
+            let mesh = read_nav(&t.input)?;
+            let mut errors = 0;
+
+            for (area, id) in mesh.dangling_references() {
+                println!("Area {} connects to missing area {}", area, id);
+                errors += 1;
+            }
+
+            let islands = mesh.islands();
+            for island in islands.iter().skip(1) {
+                let ids: Vec<String> = island.iter().map(|id| id.to_string()).collect();
+                println!(
+                    "{} areas are disconnected from the main mesh: {}",
+                    island.len(),
+                    ids.join(" ")
+                );
+                if !t.allow_disconnected {
+                    errors += 1;
+                }
+            }
+
+            let bsp_path = t
+                .bsp
+                .clone()
+                .or_else(|| Some(t.input.with_extension("bsp")).filter(|p| p.exists()));
+            if let Some(bsp_path) = bsp_path {
+                let bsp_size = std::fs::metadata(&bsp_path)?.len();
+                if let Some(nav_bsp_size) = mesh.bsp_size {
+                    if nav_bsp_size as u64 != bsp_size {
+                        println!(
+                            "Nav was generated for a {} byte map, but {} is {} bytes (out of date)",
+                            nav_bsp_size,
+                            bsp_path.display(),
+                            bsp_size
+                        );
+                        errors += 1;
+                    }
+                }
+
+                let file = File::open(&bsp_path)?;
+                let reader = BufReader::new(file);
+                let bsp = BSPReader::from_reader(reader)?.into_buffered_bsp();
+                if let Some(world) = bsp.models()?.first() {
+                    for id in mesh.areas_outside(world.mins, world.maxs) {
+                        println!("Area {} is outside the world", id);
+                        errors += 1;
+                    }
+                }
+            }
+
+            println!("{} areas, {} problems", mesh.areas.len(), errors);
+            if errors > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
@@ -10,7 +10,8 @@ use std::fs::metadata;
 use std::io;
 use std::io::Read;
 use clap::{Parser, Subcommand, Args};
-use lzma::LzmaReader;
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
@@ -242,7 +243,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let file = File::open(folder)?;
                 // Legacy gmod binaries are LZMA compressed
                 // TODO verify if file is LZMA before doing this
-                reader = Box::new(LzmaReader::new_decompressor(file)?);
+                reader = Box::new(XzDecoder::new_stream(file, Stream::new_auto_decoder(u64::MAX, 0)?));
             } else {
                 let mut files = fs::read_dir(folder)?.collect::<Vec<_>>();
                 if files.len() != 1 {
@@ -0,0 +1,84 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, Cursor};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::Error;
+
+pub(crate) const BRUSH_LEN: usize = 12;
+pub(crate) const BRUSH_SIDE_LEN: usize = 8;
+
+pub const CONTENTS_SOLID: i32 = 0x1;
+pub const CONTENTS_WINDOW: i32 = 0x2;
+pub const CONTENTS_GRATE: i32 = 0x8;
+pub const CONTENTS_WATER: i32 = 0x20;
+pub const CONTENTS_PLAYERCLIP: i32 = 0x10000;
+pub const CONTENTS_MONSTERCLIP: i32 = 0x20000;
+pub const CONTENTS_DETAIL: i32 = 0x8000000;
+
+/// `dbrush_t`
+#[derive(Debug, Clone)]
+pub struct Brush {
+    pub first_side: i32,
+    pub num_sides: i32,
+    /// `CONTENTS_*` flags
+    pub contents: i32,
+}
+
+/// `dbrushside_t`
+#[derive(Debug, Clone)]
+pub struct BrushSide {
+    /// Index to `LUMP_PLANES`. The plane faces out of the brush
+    pub plane_num: u16,
+    /// Index to `LUMP_TEXINFO`, or -1
+    pub texinfo: i16,
+    pub disp_info: i16,
+    /// Bevel planes are added by vbsp for collision and were not in the source map
+    pub bevel: bool,
+}
+
+pub fn read_brushes(lump: &[u8]) -> Result<Vec<Brush>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut brushes = vec![];
+    for _ in 0..lump.len() / BRUSH_LEN {
+        brushes.push(Brush {
+            first_side: reader.read_i32::<LittleEndian>()?,
+            num_sides: reader.read_i32::<LittleEndian>()?,
+            contents: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(brushes)
+}
+
+pub fn read_brush_sides(lump: &[u8]) -> Result<Vec<BrushSide>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut sides = vec![];
+    for _ in 0..lump.len() / BRUSH_SIDE_LEN {
+        sides.push(BrushSide {
+            plane_num: reader.read_u16::<LittleEndian>()?,
+            texinfo: reader.read_i16::<LittleEndian>()?,
+            disp_info: reader.read_i16::<LittleEndian>()?,
+            // newer branches split this into bevel and thin bytes
+            bevel: reader.read_u8()? != 0,
+        });
+        reader.read_u8()?;
+    }
+
+    Ok(sides)
+}
+
+impl BufferedBSP {
+    pub fn brushes(&self) -> Result<Vec<Brush>, Error> {
+        Ok(read_brushes(&self.lump_data(LumpIndex::LUMP_BRUSHES)?)?)
+    }
+
+    pub fn brush_sides(&self) -> Result<Vec<BrushSide>, Error> {
+        self.ensure_standard_structures()?;
+        Ok(read_brush_sides(
+            &self.lump_data(LumpIndex::LUMP_BRUSHSIDES)?,
+        )?)
+    }
+}
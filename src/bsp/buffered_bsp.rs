@@ -1,8 +1,14 @@
-use std::io::Write;
+use std::borrow::Cow;
+use std::io::{Cursor, Write};
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
-use super::{lump_indices::LumpIndex, BSPHeader, BSP_HEADER_LEN, BSP_LUMP_COUNT};
+use super::game_lump::{read_directory, GAME_LUMP_ENTRY_LEN};
+use super::{lump_indices::LumpIndex, BSPHeader, BSPVariant, Error, BSP_HEADER_LEN};
+
+/// Pakfile lump opened as a zip archive
+pub type Pakfile<'a> = ZipArchive<Cursor<Cow<'a, [u8]>>>;
 
 pub struct BufferedBSP {
     pub header: BSPHeader,
@@ -10,28 +16,83 @@ pub struct BufferedBSP {
 }
 
 impl BufferedBSP {
-    pub fn lump_slice(&self, lump_index: LumpIndex) -> &[u8] {
+    /// Returns raw (possibly compressed) lump contents. Lumps not present in the map
+    /// are empty, lumps extending past the end of the file are an error
+    pub fn lump_slice(&self, lump_index: LumpIndex) -> Result<&[u8], Error> {
         let lump = &self.header.lumps[lump_index as usize];
         if lump.off < BSP_HEADER_LEN {
-            return &[];
+            return Ok(&[]);
+        }
+
+        let start = (lump.off - BSP_HEADER_LEN) as usize;
+        start
+            .checked_add(lump.len as usize)
+            .and_then(|end| self.data_without_header.get(start..end))
+            .ok_or(Error::LumpOutOfBounds(lump_index, lump.off, lump.len))
+    }
+
+    /// Returns an error for maps whose structure lumps aren't laid out like in
+    /// standard maps, and so can't be read by the structure readers
+    pub(crate) fn ensure_standard_structures(&self) -> Result<(), Error> {
+        match self.header.variant {
+            BSPVariant::Vindictus => Err(Error::UnsupportedVariant(
+                "Vindictus lump structures".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Opens the pakfile lump as a zip archive. Returns None if the map has no pakfile
+    pub fn pakfile(&self) -> Result<Option<Pakfile<'_>>, Error> {
+        let data = self.lump_data(LumpIndex::LUMP_PAKFILE)?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ZipArchive::new(Cursor::new(data))?))
+    }
+
+    /// Adds given (path, contents) files to the pakfile, creating the pakfile if needed.
+    ///
+    /// Files are stored uncompressed, as older branches of Source can't read compressed pakfiles
+    pub fn add_to_pakfile(&mut self, files: Vec<(String, Vec<u8>)>) -> Result<(), Error> {
+        let existing = self.lump_slice(LumpIndex::LUMP_PAKFILE)?.to_vec();
+        let mut writer = if existing.is_empty() {
+            ZipWriter::new(Cursor::new(existing))
+        } else {
+            ZipWriter::new_append(Cursor::new(existing))?
+        };
+
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for (path, contents) in files {
+            writer.start_file(path, options)?;
+            writer.write_all(&contents)?;
         }
 
-        &self.data_without_header
-            [(lump.off - BSP_HEADER_LEN) as usize..(lump.off + lump.len - BSP_HEADER_LEN) as usize]
+        let pakfile = writer.finish()?.into_inner();
+        self.replace_lump(LumpIndex::LUMP_PAKFILE, pakfile);
+        Ok(())
     }
 
     pub fn replace_lump(&mut self, lump_index: LumpIndex, new_lump: Vec<u8>) {
-        let mut lump = &mut self.header.lumps[lump_index as usize];
+        let lump = &mut self.header.lumps[lump_index as usize];
         let new_lump_len = new_lump.len() as u32;
+
+        // lumps not present in the map are appended to the end
+        if lump.off < BSP_HEADER_LEN {
+            lump.off = BSP_HEADER_LEN + self.data_without_header.len() as u32;
+            lump.len = new_lump_len;
+            self.data_without_header.extend(new_lump);
+            return;
+        }
+
         let lump_len_diff = (new_lump_len as i32) - (lump.len as i32);
 
-        // replace range in data
+        // replace range in data, or what there is of it if the lump is truncated
+        let data_len = self.data_without_header.len();
+        let start = ((lump.off - BSP_HEADER_LEN) as usize).min(data_len);
+        let end = start.saturating_add(lump.len as usize).min(data_len);
         self.data_without_header
-            .splice(
-                (lump.off - BSP_HEADER_LEN) as usize
-                    ..(lump.off + lump.len - BSP_HEADER_LEN) as usize,
-                new_lump,
-            )
+            .splice(start..end, new_lump)
             .count();
 
         // set new lump length in header
@@ -39,28 +100,135 @@ impl BufferedBSP {
 
         // fix offset of all lumps with offsets higher than this lump's offset
         let lump_off = lump.off;
-        for mut some_lump in &mut self.header.lumps {
+        for some_lump in &mut self.header.lumps {
             if some_lump.off > lump_off {
                 some_lump.off = (some_lump.off as i32 + lump_len_diff) as u32;
             }
         }
-    }
 
-    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn std::error::Error>> {
-        writer.write_all(b"VBSP")?;
-        writer.write_u32::<LittleEndian>(self.header.version)?;
-
-        for i in 0..BSP_LUMP_COUNT {
-            writer.write_u32::<LittleEndian>(self.header.lumps[i].off)?;
-            writer.write_u32::<LittleEndian>(self.header.lumps[i].len)?;
-            writer.write_u32::<LittleEndian>(self.header.lumps[i].version)?;
-            writer.write_all(&self.header.lumps[i].ident)?;
+        // game lump directory has file-absolute offsets, so they need fixing too
+        if lump_index != LumpIndex::LUMP_GAME_LUMP {
+            self.shift_game_lump_offsets(lump_off, lump_len_diff);
         }
+    }
 
-        writer.write_u32::<LittleEndian>(self.header.map_revision)?;
+    fn shift_game_lump_offsets(&mut self, after_off: u32, diff: i32) {
+        // read the directory as is, as indices need to match the entries on disk
+        let game_lumps = match self
+            .lump_slice(LumpIndex::LUMP_GAME_LUMP)
+            .map(read_directory)
+        {
+            Ok(Ok(game_lumps)) => game_lumps,
+            _ => return,
+        };
+        let dir_start =
+            (self.header.lumps[LumpIndex::LUMP_GAME_LUMP as usize].off - BSP_HEADER_LEN) as usize;
 
+        for (i, game_lump) in game_lumps.iter().enumerate() {
+            if game_lump.off > after_off {
+                // count + (id, flags, version) before the offset field
+                let pos = dir_start + 4 + i * GAME_LUMP_ENTRY_LEN + 8;
+                let new_off = (game_lump.off as i32 + diff) as u32;
+                self.data_without_header[pos..pos + 4].copy_from_slice(&new_off.to_le_bytes());
+            }
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn std::error::Error>> {
+        self.header.write(writer)?;
         writer.write_all(&self.data_without_header)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::super::game_lump::{GameLump, GAMELUMP_STATIC_PROPS};
+    use super::super::BSPReader;
+    use super::*;
+
+    /// Builds a map with given (lump, offset, length) entries and `data_len`
+    /// zero bytes after the header
+    pub(in crate::bsp) fn test_bsp(
+        lumps: &[(LumpIndex, u32, u32)],
+        data_len: usize,
+    ) -> BufferedBSP {
+        let mut buf = b"VBSP".to_vec();
+        buf.extend_from_slice(&20u32.to_le_bytes());
+        let mut table = vec![0; 64 * 16];
+        for (lump_index, off, len) in lumps {
+            let entry = *lump_index as usize * 16;
+            table[entry..entry + 4].copy_from_slice(&off.to_le_bytes());
+            table[entry + 4..entry + 8].copy_from_slice(&len.to_le_bytes());
+        }
+        buf.extend(table);
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.resize(BSP_HEADER_LEN as usize + data_len, 0);
+        BSPReader::from_reader(Cursor::new(buf))
+            .unwrap()
+            .into_buffered_bsp()
+    }
+
+    #[test]
+    fn keeps_game_lumps_when_pakfile_grows() {
+        let mut bsp = test_bsp(&[], 0);
+        bsp.add_to_pakfile(vec![("a.txt".to_string(), b"a".to_vec())])
+            .unwrap();
+        let game_lump = GameLump {
+            id: GAMELUMP_STATIC_PROPS,
+            flags: 0,
+            version: 10,
+            off: 0,
+            len: 4,
+        };
+        bsp.replace_game_lumps(vec![(game_lump, vec![1, 2, 3, 4])]);
+
+        // the pakfile is before the game lump, so growing it moves game lump contents
+        bsp.add_to_pakfile(vec![("b.txt".to_string(), vec![0; 100])])
+            .unwrap();
+
+        let mut buf = vec![];
+        bsp.write(&mut buf).unwrap();
+        let bsp = BSPReader::from_reader(Cursor::new(buf))
+            .unwrap()
+            .into_buffered_bsp();
+
+        let game_lumps = bsp.game_lumps().unwrap();
+        assert_eq!(game_lumps.len(), 1);
+        assert_eq!(&*bsp.game_lump_data(&game_lumps[0]).unwrap(), &[1, 2, 3, 4]);
+
+        let mut pakfile = bsp.pakfile().unwrap().unwrap();
+        let mut names: Vec<&str> = pakfile.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert_eq!(pakfile.by_name("b.txt").unwrap().size(), 100);
+        assert!(bsp.check().is_ok());
+    }
+
+    #[test]
+    fn detects_vindictus_structures() {
+        // one node and one face, in Vindictus sizes
+        let bsp = test_bsp(
+            &[
+                (LumpIndex::LUMP_NODES, 1036, 48),
+                (LumpIndex::LUMP_FACES, 1084, 72),
+            ],
+            120,
+        );
+        assert_eq!(bsp.header.variant, BSPVariant::Vindictus);
+        assert!(matches!(bsp.nodes(), Err(Error::UnsupportedVariant(_))));
+        assert!(matches!(bsp.faces(), Err(Error::UnsupportedVariant(_))));
+
+        // three standard nodes are also a multiple of a Vindictus node
+        let bsp = test_bsp(
+            &[
+                (LumpIndex::LUMP_NODES, 1036, 96),
+                (LumpIndex::LUMP_FACES, 1132, 56),
+            ],
+            152,
+        );
+        assert_eq!(bsp.header.variant, BSPVariant::Standard);
+        assert_eq!(bsp.nodes().unwrap().len(), 3);
+    }
+}
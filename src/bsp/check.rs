@@ -0,0 +1,338 @@
+use serde::Serialize;
+use std::io;
+use strum::IntoEnumIterator;
+use zip::result::ZipError;
+
+use super::brushes::{BRUSH_LEN, BRUSH_SIDE_LEN};
+use super::buffered_bsp::BufferedBSP;
+use super::cubemaps::CUBEMAP_SAMPLE_LEN;
+use super::game_lump::{read_directory, GAMELUMP_COMPRESSED};
+use super::geometry::{DISP_INFO_LEN, DISP_VERT_LEN};
+use super::lump_indices::LumpIndex;
+use super::textures::{FACE_LEN, TEXDATA_LEN, TEXINFO_LEN};
+use super::tree::{LEAF_LEN, LEAF_LEN_V0, MODEL_LEN, NODE_LEN, PLANE_LEN};
+use super::{entities, lzma, BSPVariant, BSP_HEADER_LEN};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The map is corrupt and likely won't load
+    Error,
+    /// The map is unusual, but may still load
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub severity: Severity,
+    pub lump: Option<LumpIndex>,
+    pub message: String,
+}
+
+/// Result of validating a map against its own header
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub file_len: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    /// Returns true if no errors were found. Warnings are allowed
+    pub fn is_ok(&self) -> bool {
+        self.issues.iter().all(|i| i.severity != Severity::Error)
+    }
+
+    fn error(&mut self, lump: Option<LumpIndex>, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            lump,
+            message,
+        });
+    }
+
+    fn warning(&mut self, lump: Option<LumpIndex>, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            lump,
+            message,
+        });
+    }
+}
+
+/// Size of a single element in lumps that are arrays of fixed-size structures
+fn struct_len(lump_index: LumpIndex, version: u32) -> Option<usize> {
+    use LumpIndex::*;
+    let len = match lump_index {
+        LUMP_PLANES => PLANE_LEN,
+        LUMP_TEXDATA => TEXDATA_LEN,
+        LUMP_VERTEXES | LUMP_VERTNORMALS => 12,
+        LUMP_NODES => NODE_LEN,
+        LUMP_TEXINFO => TEXINFO_LEN,
+        LUMP_FACES | LUMP_ORIGINALFACES | LUMP_FACES_HDR => FACE_LEN,
+        LUMP_LEAFS if version == 0 => LEAF_LEN_V0,
+        LUMP_LEAFS => LEAF_LEN,
+        LUMP_EDGES | LUMP_SURFEDGES | LUMP_TEXDATA_STRING_TABLE => 4,
+        LUMP_LIGHTING | LUMP_LIGHTING_HDR => 4,
+        LUMP_MODELS => MODEL_LEN,
+        LUMP_LEAFFACES | LUMP_LEAFBRUSHES | LUMP_VERTNORMALINDICES => 2,
+        LUMP_BRUSHES => BRUSH_LEN,
+        LUMP_BRUSHSIDES => BRUSH_SIDE_LEN,
+        LUMP_AREAS => 8,
+        LUMP_AREAPORTALS => 12,
+        LUMP_DISPINFO => DISP_INFO_LEN,
+        LUMP_DISP_VERTS => DISP_VERT_LEN,
+        LUMP_CUBEMAPS => CUBEMAP_SAMPLE_LEN,
+        _ => return None,
+    };
+    Some(len)
+}
+
+impl BufferedBSP {
+    fn file_len(&self) -> u64 {
+        BSP_HEADER_LEN as u64 + self.data_without_header.len() as u64
+    }
+
+    /// Validates lump bounds, fixed-size lump lengths, the pakfile, entity lump and game lumps.
+    ///
+    /// Contents of lumps that extend past the end of file are not checked
+    pub fn check(&self) -> CheckReport {
+        let file_len = self.file_len();
+        let mut report = CheckReport {
+            file_len,
+            issues: vec![],
+        };
+
+        let mut in_bounds = vec![];
+        for lump_index in LumpIndex::iter() {
+            let lump = &self.header.lumps[lump_index as usize];
+            if lump.len == 0 {
+                continue;
+            }
+
+            let end = lump.off as u64 + lump.len as u64;
+            if lump.off < BSP_HEADER_LEN {
+                report.error(
+                    Some(lump_index),
+                    format!("offset {} is inside the header", lump.off),
+                );
+            } else if end > file_len {
+                report.error(
+                    Some(lump_index),
+                    format!(
+                        "ends at {}, past the end of file ({} bytes missing)",
+                        end,
+                        end - file_len
+                    ),
+                );
+            } else {
+                in_bounds.push(lump_index);
+            }
+        }
+
+        let mut ranges: Vec<(LumpIndex, u64, u64)> = in_bounds
+            .iter()
+            .map(|l| {
+                let lump = &self.header.lumps[*l as usize];
+                (*l, lump.off as u64, lump.off as u64 + lump.len as u64)
+            })
+            .collect();
+        ranges.sort_by_key(|(_, off, _)| *off);
+        // compare against the lump reaching furthest so far, as it may contain several others
+        let mut furthest: Option<(LumpIndex, u64)> = None;
+        for (lump_index, off, end) in ranges {
+            match furthest {
+                Some((other, other_end)) if off < other_end => {
+                    report.error(
+                        Some(lump_index),
+                        format!("overlaps {:?} by {} bytes", other, end.min(other_end) - off),
+                    );
+                    if end > other_end {
+                        furthest = Some((lump_index, end));
+                    }
+                }
+                _ => furthest = Some((lump_index, end)),
+            }
+        }
+
+        if self.header.variant == BSPVariant::Vindictus {
+            report.warning(
+                None,
+                "lengths of Vindictus lump structures are not checked".to_string(),
+            );
+        }
+        for lump_index in in_bounds {
+            self.check_lump(lump_index, &mut report);
+        }
+
+        report
+    }
+
+    fn check_lump(&self, lump_index: LumpIndex, report: &mut CheckReport) {
+        let lump = &self.header.lumps[lump_index as usize];
+        let data = match self.lump_data(lump_index) {
+            Ok(data) => data,
+            Err(e) => {
+                report.error(Some(lump_index), format!("can't decompress: {}", e));
+                return;
+            }
+        };
+
+        let struct_len = match self.header.variant {
+            BSPVariant::Vindictus => None,
+            _ => struct_len(lump_index, lump.version),
+        };
+        if let Some(len) = struct_len {
+            if !data.len().is_multiple_of(len) {
+                report.error(
+                    Some(lump_index),
+                    format!(
+                        "length {} is not a multiple of {} byte structures",
+                        data.len(),
+                        len
+                    ),
+                );
+            }
+        }
+
+        match lump_index {
+            LumpIndex::LUMP_ENTITIES => {
+                if let Err(e) = entities::parse(&data) {
+                    report.error(Some(lump_index), e.to_string());
+                }
+            }
+            LumpIndex::LUMP_PAKFILE => self.check_pakfile(report),
+            LumpIndex::LUMP_GAME_LUMP => self.check_game_lumps(&data, report),
+            _ => {}
+        }
+    }
+
+    /// Reads every file in the pakfile, which also verifies their checksums
+    fn check_pakfile(&self, report: &mut CheckReport) {
+        let lump = Some(LumpIndex::LUMP_PAKFILE);
+        let mut archive = match self.pakfile() {
+            Ok(Some(archive)) => archive,
+            Ok(None) => return,
+            Err(e) => {
+                report.error(lump, e.to_string());
+                return;
+            }
+        };
+
+        for i in 0..archive.len() {
+            let mut file = match archive.by_index(i) {
+                Ok(file) => file,
+                Err(ZipError::UnsupportedArchive(e)) => {
+                    report.warning(lump, format!("can't verify file #{}: {}", i, e));
+                    continue;
+                }
+                Err(e) => {
+                    report.error(lump, format!("file #{}: {}", i, e));
+                    continue;
+                }
+            };
+            if let Err(e) = io::copy(&mut file, &mut io::sink()) {
+                report.error(lump, format!("can't read {}: {}", file.name(), e));
+            }
+        }
+    }
+
+    fn check_game_lumps(&self, data: &[u8], report: &mut CheckReport) {
+        let lump = Some(LumpIndex::LUMP_GAME_LUMP);
+        let game_lumps = match read_directory(data) {
+            Ok(game_lumps) => game_lumps,
+            Err(e) => {
+                report.error(lump, format!("can't read game lump directory: {}", e));
+                return;
+            }
+        };
+
+        let file_len = self.file_len();
+        for game_lump in game_lumps.iter().filter(|g| g.id != 0 && g.len != 0) {
+            if game_lump.off < BSP_HEADER_LEN || game_lump.off as u64 >= file_len {
+                report.error(
+                    lump,
+                    format!(
+                        "game lump {} offset {} is outside the file",
+                        game_lump.id_string(),
+                        game_lump.off
+                    ),
+                );
+                continue;
+            }
+
+            let start = (game_lump.off - BSP_HEADER_LEN) as usize;
+            let len = if game_lump.flags & GAMELUMP_COMPRESSED != 0 {
+                match lzma::read_header(&self.data_without_header[start..]) {
+                    Ok(header) => lzma::LZMA_HEADER_LEN as u64 + header.lzma_size as u64,
+                    Err(e) => {
+                        report.error(lump, format!("game lump {}: {}", game_lump.id_string(), e));
+                        continue;
+                    }
+                }
+            } else {
+                game_lump.len as u64
+            };
+
+            let end = game_lump.off as u64 + len;
+            if end > file_len {
+                report.error(
+                    lump,
+                    format!(
+                        "game lump {} ends at {}, past the end of file",
+                        game_lump.id_string(),
+                        end
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::buffered_bsp::tests::test_bsp;
+    use super::super::Error;
+    use super::*;
+
+    #[test]
+    fn reports_truncated_and_overlapping_lumps() {
+        use LumpIndex::*;
+        let bsp = test_bsp(
+            &[
+                (LUMP_VISIBILITY, 1036, 100),
+                (LUMP_PHYSCOLLIDE, 1046, 10),
+                (LUMP_OCCLUSION, 1066, 10),
+                (LUMP_ENTITIES, 1136, 100),
+            ],
+            150,
+        );
+
+        let report = bsp.check();
+        let issues: Vec<_> = report
+            .issues
+            .iter()
+            .map(|i| (i.lump, i.message.as_str()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (
+                    Some(LUMP_ENTITIES),
+                    "ends at 1236, past the end of file (50 bytes missing)"
+                ),
+                (
+                    Some(LUMP_PHYSCOLLIDE),
+                    "overlaps LUMP_VISIBILITY by 10 bytes"
+                ),
+                (Some(LUMP_OCCLUSION), "overlaps LUMP_VISIBILITY by 10 bytes"),
+            ]
+        );
+        assert!(!report.is_ok());
+
+        assert!(matches!(
+            bsp.lump_slice(LUMP_ENTITIES),
+            Err(Error::LumpOutOfBounds(LUMP_ENTITIES, 1136, 100))
+        ));
+        assert!(bsp.pakfile().unwrap().is_none());
+    }
+}
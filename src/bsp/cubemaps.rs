@@ -0,0 +1,83 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, Cursor};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::Error;
+
+pub(crate) const CUBEMAP_SAMPLE_LEN: usize = 16;
+
+/// Cubemap sample as stored in `LUMP_CUBEMAPS`
+#[derive(Debug, Clone)]
+pub struct CubemapSample {
+    pub origin: [i32; 3],
+    /// Size exponent of the cubemap. 0 means the default size
+    pub size: i32,
+}
+
+impl CubemapSample {
+    /// Cubemap face size in pixels, or None if the sample uses the default size
+    /// or the size is too large to be valid
+    pub fn size_pixels(&self) -> Option<u32> {
+        if self.size <= 0 {
+            None
+        } else {
+            1u32.checked_shl((self.size - 1) as u32)
+        }
+    }
+
+    /// Path of the texture buildcubemaps generates for this sample
+    pub fn texture_path(&self, map_name: &str) -> String {
+        format!(
+            "materials/maps/{}/c{}_{}_{}.vtf",
+            map_name.to_lowercase(),
+            self.origin[0],
+            self.origin[1],
+            self.origin[2]
+        )
+    }
+}
+
+pub fn read(lump: &[u8]) -> Result<Vec<CubemapSample>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut samples = vec![];
+    for _ in 0..lump.len() / CUBEMAP_SAMPLE_LEN {
+        samples.push(CubemapSample {
+            origin: [
+                reader.read_i32::<LittleEndian>()?,
+                reader.read_i32::<LittleEndian>()?,
+                reader.read_i32::<LittleEndian>()?,
+            ],
+            size: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(samples)
+}
+
+impl BufferedBSP {
+    pub fn cubemaps(&self) -> Result<Vec<CubemapSample>, Error> {
+        Ok(read(&self.lump_data(LumpIndex::LUMP_CUBEMAPS)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_cubemap_sizes() {
+        let sample = |size| CubemapSample {
+            origin: [0; 3],
+            size,
+        };
+        assert_eq!(sample(0).size_pixels(), None);
+        assert_eq!(sample(-1).size_pixels(), None);
+        assert_eq!(sample(1).size_pixels(), Some(1));
+        assert_eq!(sample(7).size_pixels(), Some(64));
+        assert_eq!(sample(32).size_pixels(), Some(1 << 31));
+        assert_eq!(sample(33).size_pixels(), None);
+        assert_eq!(sample(i32::MAX).size_pixels(), None);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+use super::buffered_bsp::{BufferedBSP, Pakfile};
+use super::entities;
+use super::lump_indices::LumpIndex;
+use crate::dependency::{DependencyGraph, NodeRef};
+use crate::mdl::{MDLDependencyKind, MDLFile};
+use crate::search_path::{normalize_path, ContentLocation, SearchPath};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BSPDependencyKind {
+    Map,
+    Material,
+    Texture,
+    Model,
+    Sound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BSPDependencyStatus {
+    /// Contained in the map's pakfile
+    Packed,
+    /// Found from a search path root
+    Found(ContentLocation),
+    Missing,
+}
+
+impl fmt::Display for BSPDependencyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BSPDependencyStatus::Packed => write!(f, "packed"),
+            BSPDependencyStatus::Found(_) => write!(f, "found"),
+            BSPDependencyStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BSPDependency {
+    pub kind: BSPDependencyKind,
+    /// Game-relative path, e.g. "materials/concrete/concretefloor001a.vmt"
+    pub path: String,
+    pub status: BSPDependencyStatus,
+    /// Why the file couldn't be read, e.g. a model with an unsupported version.
+    /// Dependencies of such files are not included
+    pub error: Option<String>,
+}
+
+impl BSPDependency {
+    /// Returns whether the file was found from a content root with a name
+    /// that differs in case. The map itself is never reported, as it's
+    /// opened from the given path
+    pub fn case_differs(&self) -> bool {
+        match &self.status {
+            BSPDependencyStatus::Found(location) if self.kind != BSPDependencyKind::Map => {
+                location.case_differs(&self.path)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BSPDependencyType {
+    /// Referenced by the map itself
+    Direct,
+    /// Referenced by another dependency, e.g. a texture used by a material
+    Indirect,
+}
+
+const SKYBOX_SIDES: [&str; 6] = ["up", "dn", "lf", "rt", "ft", "bk"];
+
+/// Characters that may prefix a sound path to control how it's played
+const SOUND_CHARS: &[char] = &[
+    '*', '#', '@', '>', '<', '^', ')', '(', '}', '$', '!', '?', '&', '~',
+];
+const SOUND_EXTENSIONS: [&str; 3] = [".wav", ".mp3", ".ogg"];
+
+struct Resolver<'a> {
+    pakfile: Option<Pakfile<'a>>,
+    /// Normalized path -> name of the file in pakfile
+    packed: HashMap<String, String>,
+    search_path: &'a SearchPath,
+    seen: HashSet<String>,
+    deps: DependencyGraph<BSPDependency, BSPDependencyType>,
+}
+
+impl<'a> Resolver<'a> {
+    fn status(&self, path: &str) -> BSPDependencyStatus {
+        if self.packed.contains_key(path) {
+            BSPDependencyStatus::Packed
+        } else if let Some(p) = self.search_path.find(path) {
+            BSPDependencyStatus::Found(p)
+        } else {
+            BSPDependencyStatus::Missing
+        }
+    }
+
+    fn read(
+        &mut self,
+        path: &str,
+        status: &BSPDependencyStatus,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        match status {
+            BSPDependencyStatus::Packed => {
+                let name = &self.packed[path];
+                let mut file = self.pakfile.as_mut().unwrap().by_name(name)?;
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok(Some(buf))
+            }
+            BSPDependencyStatus::Found(location) => Ok(Some(self.search_path.read(location)?)),
+            BSPDependencyStatus::Missing => Ok(None),
+        }
+    }
+
+    /// Adds dependency unless it has already been added. Optional dependencies
+    /// are only added if they exist
+    fn add(
+        &mut self,
+        parent: Option<NodeRef>,
+        kind: BSPDependencyKind,
+        path: String,
+        optional: bool,
+    ) -> Option<(NodeRef, BSPDependencyStatus)> {
+        if self.seen.contains(&path) {
+            return None;
+        }
+
+        let status = self.status(&path);
+        if optional && status == BSPDependencyStatus::Missing {
+            return None;
+        }
+
+        self.seen.insert(path.clone());
+        let dep = BSPDependency {
+            kind,
+            path,
+            status: status.clone(),
+            error: None,
+        };
+        let node = match parent {
+            Some(parent) => self
+                .deps
+                .insert_sub(parent, dep, BSPDependencyType::Indirect),
+            None => self.deps.insert(dep, BSPDependencyType::Direct),
+        };
+        Some((node, status))
+    }
+
+    fn add_material(
+        &mut self,
+        parent: Option<NodeRef>,
+        name: &str,
+        optional: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = material_path(name);
+        let (node, status) =
+            match self.add(parent, BSPDependencyKind::Material, path.clone(), optional) {
+                Some(added) => added,
+                None => return Ok(()),
+            };
+
+        if let Some(contents) = self.read(&path, &status)? {
+            let vmt = crate::vmt::parse(&String::from_utf8_lossy(&contents));
+            for tex in vmt.textures {
+                // render targets and cubemap placeholders are not files
+                let tex = normalize_path(&tex);
+                if tex.starts_with("_rt_") || tex == "env_cubemap" {
+                    continue;
+                }
+                self.add(
+                    Some(node),
+                    BSPDependencyKind::Texture,
+                    texture_path(&tex),
+                    false,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_model(
+        &mut self,
+        parent: Option<NodeRef>,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = normalize_path(name);
+        let (node, status) = match self.add(parent, BSPDependencyKind::Model, path.clone(), false) {
+            Some(added) => added,
+            None => return Ok(()),
+        };
+
+        match status {
+            BSPDependencyStatus::Found(ContentLocation::File { path: mdl_path, .. }) => {
+                // a model that can't be read is kept with the reason, so the rest of
+                // the map is still resolved
+                let mdl_deps = match MDLFile::open(&mdl_path)
+                    .and_then(|mdl| mdl.dependencies(self.search_path))
+                {
+                    Ok(mdl_deps) => mdl_deps,
+                    Err(e) => {
+                        self.deps.node_mut(node).error = Some(e.to_string());
+                        return Ok(());
+                    }
+                };
+                for dep in mdl_deps.flatten() {
+                    let kind = match dep.kind {
+                        MDLDependencyKind::Material => BSPDependencyKind::Material,
+                        MDLDependencyKind::Texture => BSPDependencyKind::Texture,
+                        _ => BSPDependencyKind::Model,
+                    };
+                    // the model itself has already been added
+                    if let Some((sub, _)) =
+                        self.add(Some(node), kind, normalize_path(&dep.path), false)
+                    {
+                        self.deps.node_mut(sub).error = dep.error;
+                    }
+                }
+            }
+            BSPDependencyStatus::Found(ref location) => {
+                // models inside archives can't be opened as MDLFile, so look for the
+                // auxiliary files (.vvd, .vtx, .phy) next to it and parse it from memory
+                let prefix = format!("{}.", path.trim_end_matches(".mdl"));
+                for sibling in self
+                    .search_path
+                    .archive_files_with_prefix(location, &prefix)
+                {
+                    if sibling != path {
+                        self.add(Some(node), BSPDependencyKind::Model, sibling, false);
+                    }
+                }
+                self.add_model_materials(node, &path, &status)?;
+            }
+            BSPDependencyStatus::Packed => {
+                // same as above, but for models in the pakfile
+                let prefix = format!("{}.", path.trim_end_matches(".mdl"));
+                let mut siblings: Vec<String> = self
+                    .packed
+                    .keys()
+                    .filter(|p| p.starts_with(&prefix) && **p != path)
+                    .cloned()
+                    .collect();
+                siblings.sort();
+                for sibling in siblings {
+                    self.add(Some(node), BSPDependencyKind::Model, sibling, false);
+                }
+                self.add_model_materials(node, &path, &status)?;
+            }
+            BSPDependencyStatus::Missing => {}
+        }
+
+        Ok(())
+    }
+
+    /// Adds materials (and their textures) of a model that isn't a loose file.
+    /// Each material is the first of its paths in the model's texture dirs that
+    /// exists, or the first path if none do
+    fn add_model_materials(
+        &mut self,
+        node: NodeRef,
+        path: &str,
+        status: &BSPDependencyStatus,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let contents = match self.read(path, status)? {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+        // models that can't be parsed still have their auxiliary files listed
+        let mdl = match MDLFile::from_bytes(contents) {
+            Ok(mdl) => mdl,
+            Err(_) => return Ok(()),
+        };
+
+        for name in &mdl.header().texture_names {
+            let paths = mdl.material_paths(name);
+            let material = paths
+                .iter()
+                .find(|p| self.status(p) != BSPDependencyStatus::Missing)
+                .or_else(|| paths.first());
+            if let Some(material) = material {
+                self.add_material(Some(node), material, false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_sound(&mut self, name: &str) {
+        let name = normalize_path(name.trim_start_matches(SOUND_CHARS));
+        // sounds without an extension are soundscript entries, not files
+        if SOUND_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+            self.add(
+                None,
+                BSPDependencyKind::Sound,
+                format!("sound/{}", name),
+                false,
+            );
+        }
+    }
+
+    fn add_entity(&mut self, entity: &entities::Entity) -> Result<(), Box<dyn std::error::Error>> {
+        let classname = entity.classname().unwrap_or("").to_lowercase();
+
+        for (key, value) in &entity.properties {
+            match (classname.as_str(), key.to_lowercase().as_str()) {
+                (_, "model") => {
+                    let lower = value.to_lowercase();
+                    // "*<n>" refers to a brush model inside the map
+                    if lower.ends_with(".mdl") {
+                        self.add_model(None, value)?;
+                    } else if lower.ends_with(".vmt") || lower.ends_with(".spr") {
+                        self.add_material(None, value, false)?;
+                    }
+                }
+                ("worldspawn", "skyname") => {
+                    for side in &SKYBOX_SIDES {
+                        self.add_material(None, &format!("skybox/{}{}", value, side), false)?;
+                        self.add_material(None, &format!("skybox/{}_hdr{}", value, side), true)?;
+                    }
+                }
+                ("worldspawn", "detailmaterial")
+                | ("info_overlay", "material")
+                | ("info_overlay_transition", "material")
+                | ("env_beam", "texture")
+                | ("env_laser", "texture")
+                | ("env_spritetrail", "spritename")
+                | ("move_rope", "ropematerial")
+                | ("keyframe_rope", "ropematerial") => {
+                    self.add_material(None, value, false)?;
+                }
+                ("ambient_generic", "message") => {
+                    self.add_sound(value);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns "materials/<name>.vmt" for a material name as used in texdata and entities
+pub fn material_path(name: &str) -> String {
+    let name = normalize_path(name);
+    let name = name.strip_prefix("materials/").unwrap_or(&name);
+    let name = name
+        .trim_end_matches(".vmt")
+        .trim_end_matches(".spr")
+        .trim_end_matches(".vtf");
+    format!("materials/{}.vmt", name)
+}
+
+/// Returns "materials/<name>.vtf" for a texture name as used in VMTs
+pub fn texture_path(name: &str) -> String {
+    let name = normalize_path(name);
+    let name = name.strip_prefix("materials/").unwrap_or(&name);
+    format!("materials/{}.vtf", name.trim_end_matches(".vtf"))
+}
+
+impl BufferedBSP {
+    /// Resolves content referenced by this map: materials (and their textures),
+    /// static prop and entity models (and their dependencies), skybox, sounds
+    /// and cubemap textures.
+    ///
+    /// Each dependency is looked up from the pakfile first and then from the search path
+    pub fn dependencies(
+        &self,
+        map_path: &Path,
+        search_path: &SearchPath,
+    ) -> Result<DependencyGraph<BSPDependency, BSPDependencyType>, Box<dyn std::error::Error>> {
+        let map_name = map_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let pakfile = self.pakfile()?;
+        let packed = match &pakfile {
+            Some(archive) => archive
+                .file_names()
+                .map(|name| (normalize_path(name), name.to_string()))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut resolver = Resolver {
+            pakfile,
+            packed,
+            search_path,
+            seen: HashSet::new(),
+            deps: DependencyGraph::new(BSPDependency {
+                kind: BSPDependencyKind::Map,
+                path: format!("maps/{}.bsp", map_name),
+                status: BSPDependencyStatus::Found(ContentLocation::File {
+                    path: map_path.to_path_buf(),
+                    root: None,
+                }),
+                error: None,
+            }),
+        };
+
+        for name in self.texdata_strings()? {
+            resolver.add_material(None, &name, false)?;
+        }
+
+        for model in self.static_prop_models()? {
+            resolver.add_model(None, &model)?;
+        }
+
+        for entity in entities::parse(&self.lump_data(LumpIndex::LUMP_ENTITIES)?)? {
+            resolver.add_entity(&entity)?;
+        }
+
+        for cubemap in self.cubemaps()? {
+            let path = cubemap.texture_path(&map_name);
+            let hdr_path = path.replace(".vtf", ".hdr.vtf");
+            resolver.add(None, BSPDependencyKind::Texture, path, false);
+            resolver.add(None, BSPDependencyKind::Texture, hdr_path, true);
+        }
+
+        Ok(resolver.deps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::buffered_bsp::tests::test_bsp;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn lists_models_that_cant_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("models")).unwrap();
+        fs::write(dir.path().join("models/bad.mdl"), b"not a model").unwrap();
+        let mut search_path = SearchPath::new();
+        search_path.add(dir.path()).unwrap();
+
+        let mut bsp = test_bsp(&[], 0);
+        bsp.replace_lump(
+            LumpIndex::LUMP_ENTITIES,
+            b"{\n\"classname\" \"prop_dynamic\"\n\"model\" \"models/bad.mdl\"\n}\n\
+              {\n\"classname\" \"info_overlay\"\n\"material\" \"decals/x\"\n}\n\0"
+                .to_vec(),
+        );
+
+        let deps = bsp
+            .dependencies(Path::new("test.bsp"), &search_path)
+            .unwrap()
+            .flatten();
+        let model = deps.iter().find(|d| d.path == "models/bad.mdl").unwrap();
+        assert!(model.error.is_some());
+        // entities after the broken model are still resolved
+        assert!(deps.iter().any(|d| d.path == "materials/decals/x.vmt"));
+    }
+}
@@ -0,0 +1,289 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use strum::IntoEnumIterator;
+
+use super::buffered_bsp::BufferedBSP;
+use super::entities::{self, Entity};
+use super::lump_indices::LumpIndex;
+use super::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Change of a single entity key. Keys that repeat (e.g. outputs) are compared
+/// by value, so an added output has no old value
+#[derive(Debug, Clone)]
+pub struct PropertyChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EntityChange {
+    pub kind: ChangeKind,
+    /// Human readable description, e.g. `prop_door_rotating "door1" (hammerid 42)`
+    pub description: String,
+    /// Changed keys of modified entities
+    pub properties: Vec<PropertyChange>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub kind: ChangeKind,
+    pub path: String,
+}
+
+/// Differences between two maps
+#[derive(Debug, Clone, Default)]
+pub struct BSPDiff {
+    /// Lumps with different (decompressed) contents
+    pub lumps: Vec<LumpIndex>,
+    pub entities: Vec<EntityChange>,
+    pub packed_files: Vec<FileChange>,
+    /// Static props, described by model and placement, that are only in the first map
+    pub removed_static_props: Vec<String>,
+    /// Static props that are only in the second map
+    pub added_static_props: Vec<String>,
+}
+
+impl BSPDiff {
+    pub fn is_empty(&self) -> bool {
+        self.lumps.is_empty()
+            && self.entities.is_empty()
+            && self.packed_files.is_empty()
+            && self.removed_static_props.is_empty()
+            && self.added_static_props.is_empty()
+    }
+}
+
+impl BufferedBSP {
+    /// Returns SHA-256 of lump contents as hex. Compressed lumps are hashed after
+    /// decompressing, so the hash doesn't depend on compression
+    pub fn lump_hash(&self, lump_index: LumpIndex) -> Result<String, Error> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.lump_data(lump_index)?);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Key used to match entities between maps. vbsp keeps hammer ids of most
+/// entities, other entities are matched by class, name and origin
+fn entity_key(entity: &Entity) -> String {
+    if let Some(id) = entity.get("hammerid") {
+        return format!("hammerid {}", id);
+    }
+    format!(
+        "{} {} {}",
+        entity.classname().unwrap_or_default(),
+        entity.get("targetname").unwrap_or_default(),
+        entity.get("origin").unwrap_or_default()
+    )
+}
+
+fn describe_entity(entity: &Entity) -> String {
+    let mut description = entity.classname().unwrap_or("<no classname>").to_string();
+    if let Some(name) = entity.get("targetname") {
+        description += &format!(" \"{}\"", name);
+    }
+    if let Some(id) = entity.get("hammerid") {
+        description += &format!(" (hammerid {})", id);
+    } else if let Some(origin) = entity.get("origin") {
+        description += &format!(" at {}", origin);
+    }
+    description
+}
+
+fn diff_properties(a: &Entity, b: &Entity) -> Vec<PropertyChange> {
+    let mut added = b.properties.clone();
+    let mut removed = vec![];
+    for property in &a.properties {
+        match added.iter().position(|p| p == property) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => removed.push(property.clone()),
+        }
+    }
+
+    let mut changes = vec![];
+    for (key, old) in removed {
+        let new = added
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(&key))
+            .map(|i| added.remove(i).1);
+        changes.push(PropertyChange {
+            key,
+            old: Some(old),
+            new,
+        });
+    }
+    for (key, new) in added {
+        changes.push(PropertyChange {
+            key,
+            old: None,
+            new: Some(new),
+        });
+    }
+    changes
+}
+
+fn diff_entities(a: &[Entity], b: &[Entity]) -> Vec<EntityChange> {
+    let mut by_key: BTreeMap<String, (Vec<&Entity>, Vec<&Entity>)> = BTreeMap::new();
+    for entity in a {
+        by_key.entry(entity_key(entity)).or_default().0.push(entity);
+    }
+    for entity in b {
+        by_key.entry(entity_key(entity)).or_default().1.push(entity);
+    }
+
+    let mut changes = vec![];
+    for (a, b) in by_key.values() {
+        for (a, b) in a.iter().zip(b.iter()) {
+            let properties = diff_properties(a, b);
+            if !properties.is_empty() {
+                changes.push(EntityChange {
+                    kind: ChangeKind::Modified,
+                    description: describe_entity(b),
+                    properties,
+                });
+            }
+        }
+        for (kind, extra) in [
+            (ChangeKind::Removed, a.iter().skip(b.len())),
+            (ChangeKind::Added, b.iter().skip(a.len())),
+        ] {
+            for entity in extra {
+                changes.push(EntityChange {
+                    kind,
+                    description: describe_entity(entity),
+                    properties: vec![],
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Returns (crc32, size) of pakfile entries by path
+fn packed_file_checksums(bsp: &BufferedBSP) -> Result<BTreeMap<String, (u32, u64)>, Error> {
+    let mut checksums = BTreeMap::new();
+    if let Some(mut archive) = bsp.pakfile()? {
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            checksums.insert(file.name().to_string(), (file.crc32(), file.size()));
+        }
+    }
+    Ok(checksums)
+}
+
+fn diff_packed_files(a: &BufferedBSP, b: &BufferedBSP) -> Result<Vec<FileChange>, Error> {
+    let a = packed_file_checksums(a)?;
+    let b = packed_file_checksums(b)?;
+
+    let mut changes = vec![];
+    for (path, checksum) in &a {
+        let kind = match b.get(path) {
+            None => ChangeKind::Removed,
+            Some(other) if other != checksum => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        changes.push(FileChange {
+            kind,
+            path: path.clone(),
+        });
+    }
+    for path in b.keys().filter(|p| !a.contains_key(*p)) {
+        changes.push(FileChange {
+            kind: ChangeKind::Added,
+            path: path.clone(),
+        });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Returns static props described as `model at origin angles skin`, with their counts
+fn static_prop_counts(bsp: &BufferedBSP) -> Result<BTreeMap<String, usize>, Error> {
+    let mut counts = BTreeMap::new();
+    for prop in bsp.static_props()? {
+        let [x, y, z] = prop.origin;
+        let [pitch, yaw, roll] = prop.angles;
+        let description = format!(
+            "{} at {} {} {} angles {} {} {} skin {}",
+            prop.model, x, y, z, pitch, yaw, roll, prop.skin
+        );
+        *counts.entry(description).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+/// Compares lump contents, entities, packed files and static props of two maps
+pub fn diff(a: &BufferedBSP, b: &BufferedBSP) -> Result<BSPDiff, Error> {
+    let mut lumps = vec![];
+    for lump_index in LumpIndex::iter() {
+        if a.lump_hash(lump_index)? != b.lump_hash(lump_index)? {
+            lumps.push(lump_index);
+        }
+    }
+
+    let a_props = static_prop_counts(a)?;
+    let b_props = static_prop_counts(b)?;
+    let only_in = |x: &BTreeMap<String, usize>, y: &BTreeMap<String, usize>| {
+        x.iter()
+            .flat_map(|(prop, count)| {
+                let extra = count.saturating_sub(*y.get(prop).unwrap_or(&0));
+                std::iter::repeat_n(prop.clone(), extra)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    Ok(BSPDiff {
+        lumps,
+        entities: diff_entities(
+            &entities::parse(&a.lump_data(LumpIndex::LUMP_ENTITIES)?)?,
+            &entities::parse(&b.lump_data(LumpIndex::LUMP_ENTITIES)?)?,
+        ),
+        packed_files: diff_packed_files(a, b)?,
+        removed_static_props: only_in(&a_props, &b_props),
+        added_static_props: only_in(&b_props, &a_props),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(properties: &[(&str, &str)]) -> Entity {
+        Entity {
+            properties: properties
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diffs_entities_by_hammer_id() {
+        let a = vec![
+            entity(&[("classname", "light"), ("hammerid", "1"), ("_light", "255")]),
+            entity(&[("classname", "info_target"), ("hammerid", "2")]),
+        ];
+        let b = vec![
+            entity(&[("classname", "light"), ("hammerid", "1"), ("_light", "128")]),
+            entity(&[("classname", "info_target"), ("hammerid", "3")]),
+        ];
+
+        let changes = diff_entities(&a, &b);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].kind, ChangeKind::Modified);
+        assert_eq!(changes[0].properties[0].old.as_deref(), Some("255"));
+        assert_eq!(changes[0].properties[0].new.as_deref(), Some("128"));
+        assert_eq!(changes[1].kind, ChangeKind::Removed);
+        assert_eq!(changes[2].kind, ChangeKind::Added);
+    }
+}
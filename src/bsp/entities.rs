@@ -0,0 +1,144 @@
+use super::Error;
+
+/// Single entity from the entity lump. Keys are kept in their original order
+/// and may repeat (e.g. multiple "OnTrigger" outputs)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub properties: Vec<(String, String)>,
+}
+
+impl Entity {
+    /// Returns the first value for given key. Keys are matched case-insensitively
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+}
+
+/// Parses entity lump contents into a list of entities
+pub fn parse(lump: &[u8]) -> Result<Vec<Entity>, Error> {
+    // entity lump is null terminated
+    let end = lump.iter().position(|b| *b == 0).unwrap_or(lump.len());
+    let text = String::from_utf8_lossy(&lump[..end]);
+
+    let mut chars = text.chars().peekable();
+    let mut entities = vec![];
+
+    loop {
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => break,
+            Some('{') => {}
+            Some(c) => {
+                return Err(Error::MalformedEntities(format!(
+                    "expected '{{', got {:?}",
+                    c
+                )))
+            }
+        }
+
+        let mut properties = vec![];
+        loop {
+            skip_whitespace(&mut chars);
+            match chars.peek() {
+                Some('}') => {
+                    chars.next();
+                    break;
+                }
+                Some('"') => {
+                    let key = read_quoted(&mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let value = read_quoted(&mut chars)?;
+                    properties.push((key, value));
+                }
+                Some(c) => {
+                    return Err(Error::MalformedEntities(format!(
+                        "expected key or '}}', got {:?}",
+                        c
+                    )))
+                }
+                None => {
+                    return Err(Error::MalformedEntities(
+                        "unexpected end of entity lump".to_string(),
+                    ))
+                }
+            }
+        }
+
+        entities.push(Entity { properties });
+    }
+
+    Ok(entities)
+}
+
+/// Serializes entities into entity lump contents, including the null terminator
+pub fn serialize(entities: &[Entity]) -> Vec<u8> {
+    let mut out = String::new();
+    for entity in entities {
+        out.push_str("{\n");
+        for (key, value) in &entity.properties {
+            out.push_str(&format!("\"{}\" \"{}\"\n", key, value));
+        }
+        out.push_str("}\n");
+    }
+
+    let mut out = out.into_bytes();
+    out.push(0);
+    out
+}
+
+fn skip_whitespace<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn read_quoted<I: Iterator<Item = char>>(
+    chars: &mut std::iter::Peekable<I>,
+) -> Result<String, Error> {
+    if chars.next() != Some('"') {
+        return Err(Error::MalformedEntities("expected '\"'".to_string()));
+    }
+
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some(c) => s.push(c),
+            None => return Err(Error::MalformedEntities("unterminated string".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entities() {
+        let lump = b"{\n\"classname\" \"worldspawn\"\n\"skyname\" \"sky_day01_01\"\n}\n{\n\"classname\" \"prop_dynamic\"\n\"model\" \"models/foo.mdl\"\n}\n\0";
+        let entities = parse(lump).unwrap();
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].classname(), Some("worldspawn"));
+        assert_eq!(entities[0].get("SkyName"), Some("sky_day01_01"));
+        assert_eq!(entities[1].get("model"), Some("models/foo.mdl"));
+    }
+
+    #[test]
+    fn serializes_entities() {
+        let lump = b"{\n\"classname\" \"worldspawn\"\n}\n{\n\"classname\" \"light\"\n\"_light\" \"255 255 255 200\"\n}\n\0";
+        assert_eq!(serialize(&parse(lump).unwrap()), lump.to_vec());
+    }
+
+    #[test]
+    fn rejects_unterminated_entity() {
+        assert!(parse(b"{\n\"classname\" \"worldspawn\"\n").is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Cow;
+use std::io::{self, Cursor, Read};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::textures::read_f32s;
+use super::{lzma, Error, BSP_HEADER_LEN};
+
+/// Game lump id of the static prop lump ("sprp")
+pub const GAMELUMP_STATIC_PROPS: u32 = u32::from_be_bytes(*b"sprp");
+
+/// Game lump flag set when the game lump is LZMA compressed. In that case `len`
+/// is the uncompressed length
+pub const GAMELUMP_COMPRESSED: u16 = 1;
+
+/// Size of a single game lump directory entry
+pub(crate) const GAME_LUMP_ENTRY_LEN: usize = 16;
+const STATIC_PROP_NAME_LEN: usize = 128;
+/// Length of the static prop fields read by `read_static_props`. Entries of every
+/// static prop lump version are at least this long
+const STATIC_PROP_MIN_LEN: usize = 36;
+
+/// Entry in the game lump directory.
+///
+/// Note that `off` is relative to the start of the file, not to the game lump
+#[derive(Debug, Clone)]
+pub struct GameLump {
+    pub id: u32,
+    pub flags: u16,
+    pub version: u16,
+    pub off: u32,
+    pub len: u32,
+}
+
+impl GameLump {
+    pub fn id_string(&self) -> String {
+        String::from_utf8_lossy(&self.id.to_be_bytes()).into_owned()
+    }
+}
+
+/// Reads the game lump directory from game lump contents.
+///
+/// Maps with compressed game lumps have a terminating dummy entry with id 0,
+/// which is included here
+pub fn read_directory(lump: &[u8]) -> Result<Vec<GameLump>, io::Error> {
+    if lump.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut reader = Cursor::new(lump);
+
+    let count = reader.read_i32::<LittleEndian>()?;
+    let mut game_lumps = vec![];
+    for _ in 0..count.max(0) {
+        game_lumps.push(GameLump {
+            id: reader.read_u32::<LittleEndian>()?,
+            flags: reader.read_u16::<LittleEndian>()?,
+            version: reader.read_u16::<LittleEndian>()?,
+            off: reader.read_u32::<LittleEndian>()?,
+            len: reader.read_u32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(game_lumps)
+}
+
+/// Reads the model dictionary from static prop game lump contents
+pub fn read_static_prop_dictionary(lump: &[u8]) -> Result<Vec<String>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let count = reader.read_i32::<LittleEndian>()?;
+    let mut names = vec![];
+    for _ in 0..count.max(0) {
+        let mut buf = [0; STATIC_PROP_NAME_LEN];
+        reader.read_exact(&mut buf)?;
+
+        let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        names.push(String::from_utf8_lossy(&buf[..end]).into_owned());
+    }
+
+    Ok(names)
+}
+
+/// Placement of a single static prop. Only fields shared by all static prop
+/// lump versions are read
+#[derive(Debug, Clone)]
+pub struct StaticProp {
+    pub origin: [f32; 3],
+    pub angles: [f32; 3],
+    pub model: String,
+    pub solid: u8,
+    pub skin: i32,
+}
+
+/// Reads static props from static prop game lump contents
+pub fn read_static_props(lump: &[u8]) -> Result<Vec<StaticProp>, io::Error> {
+    let names = read_static_prop_dictionary(lump)?;
+    let mut reader = Cursor::new(lump);
+    reader.set_position((4 + names.len() * STATIC_PROP_NAME_LEN) as u64);
+
+    // leaf indices used by the props
+    let leaf_count = reader.read_i32::<LittleEndian>()?.max(0) as u64;
+    reader.set_position(reader.position() + leaf_count * 2);
+
+    let count = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    // entry size depends on the lump version
+    let start = reader.position() as usize;
+    let entry_len = (lump.len() - start) / count;
+    if entry_len < STATIC_PROP_MIN_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} static props don't fit in {} bytes",
+                count,
+                lump.len() - start
+            ),
+        ));
+    }
+
+    let mut props = vec![];
+    for i in 0..count {
+        reader.set_position((start + i * entry_len) as u64);
+        let origin = read_f32s(&mut reader)?;
+        let angles = read_f32s(&mut reader)?;
+        let prop_type = reader.read_u16::<LittleEndian>()? as usize;
+        reader.read_u16::<LittleEndian>()?; // first leaf
+        reader.read_u16::<LittleEndian>()?; // leaf count
+        let solid = reader.read_u8()?;
+        reader.read_u8()?; // flags
+        let skin = reader.read_i32::<LittleEndian>()?;
+
+        props.push(StaticProp {
+            origin,
+            angles,
+            model: names.get(prop_type).cloned().unwrap_or_default(),
+            solid,
+            skin,
+        });
+    }
+
+    Ok(props)
+}
+
+/// Overwrites model names in static prop game lump contents. The number of names
+/// must match the existing dictionary
+pub fn write_static_prop_dictionary(lump: &mut [u8], names: &[String]) -> Result<(), Error> {
+    for (i, name) in names.iter().enumerate() {
+        // names are null terminated
+        if name.len() >= STATIC_PROP_NAME_LEN {
+            return Err(Error::StaticPropNameTooLong(name.clone()));
+        }
+
+        let start = 4 + i * STATIC_PROP_NAME_LEN;
+        let entry = lump
+            .get_mut(start..start + STATIC_PROP_NAME_LEN)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        entry.fill(0);
+        entry[..name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    Ok(())
+}
+
+impl BufferedBSP {
+    /// Returns game lumps in the game lump directory, excluding terminating dummy entries
+    pub fn game_lumps(&self) -> Result<Vec<GameLump>, Error> {
+        let mut game_lumps = read_directory(self.lump_slice(LumpIndex::LUMP_GAME_LUMP)?)?;
+        game_lumps.retain(|g| g.id != 0);
+        Ok(game_lumps)
+    }
+
+    /// Returns raw (possibly compressed) game lump contents
+    pub fn game_lump_slice(&self, game_lump: &GameLump) -> &[u8] {
+        if game_lump.off < BSP_HEADER_LEN {
+            return &[];
+        }
+
+        let start = (game_lump.off - BSP_HEADER_LEN) as usize;
+        let rest = self.data_without_header.get(start..).unwrap_or(&[]);
+        let len = if game_lump.flags & GAMELUMP_COMPRESSED != 0 {
+            match lzma::read_header(rest) {
+                Ok(header) => lzma::LZMA_HEADER_LEN + header.lzma_size as usize,
+                Err(_) => 0,
+            }
+        } else {
+            game_lump.len as usize
+        };
+        rest.get(..len).unwrap_or(&[])
+    }
+
+    /// Returns game lump contents, decompressing them if needed
+    pub fn game_lump_data(&self, game_lump: &GameLump) -> Result<Cow<'_, [u8]>, Error> {
+        let slice = self.game_lump_slice(game_lump);
+        if game_lump.flags & GAMELUMP_COMPRESSED != 0 {
+            Ok(Cow::Owned(lzma::decompress(slice)?))
+        } else {
+            Ok(Cow::Borrowed(slice))
+        }
+    }
+
+    /// Rebuilds the game lump from given game lumps and their contents.
+    /// Offsets of the given game lumps are ignored
+    pub fn replace_game_lumps(&mut self, game_lumps: Vec<(GameLump, Vec<u8>)>) {
+        let lump_off = self.header.lumps[LumpIndex::LUMP_GAME_LUMP as usize].off;
+        let lump_off = if lump_off < BSP_HEADER_LEN {
+            BSP_HEADER_LEN + self.data_without_header.len() as u32
+        } else {
+            lump_off
+        };
+
+        // compressed game lump sizes are computed from the next entry's offset,
+        // so the directory needs a terminating entry after the last one
+        let has_compressed = game_lumps
+            .iter()
+            .any(|(g, _)| g.flags & GAMELUMP_COMPRESSED != 0);
+        let entry_count = game_lumps.len() + has_compressed as usize;
+
+        let mut directory = vec![];
+        let mut contents = vec![];
+        let mut off = lump_off + (4 + entry_count * GAME_LUMP_ENTRY_LEN) as u32;
+
+        directory
+            .write_i32::<LittleEndian>(entry_count as i32)
+            .unwrap();
+        for (game_lump, data) in &game_lumps {
+            directory.write_u32::<LittleEndian>(game_lump.id).unwrap();
+            directory
+                .write_u16::<LittleEndian>(game_lump.flags)
+                .unwrap();
+            directory
+                .write_u16::<LittleEndian>(game_lump.version)
+                .unwrap();
+            directory.write_u32::<LittleEndian>(off).unwrap();
+            directory.write_u32::<LittleEndian>(game_lump.len).unwrap();
+
+            contents.extend_from_slice(data);
+            off += data.len() as u32;
+        }
+        if has_compressed {
+            directory.write_u32::<LittleEndian>(0).unwrap();
+            directory.write_u16::<LittleEndian>(0).unwrap();
+            directory.write_u16::<LittleEndian>(0).unwrap();
+            directory.write_u32::<LittleEndian>(off).unwrap();
+            directory.write_u32::<LittleEndian>(0).unwrap();
+        }
+
+        directory.extend(contents);
+        self.replace_lump(LumpIndex::LUMP_GAME_LUMP, directory);
+    }
+
+    /// Replaces contents of the game lump with given id, compressing them if the
+    /// game lump was compressed
+    pub fn replace_game_lump_data(&mut self, id: u32, data: Vec<u8>) -> Result<(), Error> {
+        let mut game_lumps = vec![];
+        for game_lump in self.game_lumps()? {
+            if game_lump.id != id {
+                let slice = self.game_lump_slice(&game_lump).to_vec();
+                game_lumps.push((game_lump, slice));
+                continue;
+            }
+
+            let mut game_lump = game_lump;
+            game_lump.len = data.len() as u32;
+            let contents = if game_lump.flags & GAMELUMP_COMPRESSED != 0 {
+                lzma::compress(&data)?
+            } else {
+                data.clone()
+            };
+            game_lumps.push((game_lump, contents));
+        }
+
+        self.replace_game_lumps(game_lumps);
+        Ok(())
+    }
+
+    /// Returns model names used by static props
+    pub fn static_prop_models(&self) -> Result<Vec<String>, Error> {
+        for game_lump in self.game_lumps()? {
+            if game_lump.id == GAMELUMP_STATIC_PROPS {
+                return Ok(read_static_prop_dictionary(
+                    &self.game_lump_data(&game_lump)?,
+                )?);
+            }
+        }
+        Ok(vec![])
+    }
+
+    pub fn static_props(&self) -> Result<Vec<StaticProp>, Error> {
+        for game_lump in self.game_lumps()? {
+            if game_lump.id == GAMELUMP_STATIC_PROPS {
+                return Ok(read_static_props(&self.game_lump_data(&game_lump)?)?);
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds static prop lump contents without names or leafs
+    fn static_props(count: i32, entries: &[u8]) -> Vec<u8> {
+        let mut lump = vec![0; 8];
+        lump.extend_from_slice(&count.to_le_bytes());
+        lump.extend_from_slice(entries);
+        lump
+    }
+
+    #[test]
+    fn rejects_prop_counts_larger_than_the_lump() {
+        let lump = static_props(1000, &[0; 56]);
+        assert!(read_static_props(&lump).is_err());
+
+        let lump = static_props(2, &[0; 56]);
+        assert!(read_static_props(&lump).is_err());
+
+        let mut entries = vec![0; 112];
+        entries[56..60].copy_from_slice(&1f32.to_le_bytes());
+        let props = read_static_props(&static_props(2, &entries)).unwrap();
+        assert_eq!(props.len(), 2);
+        assert_eq!(props[1].origin, [1.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,384 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Cursor, Seek, SeekFrom};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::textures::{
+    read_f32s, Face, TexInfo, SURF_HINT, SURF_NODRAW, SURF_SKIP, SURF_SKY, SURF_SKY2D,
+};
+use super::Error;
+use crate::mesh::Mesh;
+
+pub(crate) const DISP_INFO_LEN: usize = 176;
+pub(crate) const DISP_VERT_LEN: usize = 20;
+
+/// `ddispinfo_t`, without neighbor information
+#[derive(Debug, Clone)]
+pub struct DispInfo {
+    pub start_position: [f32; 3],
+    /// Index of the first vertex in `LUMP_DISP_VERTS`
+    pub disp_vert_start: i32,
+    pub disp_tri_start: i32,
+    /// Displacement has `(2^power + 1)^2` vertices
+    pub power: i32,
+    pub min_tess: i32,
+    pub smoothing_angle: f32,
+    pub contents: i32,
+    pub map_face: u16,
+}
+
+/// `CDispVert`: offset of a displacement vertex from the flat surface
+#[derive(Debug, Clone)]
+pub struct DispVert {
+    pub vec: [f32; 3],
+    pub dist: f32,
+    pub alpha: f32,
+}
+
+pub fn read_vertexes(lump: &[u8]) -> Result<Vec<[f32; 3]>, io::Error> {
+    let mut reader = Cursor::new(lump);
+    (0..lump.len() / 12)
+        .map(|_| read_f32s(&mut reader))
+        .collect()
+}
+
+pub fn read_edges(lump: &[u8]) -> Result<Vec<[u16; 2]>, io::Error> {
+    let mut reader = Cursor::new(lump);
+    (0..lump.len() / 4)
+        .map(|_| {
+            Ok([
+                reader.read_u16::<LittleEndian>()?,
+                reader.read_u16::<LittleEndian>()?,
+            ])
+        })
+        .collect()
+}
+
+pub fn read_surfedges(lump: &[u8]) -> Result<Vec<i32>, io::Error> {
+    let mut reader = Cursor::new(lump);
+    (0..lump.len() / 4)
+        .map(|_| reader.read_i32::<LittleEndian>())
+        .collect()
+}
+
+pub fn read_disp_infos(lump: &[u8]) -> Result<Vec<DispInfo>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut infos = vec![];
+    for i in 0..lump.len() / DISP_INFO_LEN {
+        reader.seek(SeekFrom::Start((i * DISP_INFO_LEN) as u64))?;
+        infos.push(DispInfo {
+            start_position: read_f32s(&mut reader)?,
+            disp_vert_start: reader.read_i32::<LittleEndian>()?,
+            disp_tri_start: reader.read_i32::<LittleEndian>()?,
+            power: reader.read_i32::<LittleEndian>()?,
+            min_tess: reader.read_i32::<LittleEndian>()?,
+            smoothing_angle: reader.read_f32::<LittleEndian>()?,
+            contents: reader.read_i32::<LittleEndian>()?,
+            map_face: reader.read_u16::<LittleEndian>()?,
+        });
+    }
+
+    Ok(infos)
+}
+
+pub fn read_disp_verts(lump: &[u8]) -> Result<Vec<DispVert>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut verts = vec![];
+    for _ in 0..lump.len() / DISP_VERT_LEN {
+        verts.push(DispVert {
+            vec: read_f32s(&mut reader)?,
+            dist: reader.read_f32::<LittleEndian>()?,
+            alpha: reader.read_f32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(verts)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = dot(v, v).sqrt();
+    if len == 0.0 {
+        v
+    } else {
+        [v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+/// Map data needed to build face geometry
+struct FaceData {
+    vertexes: Vec<[f32; 3]>,
+    edges: Vec<[u16; 2]>,
+    surfedges: Vec<i32>,
+    texinfo: Vec<TexInfo>,
+    /// Material name and texture size of each texdata
+    materials: Vec<(String, [f32; 2])>,
+    disp_infos: Vec<DispInfo>,
+    disp_verts: Vec<DispVert>,
+    normals: Vec<[f32; 3]>,
+}
+
+impl FaceData {
+    fn face_vertices(&self, face: &Face) -> Option<Vec<[f32; 3]>> {
+        let first = usize::try_from(face.first_edge).ok()?;
+        let surfedges = self
+            .surfedges
+            .get(first..first + face.num_edges.max(0) as usize)?;
+        surfedges
+            .iter()
+            .map(|surfedge| {
+                let edge = self.edges.get(surfedge.unsigned_abs() as usize)?;
+                let vertex = if *surfedge >= 0 { edge[0] } else { edge[1] };
+                self.vertexes.get(vertex as usize).copied()
+            })
+            .collect()
+    }
+
+    fn add_face(&self, mesh: &mut Mesh, face: &Face, normal: [f32; 3]) {
+        let info = match usize::try_from(face.texinfo)
+            .ok()
+            .and_then(|i| self.texinfo.get(i))
+        {
+            Some(info) => info,
+            None => return,
+        };
+        let (material, size) = usize::try_from(info.texdata)
+            .ok()
+            .and_then(|i| self.materials.get(i))
+            .cloned()
+            .unwrap_or_else(|| (String::new(), [1.0, 1.0]));
+        let uv = |p: [f32; 3]| {
+            let [s, t] = info.texture_vecs;
+            [
+                (dot(p, [s[0], s[1], s[2]]) + s[3]) / size[0],
+                (dot(p, [t[0], t[1], t[2]]) + t[3]) / size[1],
+            ]
+        };
+
+        let vertices = match self.face_vertices(face) {
+            Some(vertices) if vertices.len() >= 3 => vertices,
+            _ => return,
+        };
+
+        let disp_info = usize::try_from(face.disp_info)
+            .ok()
+            .and_then(|i| self.disp_infos.get(i));
+        if let Some(disp_info) = disp_info {
+            if vertices.len() == 4 {
+                self.add_displacement(mesh, &material, disp_info, &vertices, normal, uv);
+            }
+            return;
+        }
+
+        let indices: Vec<u32> = vertices
+            .iter()
+            .map(|p| mesh.add_vertex(*p, normal, uv(*p)))
+            .collect();
+
+        // faces are wound clockwise in Source, but check just in case
+        let ccw = dot(
+            cross(sub(vertices[1], vertices[0]), sub(vertices[2], vertices[0])),
+            normal,
+        ) >= 0.0;
+        let primitive = mesh.primitive(&material);
+        for i in 1..indices.len() - 1 {
+            if ccw {
+                primitive
+                    .indices
+                    .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+            } else {
+                primitive
+                    .indices
+                    .extend_from_slice(&[indices[0], indices[i + 1], indices[i]]);
+            }
+        }
+    }
+
+    fn add_displacement<F: Fn([f32; 3]) -> [f32; 2]>(
+        &self,
+        mesh: &mut Mesh,
+        material: &str,
+        disp_info: &DispInfo,
+        corners: &[[f32; 3]],
+        normal: [f32; 3],
+        uv: F,
+    ) {
+        // corners start from the one closest to the displacement's start position
+        let start = (0..4)
+            .min_by(|a, b| {
+                let da = sub(corners[*a], disp_info.start_position);
+                let db = sub(corners[*b], disp_info.start_position);
+                dot(da, da).total_cmp(&dot(db, db))
+            })
+            .unwrap();
+        let c: Vec<[f32; 3]> = (0..4).map(|i| corners[(start + i) % 4]).collect();
+
+        let n = (1usize << disp_info.power.clamp(0, 4)) + 1;
+        let first = disp_info.disp_vert_start.max(0) as usize;
+        let verts = match self.disp_verts.get(first..first + n * n) {
+            Some(verts) => verts,
+            None => return,
+        };
+
+        let mut flat = vec![];
+        let mut positions = vec![];
+        for i in 0..n {
+            let t = i as f32 / (n - 1) as f32;
+            let row_start = lerp(c[0], c[1], t);
+            let row_end = lerp(c[3], c[2], t);
+            for j in 0..n {
+                let base = lerp(row_start, row_end, j as f32 / (n - 1) as f32);
+                let vert = &verts[i * n + j];
+                flat.push(base);
+                positions.push([
+                    base[0] + vert.vec[0] * vert.dist,
+                    base[1] + vert.vec[1] * vert.dist,
+                    base[2] + vert.vec[2] * vert.dist,
+                ]);
+            }
+        }
+
+        let ccw = dot(cross(sub(c[3], c[0]), sub(c[1], c[0])), normal) >= 0.0;
+        let mut triangles = vec![];
+        for i in 0..n - 1 {
+            for j in 0..n - 1 {
+                let a = i * n + j;
+                let b = a + 1;
+                let c = a + n;
+                let d = c + 1;
+                // alternate the diagonal like the engine does
+                let quad = if (i + j) % 2 == 0 {
+                    [[a, b, d], [a, d, c]]
+                } else {
+                    [[a, b, c], [b, d, c]]
+                };
+                for [x, y, z] in quad {
+                    triangles.push(if ccw { [x, y, z] } else { [x, z, y] });
+                }
+            }
+        }
+
+        let mut normals = vec![[0.0f32; 3]; positions.len()];
+        for [x, y, z] in &triangles {
+            let face_normal = cross(
+                sub(positions[*y], positions[*x]),
+                sub(positions[*z], positions[*x]),
+            );
+            for v in [x, y, z] {
+                for k in 0..3 {
+                    normals[*v][k] += face_normal[k];
+                }
+            }
+        }
+
+        let base_index = mesh.positions.len() as u32;
+        for ((position, normal), flat) in positions.iter().zip(&normals).zip(&flat) {
+            mesh.add_vertex(*position, normalize(*normal), uv(*flat));
+        }
+        mesh.primitive(material)
+            .indices
+            .extend(triangles.iter().flatten().map(|i| base_index + *i as u32));
+    }
+}
+
+impl BufferedBSP {
+    pub fn vertexes(&self) -> Result<Vec<[f32; 3]>, Error> {
+        Ok(read_vertexes(&self.lump_data(LumpIndex::LUMP_VERTEXES)?)?)
+    }
+
+    pub fn disp_infos(&self) -> Result<Vec<DispInfo>, Error> {
+        Ok(read_disp_infos(&self.lump_data(LumpIndex::LUMP_DISPINFO)?)?)
+    }
+
+    /// Builds a triangle mesh of the world (model 0), including displacements.
+    ///
+    /// Faces are grouped by material and have UVs normalized by texture size.
+    /// Nodraw, skip and hint faces are left out, as are sky faces unless `include_sky` is set
+    pub fn world_mesh(&self, include_sky: bool) -> Result<Mesh, Error> {
+        let strings = self.texdata_strings()?;
+        let data = FaceData {
+            vertexes: self.vertexes()?,
+            edges: read_edges(&self.lump_data(LumpIndex::LUMP_EDGES)?)?,
+            surfedges: read_surfedges(&self.lump_data(LumpIndex::LUMP_SURFEDGES)?)?,
+            texinfo: self.texinfo()?,
+            materials: self
+                .texdata()?
+                .iter()
+                .map(|texdata| {
+                    let name = usize::try_from(texdata.name_string_table_id)
+                        .ok()
+                        .and_then(|i| strings.get(i))
+                        .cloned()
+                        .unwrap_or_default();
+                    let size = [texdata.width.max(1) as f32, texdata.height.max(1) as f32];
+                    (name, size)
+                })
+                .collect(),
+            disp_infos: self.disp_infos()?,
+            disp_verts: read_disp_verts(&self.lump_data(LumpIndex::LUMP_DISP_VERTS)?)?,
+            normals: self.planes()?.iter().map(|p| p.normal).collect(),
+        };
+
+        let mut skip_flags = SURF_NODRAW | SURF_SKIP | SURF_HINT;
+        if !include_sky {
+            skip_flags |= SURF_SKY | SURF_SKY2D;
+        }
+
+        let faces = self.faces()?;
+        let world_faces = match self.models()?.first() {
+            Some(world) => {
+                let first = world.first_face.max(0) as usize;
+                faces
+                    .get(first..first + world.num_faces.max(0) as usize)
+                    .unwrap_or_default()
+            }
+            None => &faces[..],
+        };
+
+        let mut mesh = Mesh::default();
+        for face in world_faces {
+            let flags = usize::try_from(face.texinfo)
+                .ok()
+                .and_then(|i| data.texinfo.get(i))
+                .map_or(SURF_NODRAW, |info| info.flags);
+            if flags & skip_flags != 0 {
+                continue;
+            }
+
+            let normal = match data.normals.get(face.plane_num as usize) {
+                Some(n) if face.side != 0 => [-n[0], -n[1], -n[2]],
+                Some(n) => *n,
+                None => continue,
+            };
+            data.add_face(&mut mesh, face, normal);
+        }
+
+        Ok(mesh)
+    }
+}
@@ -0,0 +1,208 @@
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::textures::read_faces;
+use super::Error;
+
+/// Style index marking unused lightstyle slots of a face
+const NO_STYLE: u8 = 255;
+
+/// `ColorRGBExp32`: lightmap sample with a shared exponent
+#[derive(Debug, Clone, Copy)]
+pub struct ColorRGBExp32 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub exponent: i8,
+}
+
+impl ColorRGBExp32 {
+    /// Returns linear color, where 1.0 is full brightness
+    pub fn to_linear(&self) -> [f32; 3] {
+        let scale = 2f32.powi(self.exponent as i32) / 255.0;
+        [
+            self.r as f32 * scale,
+            self.g as f32 * scale,
+            self.b as f32 * scale,
+        ]
+    }
+
+    /// Returns gamma corrected 8-bit color, clamping overbright values
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let [r, g, b] = self.to_linear();
+        let encode = |c: f32| (c.max(0.0).powf(1.0 / 2.2).min(1.0) * 255.0).round() as u8;
+        [encode(r), encode(g), encode(b)]
+    }
+}
+
+/// Lightmap of a single face for the first lightstyle
+#[derive(Debug, Clone)]
+pub struct FaceLightmap {
+    /// Index of the face in the faces lump
+    pub face: usize,
+    pub width: u32,
+    pub height: u32,
+    pub samples: Vec<ColorRGBExp32>,
+}
+
+impl FaceLightmap {
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            Rgb(self.samples[(y * self.width + x) as usize].to_rgb8())
+        })
+    }
+}
+
+/// Position of a face lightmap in an atlas
+#[derive(Debug, Clone, Serialize)]
+pub struct AtlasPlacement {
+    pub face: usize,
+    pub atlas: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Packs lightmaps into square atlases of given size, row by row from the tallest.
+///
+/// Lightmaps larger than the atlas are skipped
+pub fn pack_atlases(lightmaps: &[FaceLightmap], size: u32) -> (Vec<RgbImage>, Vec<AtlasPlacement>) {
+    let mut order: Vec<&FaceLightmap> = lightmaps
+        .iter()
+        .filter(|l| l.width <= size && l.height <= size)
+        .collect();
+    order.sort_by(|a, b| b.height.cmp(&a.height).then(b.width.cmp(&a.width)));
+
+    let mut atlases = vec![];
+    let mut placements = vec![];
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+    for lightmap in order {
+        if x + lightmap.width > size {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        if atlases.is_empty() || y + lightmap.height > size {
+            atlases.push(RgbImage::new(size, size));
+            x = 0;
+            y = 0;
+            row_height = 0;
+        }
+
+        let atlas = atlases.len() - 1;
+        image::imageops::replace(
+            &mut atlases[atlas],
+            &lightmap.to_image(),
+            x as i64,
+            y as i64,
+        );
+        placements.push(AtlasPlacement {
+            face: lightmap.face,
+            atlas,
+            x,
+            y,
+            width: lightmap.width,
+            height: lightmap.height,
+        });
+
+        x += lightmap.width;
+        row_height = row_height.max(lightmap.height);
+    }
+
+    (atlases, placements)
+}
+
+impl BufferedBSP {
+    /// Decodes lightmaps of faces that have one. Only the first lightstyle is
+    /// included, and for bumpmapped faces only the flat lightmap, which comes
+    /// before the three bump lightmaps
+    pub fn face_lightmaps(&self, hdr: bool) -> Result<Vec<FaceLightmap>, Error> {
+        self.ensure_standard_structures()?;
+        let lighting = self.lump_data(if hdr {
+            LumpIndex::LUMP_LIGHTING_HDR
+        } else {
+            LumpIndex::LUMP_LIGHTING
+        })?;
+        let hdr_faces = read_faces(&self.lump_data(LumpIndex::LUMP_FACES_HDR)?)?;
+        let faces = if hdr && !hdr_faces.is_empty() {
+            hdr_faces
+        } else {
+            self.faces()?
+        };
+
+        let mut lightmaps = vec![];
+        for (i, face) in faces.iter().enumerate() {
+            if face.light_ofs < 0 || face.styles[0] == NO_STYLE {
+                continue;
+            }
+
+            let [width, height] = face.lightmap_texture_size_in_luxels;
+            if width < 0 || height < 0 {
+                continue;
+            }
+            let (width, height) = (width as u32 + 1, height as u32 + 1);
+            let start = face.light_ofs as usize;
+            let end = (width as usize)
+                .checked_mul(height as usize)
+                .and_then(|samples| samples.checked_mul(4))
+                .and_then(|len| len.checked_add(start));
+            let data = match end.and_then(|end| lighting.get(start..end)) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            lightmaps.push(FaceLightmap {
+                face: i,
+                width,
+                height,
+                samples: data
+                    .chunks(4)
+                    .map(|c| ColorRGBExp32 {
+                        r: c[0],
+                        g: c[1],
+                        b: c[2],
+                        exponent: c[3] as i8,
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(lightmaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::buffered_bsp::tests::test_bsp;
+    use super::super::textures::FACE_LEN;
+    use super::*;
+
+    fn face(light_ofs: i32, size: [i32; 2]) -> Vec<u8> {
+        let mut face = vec![0; FACE_LEN];
+        face[16..20].copy_from_slice(&[0, NO_STYLE, NO_STYLE, NO_STYLE]);
+        face[20..24].copy_from_slice(&light_ofs.to_le_bytes());
+        face[36..40].copy_from_slice(&size[0].to_le_bytes());
+        face[40..44].copy_from_slice(&size[1].to_le_bytes());
+        face
+    }
+
+    #[test]
+    fn skips_invalid_lightmap_sizes() {
+        let mut faces = face(0, [1, 1]);
+        faces.extend(face(0, [-1, 0]));
+        faces.extend(face(0, [0, -5]));
+        faces.extend(face(4, [i32::MAX, i32::MAX]));
+
+        let mut bsp = test_bsp(&[], 0);
+        bsp.replace_lump(LumpIndex::LUMP_FACES, faces);
+        bsp.replace_lump(LumpIndex::LUMP_LIGHTING, [255, 255, 255, 0].repeat(4));
+
+        let lightmaps = bsp.face_lightmaps(false).unwrap();
+        assert_eq!(lightmaps.len(), 1);
+        assert_eq!((lightmaps[0].width, lightmaps[0].height), (2, 2));
+        assert_eq!(lightmaps[0].samples[3].to_rgb8(), [255, 255, 255]);
+    }
+}
@@ -0,0 +1,141 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::{lzma, Error};
+
+/// Length of `lumpfileheader_t`
+pub const LUMP_FILE_HEADER_LEN: u32 = 20;
+
+/// `lumpfileheader_t`
+#[derive(Debug, Clone)]
+pub struct LumpFileHeader {
+    /// Offset of lump data from the start of the file
+    pub lump_offset: u32,
+    pub lump_index: LumpIndex,
+    pub lump_version: u32,
+    pub lump_length: u32,
+    /// Lump files are only loaded for maps with the same revision
+    pub map_revision: u32,
+}
+
+/// Contents of a .lmp file, which overrides a single lump of a map.
+///
+/// The engine loads `maps/<map>_l_<n>.lmp` files (n counting up from 0) next to the map
+#[derive(Debug, Clone)]
+pub struct LumpFile {
+    pub header: LumpFileHeader,
+    pub data: Vec<u8>,
+}
+
+impl LumpFile {
+    pub fn new(
+        lump_index: LumpIndex,
+        lump_version: u32,
+        map_revision: u32,
+        data: Vec<u8>,
+    ) -> LumpFile {
+        LumpFile {
+            header: LumpFileHeader {
+                lump_offset: LUMP_FILE_HEADER_LEN,
+                lump_index,
+                lump_version,
+                lump_length: data.len() as u32,
+                map_revision,
+            },
+            data,
+        }
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<LumpFile, Error> {
+        let lump_offset = reader.read_u32::<LittleEndian>()?;
+        let id = reader.read_u32::<LittleEndian>()?;
+        let lump_index = LumpIndex::from_repr(id as usize).ok_or(Error::InvalidLumpIndex(id))?;
+        let header = LumpFileHeader {
+            lump_offset,
+            lump_index,
+            lump_version: reader.read_u32::<LittleEndian>()?,
+            lump_length: reader.read_u32::<LittleEndian>()?,
+            map_revision: reader.read_u32::<LittleEndian>()?,
+        };
+
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest)?;
+        let start = (header.lump_offset.saturating_sub(LUMP_FILE_HEADER_LEN)) as usize;
+        let data = rest
+            .get(start..start + header.lump_length as usize)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "lump file is shorter than its header says",
+                )
+            })?
+            .to_vec();
+
+        Ok(LumpFile { header, data })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_u32::<LittleEndian>(LUMP_FILE_HEADER_LEN)?;
+        writer.write_u32::<LittleEndian>(self.header.lump_index as u32)?;
+        writer.write_u32::<LittleEndian>(self.header.lump_version)?;
+        writer.write_u32::<LittleEndian>(self.data.len() as u32)?;
+        writer.write_u32::<LittleEndian>(self.header.map_revision)?;
+        writer.write_all(&self.data)
+    }
+}
+
+impl BufferedBSP {
+    /// Creates a lump file with the (decompressed) contents of given lump
+    pub fn lump_file(&self, lump_index: LumpIndex) -> Result<LumpFile, Error> {
+        Ok(LumpFile::new(
+            lump_index,
+            self.header.lumps[lump_index as usize].version,
+            self.header.map_revision,
+            self.lump_data(lump_index)?.into_owned(),
+        ))
+    }
+
+    /// Replaces a lump with the contents of a lump file made for this map revision
+    pub fn apply_lump_file(&mut self, lump_file: &LumpFile) -> Result<(), Error> {
+        let header = &lump_file.header;
+        if header.map_revision != self.header.map_revision {
+            return Err(Error::MapRevisionMismatch(
+                header.map_revision,
+                self.header.map_revision,
+            ));
+        }
+        // game lump directory has offsets relative to the bsp file, so it can't be moved as is
+        if header.lump_index == LumpIndex::LUMP_GAME_LUMP {
+            return Err(Error::UnsupportedLump(header.lump_index));
+        }
+
+        let data = if lzma::is_compressed(&lump_file.data) {
+            lzma::decompress(&lump_file.data)?
+        } else {
+            lump_file.data.clone()
+        };
+        self.replace_lump_data(header.lump_index, data)?;
+        self.header.lumps[header.lump_index as usize].version = header.lump_version;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_lump_file() {
+        let lump_file = LumpFile::new(LumpIndex::LUMP_ENTITIES, 0, 42, b"{\n}\n\0".to_vec());
+        let mut bytes = vec![];
+        lump_file.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), LUMP_FILE_HEADER_LEN as usize + 5);
+
+        let read = LumpFile::read(&mut &bytes[..]).unwrap();
+        assert_eq!(read.header.lump_index, LumpIndex::LUMP_ENTITIES);
+        assert_eq!(read.header.map_revision, 42);
+        assert_eq!(read.data, lump_file.data);
+    }
+}
@@ -1,6 +1,8 @@
+use serde::Serialize;
 use strum::{EnumIter, FromRepr};
 
-#[derive(Clone, Copy, FromRepr, Debug, PartialEq, EnumIter)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, FromRepr, Debug, PartialEq, EnumIter, Serialize)]
 pub enum LumpIndex {
     LUMP_ENTITIES = 0,
     LUMP_PLANES = 1,
@@ -13,6 +15,7 @@ pub enum LumpIndex {
     LUMP_LIGHTING = 8,
     LUMP_OCCLUSION = 9,
     LUMP_LEAFS = 10,
+    LUMP_FACEIDS = 11,
     LUMP_EDGES = 12,
     LUMP_SURFEDGES = 13,
     LUMP_MODELS = 14,
@@ -29,6 +32,7 @@ pub enum LumpIndex {
     LUMP_CLUSTERPORTALS = 25,
     LUMP_DISPINFO = 26,
     LUMP_ORIGINALFACES = 27,
+    LUMP_PHYSDISP = 28,
     LUMP_PHYSCOLLIDE = 29,
     LUMP_VERTNORMALS = 30,
     LUMP_VERTNORMALINDICES = 31,
@@ -49,4 +53,38 @@ pub enum LumpIndex {
     LUMP_LEAFMINDISTTOWATER = 46,
     LUMP_FACE_MACRO_TEXTURE_INFO = 47,
     LUMP_DISP_TRIS = 48,
+    LUMP_PHYSCOLLIDESURFACE = 49,
+    LUMP_WATEROVERLAYS = 50,
+    LUMP_LEAF_AMBIENT_INDEX_HDR = 51,
+    LUMP_LEAF_AMBIENT_INDEX = 52,
+    LUMP_LIGHTING_HDR = 53,
+    LUMP_WORLDLIGHTS_HDR = 54,
+    LUMP_LEAF_AMBIENT_LIGHTING_HDR = 55,
+    LUMP_LEAF_AMBIENT_LIGHTING = 56,
+    LUMP_XZIPPAKFILE = 57,
+    LUMP_FACES_HDR = 58,
+    LUMP_MAP_FLAGS = 59,
+    LUMP_OVERLAY_FADES = 60,
+    LUMP_OVERLAY_SYSTEM_LEVELS = 61,
+    LUMP_PHYSLEVEL = 62,
+    LUMP_DISP_MULTIBLEND = 63,
+}
+
+impl std::str::FromStr for LumpIndex {
+    type Err = String;
+
+    /// Parses lump index from its number or name, e.g. `0`, `LUMP_ENTITIES` or `entities`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use strum::IntoEnumIterator;
+
+        if let Ok(index) = s.parse::<usize>() {
+            return LumpIndex::from_repr(index).ok_or_else(|| format!("no lump with index {}", index));
+        }
+
+        let name = s.to_uppercase();
+        let name = name.strip_prefix("LUMP_").unwrap_or(&name);
+        LumpIndex::iter()
+            .find(|l| format!("{:?}", l)["LUMP_".len()..] == *name)
+            .ok_or_else(|| format!("unknown lump {}", s))
+    }
 }
@@ -0,0 +1,205 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Cow;
+use std::io::{Cursor, Read};
+use xz2::read::{XzDecoder, XzEncoder};
+use xz2::stream::{LzmaOptions, Stream};
+
+use super::buffered_bsp::BufferedBSP;
+use super::game_lump::{GameLump, GAMELUMP_COMPRESSED};
+use super::lump_indices::LumpIndex;
+use super::Error;
+use strum::IntoEnumIterator;
+
+/// Magic that starts LZMA-compressed lumps
+pub const LZMA_ID: &[u8; 4] = b"LZMA";
+/// Length of `lzma_header_t`: id, actual size, lzma size and 5 bytes of properties
+pub const LZMA_HEADER_LEN: usize = 17;
+/// Length of the header the .lzma ("LZMA alone") format uses: properties and u64 size
+const LZMA_ALONE_HEADER_LEN: usize = 13;
+
+#[derive(Debug, Clone)]
+pub struct LzmaHeader {
+    pub actual_size: u32,
+    pub lzma_size: u32,
+    pub properties: [u8; 5],
+}
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(LZMA_ID)
+}
+
+pub fn read_header(data: &[u8]) -> Result<LzmaHeader, Error> {
+    if !is_compressed(data) {
+        return Err(Error::InvalidLzmaHeader);
+    }
+
+    let mut reader = Cursor::new(&data[4..]);
+    let actual_size = reader.read_u32::<LittleEndian>()?;
+    let lzma_size = reader.read_u32::<LittleEndian>()?;
+    let mut properties = [0; 5];
+    reader.read_exact(&mut properties)?;
+
+    Ok(LzmaHeader {
+        actual_size,
+        lzma_size,
+        properties,
+    })
+}
+
+/// Decompresses data starting with a Source LZMA header
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let header = read_header(data)?;
+    let payload = data
+        .get(LZMA_HEADER_LEN..LZMA_HEADER_LEN + header.lzma_size as usize)
+        .ok_or(Error::InvalidLzmaHeader)?;
+
+    // liblzma doesn't know Source's header, but reads the equivalent .lzma one
+    let mut alone = Vec::with_capacity(LZMA_ALONE_HEADER_LEN + payload.len());
+    alone.extend_from_slice(&header.properties);
+    alone.write_u64::<LittleEndian>(header.actual_size as u64)?;
+    alone.extend_from_slice(payload);
+
+    let stream = Stream::new_lzma_decoder(u64::MAX)?;
+    let mut out = Vec::with_capacity(header.actual_size as usize);
+    XzDecoder::new_stream(&alone[..], stream).read_to_end(&mut out)?;
+
+    if out.len() != header.actual_size as usize {
+        return Err(Error::InvalidLzmaHeader);
+    }
+    Ok(out)
+}
+
+/// Compresses data and prefixes it with a Source LZMA header
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let stream = Stream::new_lzma_encoder(&LzmaOptions::new_preset(6)?)?;
+    let mut alone = Vec::new();
+    XzEncoder::new_stream(data, stream).read_to_end(&mut alone)?;
+
+    let payload = &alone[LZMA_ALONE_HEADER_LEN..];
+    let mut out = Vec::with_capacity(LZMA_HEADER_LEN + payload.len());
+    out.extend_from_slice(LZMA_ID);
+    out.write_u32::<LittleEndian>(data.len() as u32)?;
+    out.write_u32::<LittleEndian>(payload.len() as u32)?;
+    out.extend_from_slice(&alone[..5]);
+    out.extend_from_slice(payload);
+    Ok(out)
+}
+
+/// Lumps that are never compressed as a whole: the pakfile is read directly by
+/// the engine and game lumps are compressed individually
+fn is_compressible(lump_index: LumpIndex) -> bool {
+    lump_index != LumpIndex::LUMP_PAKFILE && lump_index != LumpIndex::LUMP_GAME_LUMP
+}
+
+impl BufferedBSP {
+    pub fn is_lump_compressed(&self, lump_index: LumpIndex) -> bool {
+        matches!(self.lump_slice(lump_index), Ok(slice) if is_compressed(slice))
+    }
+
+    /// Returns lump contents, decompressing them if needed
+    pub fn lump_data(&self, lump_index: LumpIndex) -> Result<Cow<'_, [u8]>, Error> {
+        let slice = self.lump_slice(lump_index)?;
+        if is_compressed(slice) {
+            Ok(Cow::Owned(decompress(slice)?))
+        } else {
+            Ok(Cow::Borrowed(slice))
+        }
+    }
+
+    /// Replaces lump contents, compressing new contents if the lump was compressed
+    pub fn replace_lump_data(&mut self, lump_index: LumpIndex, data: Vec<u8>) -> Result<(), Error> {
+        if self.is_lump_compressed(lump_index) {
+            let actual_size = data.len() as u32;
+            self.replace_lump(lump_index, compress(&data)?);
+            self.header.lumps[lump_index as usize].ident = actual_size.to_le_bytes();
+        } else {
+            self.replace_lump(lump_index, data);
+        }
+        Ok(())
+    }
+
+    /// Compresses all lumps (and game lumps) that get smaller by compressing
+    pub fn compress_lumps(&mut self) -> Result<(), Error> {
+        for lump_index in LumpIndex::iter().filter(|l| is_compressible(*l)) {
+            let slice = self.lump_slice(lump_index)?;
+            if slice.is_empty() || is_compressed(slice) {
+                continue;
+            }
+
+            let compressed = compress(slice)?;
+            if compressed.len() < slice.len() {
+                let actual_size = slice.len() as u32;
+                self.replace_lump(lump_index, compressed);
+                self.header.lumps[lump_index as usize].ident = actual_size.to_le_bytes();
+            }
+        }
+
+        let mut game_lumps = vec![];
+        for game_lump in self.game_lumps()? {
+            let data = self.game_lump_data(&game_lump)?;
+            let mut game_lump = game_lump;
+            game_lump.flags |= GAMELUMP_COMPRESSED;
+            game_lump.len = data.len() as u32;
+            game_lumps.push((game_lump, compress(&data)?));
+        }
+        if !game_lumps.is_empty() {
+            self.replace_game_lumps(game_lumps);
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses all compressed lumps (and game lumps)
+    pub fn decompress_lumps(&mut self) -> Result<(), Error> {
+        for lump_index in LumpIndex::iter() {
+            if !self.is_lump_compressed(lump_index) {
+                continue;
+            }
+
+            let data = decompress(self.lump_slice(lump_index)?)?;
+            self.replace_lump(lump_index, data);
+            self.header.lumps[lump_index as usize].ident = [0; 4];
+        }
+
+        let game_lumps = self.game_lumps()?;
+        if game_lumps
+            .iter()
+            .any(|g| g.flags & GAMELUMP_COMPRESSED != 0)
+        {
+            let mut decompressed = vec![];
+            for game_lump in game_lumps {
+                let data = self.game_lump_data(&game_lump)?.into_owned();
+                decompressed.push((
+                    GameLump {
+                        flags: game_lump.flags & !GAMELUMP_COMPRESSED,
+                        len: data.len() as u32,
+                        ..game_lump
+                    },
+                    data,
+                ));
+            }
+            self.replace_game_lumps(decompressed);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_lzma_lump() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+        let compressed = compress(&data).unwrap();
+
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < data.len());
+        assert_eq!(
+            read_header(&compressed).unwrap().actual_size,
+            data.len() as u32
+        );
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+}
@@ -1,17 +1,69 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{self, Error};
-use std::io::{ErrorKind, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use err_derive::Error;
+use std::io::{self, Read, Write};
 
-use self::buffered_bsp::BufferedBSP;
+pub use self::buffered_bsp::BufferedBSP;
 use self::lump_indices::LumpIndex;
 
+pub mod brushes;
 mod buffered_bsp;
+pub mod check;
 mod counting_read;
+pub mod cubemaps;
+pub mod dependencies;
+pub mod diff;
+pub mod entities;
+pub mod game_lump;
+pub mod geometry;
+pub mod lighting;
+pub mod lump_file;
 pub mod lump_indices;
+pub mod lzma;
+pub mod remap;
+pub mod strip;
+pub mod textures;
+pub mod tree;
+pub mod vis;
+pub mod vmf;
 
 pub const BSP_HEADER_LEN: u32 = 1036;
 const BSP_LUMP_COUNT: usize = 64;
 
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(display = "IO error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "Malformed entity lump: {}", _0)]
+    MalformedEntities(String),
+    #[error(display = "Pakfile error: {}", _0)]
+    Zip(#[error(source)] zip::result::ZipError),
+    #[error(display = "LZMA error: {}", _0)]
+    Lzma(#[error(source)] xz2::stream::Error),
+    #[error(display = "Lump does not have a valid LZMA header")]
+    InvalidLzmaHeader,
+    #[error(display = "Static prop model name {} is too long", _0)]
+    StaticPropNameTooLong(String),
+    #[error(display = "Header ident {:?} is not VBSP", _0)]
+    InvalidIdent([u8; 4]),
+    #[error(display = "Unsupported BSP variant: {}", _0)]
+    UnsupportedVariant(String),
+    #[error(display = "Unsupported VBSP version {}", _0)]
+    UnsupportedVersion(u32),
+    #[error(display = "Invalid lump index {}", _0)]
+    InvalidLumpIndex(u32),
+    #[error(display = "Lump file is for map revision {}, but map is revision {}", _0, _1)]
+    MapRevisionMismatch(u32, u32),
+    #[error(display = "Unsupported lump {:?}", _0)]
+    UnsupportedLump(LumpIndex),
+    #[error(
+        display = "Lump {:?} (offset {}, length {}) is outside the file",
+        _0,
+        _1,
+        _2
+    )]
+    LumpOutOfBounds(LumpIndex, u32, u32),
+}
+
 #[derive(Default)]
 pub struct Lump {
     pub off: u32,
@@ -20,61 +72,166 @@ pub struct Lump {
     pub ident: [u8; 4],
 }
 
+/// Header layout variants of VBSP files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BSPVariant {
+    Standard,
+    /// Left 4 Dead 2 stores lump version before lump offset and length
+    Left4Dead2,
+    /// Dark Messiah of Might and Magic has 4 in the upper half of the version
+    DarkMessiah,
+    /// Vindictus uses the standard v20 header, but widens nodes, faces, brush sides
+    /// and other structures. Reading those structures is not supported
+    Vindictus,
+}
+
+const DARK_MESSIAH_VERSION: u32 = 0x0004_0014;
+
+/// Lumps whose structures Vindictus widens, with their standard and Vindictus sizes
+const VINDICTUS_LUMPS: [(LumpIndex, usize, usize); 3] = [
+    (LumpIndex::LUMP_NODES, tree::NODE_LEN, 48),
+    (LumpIndex::LUMP_FACES, textures::FACE_LEN, 72),
+    (LumpIndex::LUMP_BRUSHSIDES, brushes::BRUSH_SIDE_LEN, 16),
+];
+
+/// Vindictus maps can only be told apart by their lump lengths: all of them fit
+/// the widened structures, and at least one doesn't fit the standard ones.
+/// Compressed lumps are skipped, as their length is the compressed length
+fn is_vindictus(lumps: &[Lump; BSP_LUMP_COUNT]) -> bool {
+    let lens: Vec<(usize, usize, usize)> = VINDICTUS_LUMPS
+        .iter()
+        .map(|(l, standard, vindictus)| (&lumps[*l as usize], standard, vindictus))
+        .filter(|(lump, _, _)| lump.len > 0 && lump.ident == [0; 4])
+        .map(|(lump, standard, vindictus)| (lump.len as usize, *standard, *vindictus))
+        .collect();
+    lens.iter().all(|(len, _, vindictus)| len % vindictus == 0)
+        && lens.iter().any(|(len, standard, _)| len % standard != 0)
+}
+
 pub struct BSPHeader {
     pub version: u32,
+    pub variant: BSPVariant,
     pub lumps: [Lump; BSP_LUMP_COUNT],
     pub map_revision: u32,
 }
 
-pub struct BSPReader<R> {
-    reader: R,
-    header: BSPHeader,
-}
-
-impl<R: Read> BSPReader<R> {
-    pub fn from_reader(mut reader: R) -> Result<BSPReader<R>, Box<dyn std::error::Error>> {
-        let mut ident_buf = [0; 4];
-        reader.read_exact(&mut ident_buf)?;
-        if &ident_buf != b"VBSP" {
-            eprintln!("ident_buf = {:?}", ident_buf);
-            return Err(Box::new(Error::new(
-                ErrorKind::Other,
-                "header ident is not VBSP",
-            )));
+impl BSPHeader {
+    pub fn read<R: Read>(reader: &mut R) -> Result<BSPHeader, Error> {
+        let mut ident = [0; 4];
+        reader.read_exact(&mut ident)?;
+        let unsupported = match &ident {
+            b"VBSP" => None,
+            b"PSBV" => Some("big-endian console VBSP"),
+            b"rBSP" => Some("Titanfall/Apex Legends rBSP"),
+            b"IBSP" => Some("id Tech 2/3 IBSP"),
+            [29, 0, 0, 0] | [30, 0, 0, 0] => Some("Quake/GoldSrc BSP"),
+            _ => return Err(Error::InvalidIdent(ident)),
+        };
+        if let Some(variant) = unsupported {
+            return Err(Error::UnsupportedVariant(variant.to_string()));
         }
 
         let version = reader.read_u32::<LittleEndian>()?;
+        let variant = match version {
+            17..=21 => BSPVariant::Standard,
+            DARK_MESSIAH_VERSION => BSPVariant::DarkMessiah,
+            _ => return Err(Error::UnsupportedVersion(version)),
+        };
 
-        let mut lumps: [Lump; BSP_LUMP_COUNT] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-
+        let mut raw_lumps = [[0u32; 3]; BSP_LUMP_COUNT];
+        let mut idents = [[0u8; 4]; BSP_LUMP_COUNT];
         for i in 0..BSP_LUMP_COUNT {
-            let off = reader.read_u32::<LittleEndian>()?;
-            let len = reader.read_u32::<LittleEndian>()?;
-            let vers = reader.read_u32::<LittleEndian>()?;
-
-            let mut ident_buf = [0; 4];
-            reader.read_exact(&mut ident_buf)?;
-
-            lumps[i] = Lump {
-                off: off,
-                len: len,
-                version: vers,
-                ident: ident_buf,
-            };
+            for field in &mut raw_lumps[i] {
+                *field = reader.read_u32::<LittleEndian>()?;
+            }
+            reader.read_exact(&mut idents[i])?;
         }
         let map_revision = reader.read_u32::<LittleEndian>()?;
 
-        Ok(BSPReader {
-            reader: reader,
-            header: BSPHeader {
-                version: version,
-                lumps: lumps,
-                map_revision: map_revision,
-            },
+        // Both v21 layouts exist, so look at the entity lump: its version is
+        // always small, while its offset can't point inside the header
+        let entities = raw_lumps[LumpIndex::LUMP_ENTITIES as usize];
+        let swapped = entities[0] < BSP_HEADER_LEN && entities[1] >= BSP_HEADER_LEN;
+        let variant = if version == 21 && swapped {
+            BSPVariant::Left4Dead2
+        } else {
+            variant
+        };
+
+        let lumps = std::array::from_fn(|i| {
+            let [a, b, c] = raw_lumps[i];
+            let (off, len, version) = match variant {
+                BSPVariant::Left4Dead2 => (b, c, a),
+                _ => (a, b, c),
+            };
+            Lump {
+                off,
+                len,
+                version,
+                ident: idents[i],
+            }
+        });
+        let variant = if version == 20 && is_vindictus(&lumps) {
+            BSPVariant::Vindictus
+        } else {
+            variant
+        };
+
+        Ok(BSPHeader {
+            version,
+            variant,
+            lumps,
+            map_revision,
         })
     }
 
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"VBSP")?;
+        writer.write_u32::<LittleEndian>(self.version)?;
+
+        for lump in &self.lumps {
+            let fields = match self.variant {
+                BSPVariant::Left4Dead2 => [lump.version, lump.off, lump.len],
+                _ => [lump.off, lump.len, lump.version],
+            };
+            for field in &fields {
+                writer.write_u32::<LittleEndian>(*field)?;
+            }
+            writer.write_all(&lump.ident)?;
+        }
+
+        writer.write_u32::<LittleEndian>(self.map_revision)?;
+        Ok(())
+    }
+
+    /// Name of the engine branch that uses this header's version and layout
+    pub fn engine_branch(&self) -> &'static str {
+        match (self.variant, self.version) {
+            (BSPVariant::DarkMessiah, _) => "Dark Messiah of Might and Magic",
+            (BSPVariant::Left4Dead2, _) => "Left 4 Dead 2",
+            (BSPVariant::Vindictus, _) => "Vindictus",
+            (_, 17) => "Vampire: The Masquerade - Bloodlines",
+            (_, 18) => "Half-Life 2 beta",
+            (_, 19) => "Source 2004 (Half-Life 2, Counter-Strike: Source)",
+            (_, 20) => "Source 2007 (Orange Box, Garry's Mod, Left 4 Dead)",
+            (_, 21) => "Source 2009+ (Portal 2, CS:GO)",
+            _ => "Unknown",
+        }
+    }
+}
+
+pub struct BSPReader<R> {
+    reader: R,
+    header: BSPHeader,
+}
+
+impl<R: Read> BSPReader<R> {
+    pub fn from_reader(mut reader: R) -> Result<BSPReader<R>, Box<dyn std::error::Error>> {
+        let header = BSPHeader::read(&mut reader)?;
+
+        Ok(BSPReader { reader, header })
+    }
+
     pub fn header(&self) -> &BSPHeader {
         &self.header
     }
@@ -106,3 +263,80 @@ impl<R: Read> BSPReader<R> {
         Ok(self.reader.take(lump.len.into()).into_inner())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a header with given raw lump fields, in the order they are in the file
+    fn header_bytes(version: u32, lumps: &[(LumpIndex, [u32; 3])]) -> Vec<u8> {
+        let mut buf = b"VBSP".to_vec();
+        buf.extend_from_slice(&version.to_le_bytes());
+        let mut table = vec![0; BSP_LUMP_COUNT * 16];
+        for (lump_index, fields) in lumps {
+            let entry = *lump_index as usize * 16;
+            for (i, field) in fields.iter().enumerate() {
+                table[entry + i * 4..entry + i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+            }
+        }
+        buf.extend(table);
+        buf.extend_from_slice(&7u32.to_le_bytes());
+        buf
+    }
+
+    /// Reads the header, writes it back and checks the bytes didn't change
+    fn round_trip(buf: &[u8]) -> BSPHeader {
+        let header = BSPHeader::read(&mut &buf[..]).unwrap();
+        let mut written = vec![];
+        header.write(&mut written).unwrap();
+        assert_eq!(written, buf);
+
+        let reread = BSPHeader::read(&mut &written[..]).unwrap();
+        assert_eq!(reread.variant, header.variant);
+        header
+    }
+
+    #[test]
+    fn round_trips_v21_headers() {
+        // offset, length, version
+        let buf = header_bytes(
+            21,
+            &[
+                (LumpIndex::LUMP_ENTITIES, [1036, 10, 0]),
+                (LumpIndex::LUMP_LEAFS, [1046, 32, 1]),
+            ],
+        );
+        let header = round_trip(&buf);
+        assert_eq!(header.variant, BSPVariant::Standard);
+        let leafs = &header.lumps[LumpIndex::LUMP_LEAFS as usize];
+        assert_eq!((leafs.off, leafs.len, leafs.version), (1046, 32, 1));
+
+        // version, offset, length
+        let buf = header_bytes(
+            21,
+            &[
+                (LumpIndex::LUMP_ENTITIES, [0, 1036, 10]),
+                (LumpIndex::LUMP_LEAFS, [1, 1046, 32]),
+            ],
+        );
+        let header = round_trip(&buf);
+        assert_eq!(header.variant, BSPVariant::Left4Dead2);
+        let leafs = &header.lumps[LumpIndex::LUMP_LEAFS as usize];
+        assert_eq!((leafs.off, leafs.len, leafs.version), (1046, 32, 1));
+        assert_eq!(header.map_revision, 7);
+    }
+
+    #[test]
+    fn round_trips_dark_messiah_headers() {
+        let buf = header_bytes(
+            DARK_MESSIAH_VERSION,
+            &[(LumpIndex::LUMP_ENTITIES, [1036, 10, 0])],
+        );
+        let header = round_trip(&buf);
+        assert_eq!(header.variant, BSPVariant::DarkMessiah);
+        assert_eq!(header.version, 0x0004_0014);
+        assert_eq!(header.engine_branch(), "Dark Messiah of Might and Magic");
+        let entities = &header.lumps[LumpIndex::LUMP_ENTITIES as usize];
+        assert_eq!((entities.off, entities.len), (1036, 10));
+    }
+}
@@ -0,0 +1,175 @@
+use super::buffered_bsp::BufferedBSP;
+use super::entities;
+use super::game_lump::{write_static_prop_dictionary, GAMELUMP_STATIC_PROPS};
+use super::lump_indices::LumpIndex;
+use super::Error;
+use crate::search_path::normalize_path;
+
+/// Replaces a path prefix, e.g. "oldteam" with "newteam".
+///
+/// Prefixes only match whole path components, so "oldteam" matches "oldteam/wall"
+/// but not "oldteam2/wall"
+#[derive(Debug, Clone)]
+pub struct PathRemap {
+    pub from: String,
+    pub to: String,
+}
+
+impl PathRemap {
+    pub fn new(from: &str, to: &str) -> PathRemap {
+        PathRemap {
+            from: normalize_path(from).trim_end_matches('/').to_string(),
+            to: normalize_path(to).trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Makes both paths relative to given root folder (e.g. "materials"), stripping
+    /// the folder if it was included
+    fn relative_to(&self, root: &str) -> PathRemap {
+        let strip = |path: &str| {
+            path.strip_prefix(root)
+                .and_then(|p| p.strip_prefix('/'))
+                .unwrap_or(path)
+                .to_string()
+        };
+        PathRemap {
+            from: strip(&self.from),
+            to: strip(&self.to),
+        }
+    }
+
+    /// Makes both paths start with given root folder (e.g. "models")
+    fn rooted_at(&self, root: &str) -> PathRemap {
+        let relative = self.relative_to(root);
+        PathRemap {
+            from: format!("{}/{}", root, relative.from),
+            to: format!("{}/{}", root, relative.to),
+        }
+    }
+
+    /// Returns remapped path if given path starts with `from`
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let path = normalize_path(path);
+        let rest = path.strip_prefix(&self.from)?;
+        if rest.is_empty() || rest.starts_with('/') {
+            Some(format!("{}{}", self.to, rest))
+        } else {
+            None
+        }
+    }
+}
+
+/// Applies the first matching remap to given path
+fn apply_first(remaps: &[PathRemap], path: &str) -> Option<String> {
+    remaps.iter().find_map(|remap| remap.apply(path))
+}
+
+impl BufferedBSP {
+    /// Renames materials used by brushes. Paths are relative to "materials".
+    ///
+    /// Returns the number of renamed materials
+    pub fn remap_materials(&mut self, remaps: &[PathRemap]) -> Result<usize, Error> {
+        let remaps: Vec<_> = remaps.iter().map(|r| r.relative_to("materials")).collect();
+
+        let mut count = 0;
+        let mut names = self.texdata_strings()?;
+        for name in &mut names {
+            if let Some(new_name) = apply_first(&remaps, name) {
+                *name = new_name;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut table = vec![];
+        let mut data = vec![];
+        for name in names {
+            table.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+        }
+        self.replace_lump_data(LumpIndex::LUMP_TEXDATA_STRING_DATA, data)?;
+        self.replace_lump_data(LumpIndex::LUMP_TEXDATA_STRING_TABLE, table)?;
+
+        Ok(count)
+    }
+
+    /// Renames models used by static props. Paths are relative to the game folder,
+    /// but "models/" may be left out.
+    ///
+    /// Returns the number of renamed models
+    pub fn remap_static_prop_models(&mut self, remaps: &[PathRemap]) -> Result<usize, Error> {
+        let remaps: Vec<_> = remaps.iter().map(|r| r.rooted_at("models")).collect();
+
+        let mut count = 0;
+        let mut names = self.static_prop_models()?;
+        for name in &mut names {
+            if let Some(new_name) = apply_first(&remaps, name) {
+                *name = new_name;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let game_lump = self
+            .game_lumps()?
+            .into_iter()
+            .find(|g| g.id == GAMELUMP_STATIC_PROPS);
+        if let Some(game_lump) = game_lump {
+            let mut data = self.game_lump_data(&game_lump)?.into_owned();
+            write_static_prop_dictionary(&mut data, &names)?;
+            self.replace_game_lump_data(GAMELUMP_STATIC_PROPS, data)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Renames models in entity "model" keys. Paths are relative to the game folder,
+    /// but "models/" may be left out.
+    ///
+    /// Returns the number of renamed keys
+    pub fn remap_entity_models(&mut self, remaps: &[PathRemap]) -> Result<usize, Error> {
+        let remaps: Vec<_> = remaps.iter().map(|r| r.rooted_at("models")).collect();
+
+        let mut count = 0;
+        let mut entities = entities::parse(&self.lump_data(LumpIndex::LUMP_ENTITIES)?)?;
+        for entity in &mut entities {
+            for (key, value) in &mut entity.properties {
+                if !key.eq_ignore_ascii_case("model") {
+                    continue;
+                }
+                if let Some(new_value) = apply_first(&remaps, value) {
+                    *value = new_value;
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            return Ok(0);
+        }
+
+        self.replace_lump_data(LumpIndex::LUMP_ENTITIES, entities::serialize(&entities))?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaps_whole_path_components() {
+        let remap = PathRemap::new("materials/OldTeam/", "materials/newteam");
+        let remap = remap.relative_to("materials");
+
+        assert_eq!(
+            remap.apply("OLDTEAM/Wall01"),
+            Some("newteam/wall01".to_string())
+        );
+        assert_eq!(remap.apply("oldteam2/wall01"), None);
+    }
+}
@@ -0,0 +1,185 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use globset::GlobSet;
+use std::collections::BTreeSet;
+use std::io::{self, Cursor};
+use zip::write::ZipWriter;
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::{entities, Error};
+
+/// Length of `dphysmodel_t`
+const PHYS_MODEL_LEN: usize = 16;
+
+/// Brush entities that never collide, so their collision models are unused
+pub const NON_SOLID_CLASSES: &[&str] = &[
+    "func_illusionary",
+    "func_dustcloud",
+    "func_dustmotes",
+    "func_precipitation",
+    "func_smokevolume",
+];
+
+/// Lumps containing LDR lighting, and their HDR counterparts
+pub const LDR_LIGHTING_LUMPS: &[LumpIndex] = &[
+    LumpIndex::LUMP_LIGHTING,
+    LumpIndex::LUMP_WORLDLIGHTS,
+    LumpIndex::LUMP_LEAF_AMBIENT_INDEX,
+    LumpIndex::LUMP_LEAF_AMBIENT_LIGHTING,
+];
+pub const HDR_LIGHTING_LUMPS: &[LumpIndex] = &[
+    LumpIndex::LUMP_LIGHTING_HDR,
+    LumpIndex::LUMP_WORLDLIGHTS_HDR,
+    LumpIndex::LUMP_LEAF_AMBIENT_INDEX_HDR,
+    LumpIndex::LUMP_LEAF_AMBIENT_LIGHTING_HDR,
+];
+
+/// Removes collision models of given brush models from physcollide lump contents.
+///
+/// The lump is a list of `dphysmodel_t` headers, each followed by collision and
+/// key data, and terminated by an entry with model index -1.
+///
+/// Returns the new contents and the number of removed collision models
+pub fn strip_physcollide(
+    lump: &[u8],
+    models: &BTreeSet<usize>,
+) -> Result<(Vec<u8>, usize), io::Error> {
+    let mut reader = Cursor::new(lump);
+    let mut stripped = vec![];
+    let mut removed = 0;
+
+    loop {
+        let start = reader.position() as usize;
+        if start >= lump.len() {
+            break;
+        }
+
+        let model_index = reader.read_i32::<LittleEndian>()?;
+        if model_index < 0 {
+            stripped.extend_from_slice(&lump[start..]);
+            break;
+        }
+        let data_size = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+        let key_data_size = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+
+        let end = start + PHYS_MODEL_LEN + data_size + key_data_size;
+        let entry = lump
+            .get(start..end)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if models.contains(&(model_index as usize)) {
+            removed += 1;
+        } else {
+            stripped.extend_from_slice(entry);
+        }
+        reader.set_position(end as u64);
+    }
+
+    Ok((stripped, removed))
+}
+
+impl BufferedBSP {
+    /// Empties given lump
+    pub fn clear_lump(&mut self, lump_index: LumpIndex) {
+        self.replace_lump(lump_index, vec![]);
+        // compressed lumps store their uncompressed size here
+        self.header.lumps[lump_index as usize].ident = [0; 4];
+    }
+
+    /// Removes pakfile entries with paths matching given globs, returning removed paths
+    pub fn remove_packed_files(&mut self, globs: &GlobSet) -> Result<Vec<String>, Error> {
+        let mut archive = match self.pakfile()? {
+            Some(archive) => archive,
+            None => return Ok(vec![]),
+        };
+
+        let mut removed = vec![];
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            if globs.is_match(file.name()) {
+                removed.push(file.name().to_string());
+            } else {
+                // copied as is, so compression (if any) is kept
+                writer.raw_copy_file(file)?;
+            }
+        }
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+
+        let pakfile = writer.finish()?.into_inner();
+        drop(archive);
+        self.replace_lump_data(LumpIndex::LUMP_PAKFILE, pakfile)?;
+        Ok(removed)
+    }
+
+    /// Returns indices of brush models used only by entities in `NON_SOLID_CLASSES`
+    pub fn non_solid_models(&self) -> Result<BTreeSet<usize>, Error> {
+        let mut non_solid = BTreeSet::new();
+        let mut solid = BTreeSet::new();
+        for entity in entities::parse(&self.lump_data(LumpIndex::LUMP_ENTITIES)?)? {
+            let model = match entity
+                .get("model")
+                .and_then(|m| m.strip_prefix('*'))
+                .and_then(|m| m.parse::<usize>().ok())
+            {
+                Some(model) => model,
+                None => continue,
+            };
+
+            let class = entity.classname().unwrap_or_default();
+            if NON_SOLID_CLASSES
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(class))
+            {
+                non_solid.insert(model);
+            } else {
+                solid.insert(model);
+            }
+        }
+
+        Ok(non_solid.difference(&solid).copied().collect())
+    }
+
+    /// Removes collision models of non-solid brush entities, returning how many were removed
+    pub fn strip_non_solid_physcollide(&mut self) -> Result<usize, Error> {
+        let models = self.non_solid_models()?;
+        if models.is_empty() {
+            return Ok(0);
+        }
+
+        let lump = self.lump_data(LumpIndex::LUMP_PHYSCOLLIDE)?;
+        let (stripped, removed) = strip_physcollide(&lump, &models)?;
+        self.replace_lump_data(LumpIndex::LUMP_PHYSCOLLIDE, stripped)?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phys_model(index: i32, data: &[u8]) -> Vec<u8> {
+        let mut entry = vec![];
+        for v in [index, data.len() as i32, 0, 1] {
+            entry.extend_from_slice(&v.to_le_bytes());
+        }
+        entry.extend_from_slice(data);
+        entry
+    }
+
+    #[test]
+    fn strips_physcollide_models() {
+        let terminator = phys_model(-1, &[]);
+        let lump = [
+            phys_model(0, &[1, 2]),
+            phys_model(3, &[4]),
+            terminator.clone(),
+        ]
+        .concat();
+
+        let (stripped, removed) = strip_physcollide(&lump, &std::iter::once(3).collect()).unwrap();
+        assert_eq!(stripped, [phys_model(0, &[1, 2]), terminator].concat());
+        assert_eq!(removed, 1);
+    }
+}
@@ -0,0 +1,264 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Cursor};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::Error;
+
+pub(crate) const TEXDATA_LEN: usize = 32;
+pub(crate) const TEXINFO_LEN: usize = 72;
+pub(crate) const FACE_LEN: usize = 56;
+
+pub const SURF_LIGHT: i32 = 0x1;
+pub const SURF_SKY2D: i32 = 0x2;
+pub const SURF_SKY: i32 = 0x4;
+pub const SURF_WARP: i32 = 0x8;
+pub const SURF_TRANS: i32 = 0x10;
+pub const SURF_NOPORTAL: i32 = 0x20;
+pub const SURF_TRIGGER: i32 = 0x40;
+pub const SURF_NODRAW: i32 = 0x80;
+pub const SURF_HINT: i32 = 0x100;
+pub const SURF_SKIP: i32 = 0x200;
+pub const SURF_NOLIGHT: i32 = 0x400;
+pub const SURF_BUMPLIGHT: i32 = 0x800;
+
+/// `dtexdata_t`: a material along with properties of its base texture
+#[derive(Debug, Clone)]
+pub struct TexData {
+    pub reflectivity: [f32; 3],
+    /// Index to `LUMP_TEXDATA_STRING_TABLE`
+    pub name_string_table_id: i32,
+    pub width: i32,
+    pub height: i32,
+    pub view_width: i32,
+    pub view_height: i32,
+}
+
+/// `texinfo_t`: texture projection of a face
+#[derive(Debug, Clone)]
+pub struct TexInfo {
+    pub texture_vecs: [[f32; 4]; 2],
+    pub lightmap_vecs: [[f32; 4]; 2],
+    /// `SURF_*` flags
+    pub flags: i32,
+    /// Index to `LUMP_TEXDATA`, or -1 if the texinfo has no material
+    pub texdata: i32,
+}
+
+/// `dface_t`
+#[derive(Debug, Clone)]
+pub struct Face {
+    pub plane_num: u16,
+    pub side: u8,
+    pub on_node: u8,
+    pub first_edge: i32,
+    pub num_edges: i16,
+    /// Index to `LUMP_TEXINFO`, or -1 for faces without a texinfo
+    pub texinfo: i16,
+    pub disp_info: i16,
+    pub surface_fog_volume_id: i16,
+    pub styles: [u8; 4],
+    pub light_ofs: i32,
+    pub area: f32,
+    pub lightmap_texture_mins_in_luxels: [i32; 2],
+    pub lightmap_texture_size_in_luxels: [i32; 2],
+    pub orig_face: i32,
+    pub num_prims: u16,
+    pub first_prim_id: u16,
+    pub smoothing_groups: u32,
+}
+
+/// Material used by the map, along with how it is used
+#[derive(Debug, Clone)]
+pub struct MapMaterial {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub reflectivity: [f32; 3],
+    /// Number of faces using this material
+    pub face_count: usize,
+    /// `SURF_*` flags of all texinfos using this material combined
+    pub flags: i32,
+}
+
+impl MapMaterial {
+    /// Whether the material is one of the tool textures (e.g. tools/toolsclip)
+    pub fn is_tool(&self) -> bool {
+        self.name.to_lowercase().starts_with("tools/")
+    }
+
+    pub fn is_nodraw(&self) -> bool {
+        self.flags & SURF_NODRAW != 0 || self.name.eq_ignore_ascii_case("tools/toolsnodraw")
+    }
+}
+
+pub(crate) fn read_f32s<const N: usize>(reader: &mut Cursor<&[u8]>) -> Result<[f32; N], io::Error> {
+    let mut values = [0.0; N];
+    for value in &mut values {
+        *value = reader.read_f32::<LittleEndian>()?;
+    }
+    Ok(values)
+}
+
+pub fn read_texdata(lump: &[u8]) -> Result<Vec<TexData>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut texdata = vec![];
+    for _ in 0..lump.len() / TEXDATA_LEN {
+        texdata.push(TexData {
+            reflectivity: read_f32s(&mut reader)?,
+            name_string_table_id: reader.read_i32::<LittleEndian>()?,
+            width: reader.read_i32::<LittleEndian>()?,
+            height: reader.read_i32::<LittleEndian>()?,
+            view_width: reader.read_i32::<LittleEndian>()?,
+            view_height: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(texdata)
+}
+
+pub fn read_texinfo(lump: &[u8]) -> Result<Vec<TexInfo>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut texinfo = vec![];
+    for _ in 0..lump.len() / TEXINFO_LEN {
+        texinfo.push(TexInfo {
+            texture_vecs: [read_f32s(&mut reader)?, read_f32s(&mut reader)?],
+            lightmap_vecs: [read_f32s(&mut reader)?, read_f32s(&mut reader)?],
+            flags: reader.read_i32::<LittleEndian>()?,
+            texdata: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(texinfo)
+}
+
+pub fn read_faces(lump: &[u8]) -> Result<Vec<Face>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut faces = vec![];
+    for _ in 0..lump.len() / FACE_LEN {
+        faces.push(Face {
+            plane_num: reader.read_u16::<LittleEndian>()?,
+            side: reader.read_u8()?,
+            on_node: reader.read_u8()?,
+            first_edge: reader.read_i32::<LittleEndian>()?,
+            num_edges: reader.read_i16::<LittleEndian>()?,
+            texinfo: reader.read_i16::<LittleEndian>()?,
+            disp_info: reader.read_i16::<LittleEndian>()?,
+            surface_fog_volume_id: reader.read_i16::<LittleEndian>()?,
+            styles: [
+                reader.read_u8()?,
+                reader.read_u8()?,
+                reader.read_u8()?,
+                reader.read_u8()?,
+            ],
+            light_ofs: reader.read_i32::<LittleEndian>()?,
+            area: reader.read_f32::<LittleEndian>()?,
+            lightmap_texture_mins_in_luxels: [
+                reader.read_i32::<LittleEndian>()?,
+                reader.read_i32::<LittleEndian>()?,
+            ],
+            lightmap_texture_size_in_luxels: [
+                reader.read_i32::<LittleEndian>()?,
+                reader.read_i32::<LittleEndian>()?,
+            ],
+            orig_face: reader.read_i32::<LittleEndian>()?,
+            num_prims: reader.read_u16::<LittleEndian>()?,
+            first_prim_id: reader.read_u16::<LittleEndian>()?,
+            smoothing_groups: reader.read_u32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(faces)
+}
+
+/// Reads null-terminated strings from string data at the offsets given in string table
+pub fn read_strings(table: &[u8], data: &[u8]) -> Result<Vec<String>, io::Error> {
+    let mut reader = Cursor::new(table);
+
+    let mut strings = vec![];
+    for _ in 0..table.len() / 4 {
+        let off = reader.read_i32::<LittleEndian>()? as usize;
+        let bytes = data.get(off..).unwrap_or_default();
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        strings.push(String::from_utf8_lossy(&bytes[..end]).into_owned());
+    }
+
+    Ok(strings)
+}
+
+impl BufferedBSP {
+    pub fn texdata(&self) -> Result<Vec<TexData>, Error> {
+        Ok(read_texdata(&self.lump_data(LumpIndex::LUMP_TEXDATA)?)?)
+    }
+
+    pub fn texinfo(&self) -> Result<Vec<TexInfo>, Error> {
+        Ok(read_texinfo(&self.lump_data(LumpIndex::LUMP_TEXINFO)?)?)
+    }
+
+    /// Returns LDR faces, or HDR faces if the map was compiled with HDR only
+    pub fn faces(&self) -> Result<Vec<Face>, Error> {
+        self.ensure_standard_structures()?;
+        let ldr = self.lump_data(LumpIndex::LUMP_FACES)?;
+        if ldr.is_empty() {
+            Ok(read_faces(&self.lump_data(LumpIndex::LUMP_FACES_HDR)?)?)
+        } else {
+            Ok(read_faces(&ldr)?)
+        }
+    }
+
+    /// Returns material names, indexed by `TexData::name_string_table_id`
+    pub fn texdata_strings(&self) -> Result<Vec<String>, Error> {
+        Ok(read_strings(
+            &self.lump_data(LumpIndex::LUMP_TEXDATA_STRING_TABLE)?,
+            &self.lump_data(LumpIndex::LUMP_TEXDATA_STRING_DATA)?,
+        )?)
+    }
+
+    /// Lists materials used by the map's texdata, along with the number of faces
+    /// using each of them
+    pub fn materials(&self) -> Result<Vec<MapMaterial>, Error> {
+        let strings = self.texdata_strings()?;
+        let mut materials: Vec<MapMaterial> = self
+            .texdata()?
+            .into_iter()
+            .map(|texdata| MapMaterial {
+                name: usize::try_from(texdata.name_string_table_id)
+                    .ok()
+                    .and_then(|i| strings.get(i))
+                    .cloned()
+                    .unwrap_or_default(),
+                width: texdata.width,
+                height: texdata.height,
+                reflectivity: texdata.reflectivity,
+                face_count: 0,
+                flags: 0,
+            })
+            .collect();
+
+        let texinfo = self.texinfo()?;
+        for info in &texinfo {
+            if let Some(material) = usize::try_from(info.texdata)
+                .ok()
+                .and_then(|i| materials.get_mut(i))
+            {
+                material.flags |= info.flags;
+            }
+        }
+
+        for face in self.faces()? {
+            let texdata = usize::try_from(face.texinfo)
+                .ok()
+                .and_then(|i| texinfo.get(i))
+                .and_then(|info| usize::try_from(info.texdata).ok());
+            if let Some(material) = texdata.and_then(|i| materials.get_mut(i)) {
+                material.face_count += 1;
+            }
+        }
+
+        Ok(materials)
+    }
+}
@@ -0,0 +1,269 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::BTreeSet;
+use std::io::{self, Cursor, Seek, SeekFrom};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::textures::read_f32s;
+use super::Error;
+
+pub(crate) const PLANE_LEN: usize = 20;
+pub(crate) const NODE_LEN: usize = 32;
+pub(crate) const MODEL_LEN: usize = 48;
+/// Length of `dleaf_t` in leaf lump version 1
+pub(crate) const LEAF_LEN: usize = 32;
+/// Length of `dleaf_t` in leaf lump version 0, which includes ambient lighting
+pub(crate) const LEAF_LEN_V0: usize = 56;
+
+/// `dplane_t`
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub normal: [f32; 3],
+    pub dist: f32,
+    pub axis_type: i32,
+}
+
+/// `dnode_t`
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub plane_num: i32,
+    /// Child node indices. Negative values are leafs, as `-(leaf + 1)`
+    pub children: [i32; 2],
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_face: u16,
+    pub num_faces: u16,
+    pub area: i16,
+}
+
+/// `dleaf_t`
+#[derive(Debug, Clone)]
+pub struct Leaf {
+    pub contents: i32,
+    /// Visibility cluster, or -1 if the leaf is outside the world
+    pub cluster: i16,
+    pub area: i16,
+    pub flags: i16,
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_leaf_face: u16,
+    pub num_leaf_faces: u16,
+    pub first_leaf_brush: u16,
+    pub num_leaf_brushes: u16,
+    pub leaf_water_data_id: i16,
+}
+
+/// `dmodel_t`: the world (model 0) or a brush entity model
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    pub origin: [f32; 3],
+    pub head_node: i32,
+    pub first_face: i32,
+    pub num_faces: i32,
+}
+
+fn read_i16s<const N: usize>(reader: &mut Cursor<&[u8]>) -> Result<[i16; N], io::Error> {
+    let mut values = [0; N];
+    for value in &mut values {
+        *value = reader.read_i16::<LittleEndian>()?;
+    }
+    Ok(values)
+}
+
+pub fn read_planes(lump: &[u8]) -> Result<Vec<Plane>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut planes = vec![];
+    for _ in 0..lump.len() / PLANE_LEN {
+        planes.push(Plane {
+            normal: read_f32s(&mut reader)?,
+            dist: reader.read_f32::<LittleEndian>()?,
+            axis_type: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(planes)
+}
+
+pub fn read_nodes(lump: &[u8]) -> Result<Vec<Node>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut nodes = vec![];
+    for _ in 0..lump.len() / NODE_LEN {
+        nodes.push(Node {
+            plane_num: reader.read_i32::<LittleEndian>()?,
+            children: [
+                reader.read_i32::<LittleEndian>()?,
+                reader.read_i32::<LittleEndian>()?,
+            ],
+            mins: read_i16s(&mut reader)?,
+            maxs: read_i16s(&mut reader)?,
+            first_face: reader.read_u16::<LittleEndian>()?,
+            num_faces: reader.read_u16::<LittleEndian>()?,
+            area: reader.read_i16::<LittleEndian>()?,
+        });
+        // padding
+        reader.read_i16::<LittleEndian>()?;
+    }
+
+    Ok(nodes)
+}
+
+/// Reads leafs from leaf lump of given lump version
+pub fn read_leafs(lump: &[u8], version: u32) -> Result<Vec<Leaf>, io::Error> {
+    let leaf_len = if version == 0 { LEAF_LEN_V0 } else { LEAF_LEN };
+    let mut reader = Cursor::new(lump);
+
+    let mut leafs = vec![];
+    for i in 0..lump.len() / leaf_len {
+        reader.seek(SeekFrom::Start((i * leaf_len) as u64))?;
+
+        let contents = reader.read_i32::<LittleEndian>()?;
+        let cluster = reader.read_i16::<LittleEndian>()?;
+        // area:9 and flags:7 bitfields
+        let area_flags = reader.read_i16::<LittleEndian>()?;
+        leafs.push(Leaf {
+            contents,
+            cluster,
+            area: area_flags & 0x1ff,
+            flags: (area_flags >> 9) & 0x7f,
+            mins: read_i16s(&mut reader)?,
+            maxs: read_i16s(&mut reader)?,
+            first_leaf_face: reader.read_u16::<LittleEndian>()?,
+            num_leaf_faces: reader.read_u16::<LittleEndian>()?,
+            first_leaf_brush: reader.read_u16::<LittleEndian>()?,
+            num_leaf_brushes: reader.read_u16::<LittleEndian>()?,
+            leaf_water_data_id: reader.read_i16::<LittleEndian>()?,
+        });
+    }
+
+    Ok(leafs)
+}
+
+pub fn read_models(lump: &[u8]) -> Result<Vec<Model>, io::Error> {
+    let mut reader = Cursor::new(lump);
+
+    let mut models = vec![];
+    for _ in 0..lump.len() / MODEL_LEN {
+        models.push(Model {
+            mins: read_f32s(&mut reader)?,
+            maxs: read_f32s(&mut reader)?,
+            origin: read_f32s(&mut reader)?,
+            head_node: reader.read_i32::<LittleEndian>()?,
+            first_face: reader.read_i32::<LittleEndian>()?,
+            num_faces: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(models)
+}
+
+/// Reads a lump consisting of u16 indices, e.g. `LUMP_LEAFBRUSHES`
+pub fn read_u16_indices(lump: &[u8]) -> Result<Vec<u16>, io::Error> {
+    let mut reader = Cursor::new(lump);
+    (0..lump.len() / 2)
+        .map(|_| reader.read_u16::<LittleEndian>())
+        .collect()
+}
+
+/// Returns indices of leafs in the subtree starting from given node.
+///
+/// Each node is visited once, so malformed maps with cycles in the tree terminate
+pub fn leafs_under(nodes: &[Node], head_node: i32) -> Vec<usize> {
+    let mut leafs = vec![];
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = vec![head_node];
+    while let Some(node) = stack.pop() {
+        if node < 0 {
+            leafs.push((-(node + 1)) as usize);
+        } else if let Some(seen) = visited.get_mut(node as usize) {
+            if !*seen {
+                *seen = true;
+                stack.extend_from_slice(&nodes[node as usize].children);
+            }
+        }
+    }
+    leafs
+}
+
+impl BufferedBSP {
+    pub fn planes(&self) -> Result<Vec<Plane>, Error> {
+        Ok(read_planes(&self.lump_data(LumpIndex::LUMP_PLANES)?)?)
+    }
+
+    pub fn nodes(&self) -> Result<Vec<Node>, Error> {
+        self.ensure_standard_structures()?;
+        Ok(read_nodes(&self.lump_data(LumpIndex::LUMP_NODES)?)?)
+    }
+
+    pub fn leafs(&self) -> Result<Vec<Leaf>, Error> {
+        self.ensure_standard_structures()?;
+        let version = self.header.lumps[LumpIndex::LUMP_LEAFS as usize].version;
+        Ok(read_leafs(
+            &self.lump_data(LumpIndex::LUMP_LEAFS)?,
+            version,
+        )?)
+    }
+
+    pub fn models(&self) -> Result<Vec<Model>, Error> {
+        Ok(read_models(&self.lump_data(LumpIndex::LUMP_MODELS)?)?)
+    }
+
+    /// Returns indices of brushes belonging to each model, found by walking
+    /// the tree of each model
+    pub fn model_brushes(&self) -> Result<Vec<BTreeSet<usize>>, Error> {
+        let nodes = self.nodes()?;
+        let leafs = self.leafs()?;
+        let leaf_brushes = read_u16_indices(&self.lump_data(LumpIndex::LUMP_LEAFBRUSHES)?)?;
+
+        Ok(self
+            .models()?
+            .iter()
+            .map(|model| {
+                let mut brushes = BTreeSet::new();
+                for leaf in leafs_under(&nodes, model.head_node) {
+                    if let Some(leaf) = leafs.get(leaf) {
+                        let first = leaf.first_leaf_brush as usize;
+                        let last = first + leaf.num_leaf_brushes as usize;
+                        brushes.extend(
+                            leaf_brushes
+                                .get(first..last)
+                                .unwrap_or_default()
+                                .iter()
+                                .map(|b| *b as usize),
+                        );
+                    }
+                }
+                brushes
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(children: [i32; 2]) -> Node {
+        Node {
+            plane_num: 0,
+            children,
+            mins: [0; 3],
+            maxs: [0; 3],
+            first_face: 0,
+            num_faces: 0,
+            area: 0,
+        }
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        // node 1 points back to node 0
+        let nodes = vec![node([1, -1]), node([0, -2])];
+        let mut leafs = leafs_under(&nodes, 0);
+        leafs.sort_unstable();
+        assert_eq!(leafs, vec![0, 1]);
+    }
+}
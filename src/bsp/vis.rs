@@ -0,0 +1,212 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Cursor};
+
+use super::buffered_bsp::BufferedBSP;
+use super::lump_indices::LumpIndex;
+use super::tree::{Leaf, Node, Plane};
+use super::Error;
+
+/// Decoded visibility lump: the potentially visible set (PVS) and potentially
+/// audible set (PAS) of each cluster
+#[derive(Debug, Clone)]
+pub struct Visibility {
+    pub cluster_count: usize,
+    /// Bit vectors of `cluster_count` bits, indexed by cluster
+    pub pvs: Vec<Vec<u8>>,
+    pub pas: Vec<Vec<u8>>,
+}
+
+impl Visibility {
+    /// Returns clusters in the PVS of given cluster
+    pub fn visible_clusters(&self, cluster: usize) -> Vec<usize> {
+        match self.pvs.get(cluster) {
+            Some(row) => set_bits(row, self.cluster_count),
+            None => vec![],
+        }
+    }
+
+    /// Returns clusters in the PAS of given cluster
+    pub fn audible_clusters(&self, cluster: usize) -> Vec<usize> {
+        match self.pas.get(cluster) {
+            Some(row) => set_bits(row, self.cluster_count),
+            None => vec![],
+        }
+    }
+}
+
+fn set_bits(row: &[u8], count: usize) -> Vec<usize> {
+    (0..count)
+        .filter(|i| row.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) != 0))
+        .collect()
+}
+
+/// Decompresses a run-length encoded bit vector, where a zero byte is followed
+/// by the number of zero bytes it stands for
+pub fn decompress_row(data: &[u8], row_len: usize) -> Vec<u8> {
+    let mut row = Vec::with_capacity(row_len);
+    let mut bytes = data.iter();
+    while row.len() < row_len {
+        match bytes.next() {
+            Some(0) => {
+                let count = bytes.next().copied().unwrap_or(0) as usize;
+                row.resize((row.len() + count).min(row_len), 0);
+            }
+            Some(b) => row.push(*b),
+            None => break,
+        }
+    }
+    row.resize(row_len, 0);
+    row
+}
+
+pub fn read_visibility(lump: &[u8]) -> Result<Visibility, io::Error> {
+    if lump.is_empty() {
+        return Ok(Visibility {
+            cluster_count: 0,
+            pvs: vec![],
+            pas: vec![],
+        });
+    }
+
+    let mut reader = Cursor::new(lump);
+    let cluster_count = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+    let row_len = cluster_count.div_ceil(8);
+
+    let mut pvs = vec![];
+    let mut pas = vec![];
+    for _ in 0..cluster_count {
+        let pvs_off = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+        let pas_off = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+        pvs.push(decompress_row(
+            lump.get(pvs_off..).unwrap_or_default(),
+            row_len,
+        ));
+        pas.push(decompress_row(
+            lump.get(pas_off..).unwrap_or_default(),
+            row_len,
+        ));
+    }
+
+    Ok(Visibility {
+        cluster_count,
+        pvs,
+        pas,
+    })
+}
+
+/// Walks the tree from given node down to the leaf containing given point.
+///
+/// Returns None if the walk visits more nodes than there are, which only happens
+/// when the tree has a cycle
+pub fn find_leaf(
+    nodes: &[Node],
+    planes: &[Plane],
+    head_node: i32,
+    point: [f32; 3],
+) -> Option<usize> {
+    let mut node = head_node;
+    for _ in 0..nodes.len() {
+        if node < 0 {
+            break;
+        }
+        let n = nodes.get(node as usize)?;
+        let plane = planes.get(n.plane_num as usize)?;
+        let d =
+            plane.normal[0] * point[0] + plane.normal[1] * point[1] + plane.normal[2] * point[2]
+                - plane.dist;
+        node = if d >= 0.0 {
+            n.children[0]
+        } else {
+            n.children[1]
+        };
+    }
+    if node >= 0 {
+        return None;
+    }
+    Some((-(node + 1)) as usize)
+}
+
+/// Per-cluster visibility statistics
+#[derive(Debug, Clone)]
+pub struct ClusterStats {
+    pub cluster: usize,
+    pub visible_clusters: usize,
+    /// Number of leafs in the visible clusters
+    pub visible_leafs: usize,
+}
+
+impl BufferedBSP {
+    pub fn visibility(&self) -> Result<Visibility, Error> {
+        Ok(read_visibility(
+            &self.lump_data(LumpIndex::LUMP_VISIBILITY)?,
+        )?)
+    }
+
+    /// Returns index of the world leaf containing given point
+    pub fn leaf_at(&self, point: [f32; 3]) -> Result<Option<usize>, Error> {
+        let head_node = self.models()?.first().map_or(0, |m| m.head_node);
+        Ok(find_leaf(&self.nodes()?, &self.planes()?, head_node, point))
+    }
+
+    /// Computes how many clusters and leafs each cluster can potentially see
+    pub fn cluster_stats(&self, leafs: &[Leaf]) -> Result<Vec<ClusterStats>, Error> {
+        let vis = self.visibility()?;
+
+        let mut leafs_per_cluster = vec![0; vis.cluster_count];
+        for leaf in leafs {
+            if let Some(count) = usize::try_from(leaf.cluster)
+                .ok()
+                .and_then(|c| leafs_per_cluster.get_mut(c))
+            {
+                *count += 1;
+            }
+        }
+
+        Ok((0..vis.cluster_count)
+            .map(|cluster| {
+                let visible = vis.visible_clusters(cluster);
+                ClusterStats {
+                    cluster,
+                    visible_clusters: visible.len(),
+                    visible_leafs: visible.iter().map(|c| leafs_per_cluster[*c]).sum(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompresses_zero_runs() {
+        // 0b101, then 3 zero bytes, then 0x80
+        let row = decompress_row(&[0x05, 0x00, 0x03, 0x80], 5);
+        assert_eq!(row, vec![0x05, 0, 0, 0, 0x80]);
+        assert_eq!(set_bits(&row, 40), vec![0, 2, 39]);
+    }
+
+    #[test]
+    fn gives_up_on_cyclic_trees() {
+        let planes = vec![Plane {
+            normal: [1.0, 0.0, 0.0],
+            dist: 0.0,
+            axis_type: 0,
+        }];
+        let node = |children| Node {
+            plane_num: 0,
+            children,
+            mins: [0; 3],
+            maxs: [0; 3],
+            first_face: 0,
+            num_faces: 0,
+            area: 0,
+        };
+        // front child of node 1 points back to node 0
+        let nodes = vec![node([1, -1]), node([0, -2])];
+        assert_eq!(find_leaf(&nodes, &planes, 0, [-1.0, 0.0, 0.0]), Some(0));
+        assert_eq!(find_leaf(&nodes, &planes, 0, [1.0, 0.0, 0.0]), None);
+    }
+}
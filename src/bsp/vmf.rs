@@ -0,0 +1,491 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+use super::brushes::{Brush, BrushSide, CONTENTS_DETAIL};
+use super::buffered_bsp::BufferedBSP;
+use super::entities::{self, Entity};
+use super::lump_indices::LumpIndex;
+use super::textures::TexInfo;
+use super::tree::Plane;
+use super::Error;
+
+type Vec3 = [f64; 3];
+
+/// Half size of the initial winding clipped down to a brush side. Larger than
+/// the maximum map size
+const BASE_WINDING_SIZE: f64 = 65536.0;
+const CLIP_EPSILON: f64 = 0.01;
+const DEFAULT_LIGHTMAP_SCALE: i32 = 16;
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add_scaled(a: Vec3, b: Vec3, scale: f64) -> Vec3 {
+    [
+        a[0] + b[0] * scale,
+        a[1] + b[1] * scale,
+        a[2] + b[2] * scale,
+    ]
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    [v[0] as f64, v[1] as f64, v[2] as f64]
+}
+
+/// Returns a large quad lying on given plane, wound clockwise when looking
+/// at the front of the plane
+fn base_winding(normal: Vec3, dist: f64) -> Vec<Vec3> {
+    let major_axis = (0..3)
+        .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+        .unwrap();
+    let up = if major_axis == 2 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 0.0, 1.0]
+    };
+
+    let up = add_scaled(up, normal, -dot(up, normal));
+    let len = dot(up, up).sqrt();
+    let up = [up[0] / len, up[1] / len, up[2] / len];
+    let right = cross(up, normal);
+
+    let origin = [normal[0] * dist, normal[1] * dist, normal[2] * dist];
+    let s = BASE_WINDING_SIZE;
+    vec![
+        add_scaled(add_scaled(origin, right, -s), up, s),
+        add_scaled(add_scaled(origin, right, s), up, s),
+        add_scaled(add_scaled(origin, right, s), up, -s),
+        add_scaled(add_scaled(origin, right, -s), up, -s),
+    ]
+}
+
+/// Clips winding to the back side of given plane
+fn clip_winding(winding: &[Vec3], normal: Vec3, dist: f64) -> Vec<Vec3> {
+    let mut clipped = vec![];
+    for (i, p) in winding.iter().enumerate() {
+        let next = winding[(i + 1) % winding.len()];
+        let d = dot(*p, normal) - dist;
+        let next_d = dot(next, normal) - dist;
+
+        if d <= CLIP_EPSILON {
+            clipped.push(*p);
+        }
+        if (d > CLIP_EPSILON && next_d < -CLIP_EPSILON)
+            || (d < -CLIP_EPSILON && next_d > CLIP_EPSILON)
+        {
+            let t = d / (d - next_d);
+            clipped.push(add_scaled(*p, sub(next, *p), t));
+        }
+    }
+    clipped
+}
+
+/// Returns three points defining the plane of given side in VMF order, or None
+/// if the side doesn't contribute to the brush
+fn side_points(planes: &[(Vec3, f64)], side: usize) -> Option<[Vec3; 3]> {
+    let (normal, dist) = planes[side];
+    let mut winding = base_winding(normal, dist);
+    for (i, (other_normal, other_dist)) in planes.iter().enumerate() {
+        if i != side {
+            winding = clip_winding(&winding, *other_normal, *other_dist);
+        }
+    }
+    if winding.len() < 3 {
+        return None;
+    }
+
+    // use the triangle with the largest area for best precision
+    let (i, area) = (1..winding.len() - 1)
+        .map(|i| {
+            let c = cross(sub(winding[0], winding[i]), sub(winding[i + 1], winding[i]));
+            (i, dot(c, c))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if area < CLIP_EPSILON {
+        return None;
+    }
+
+    let points = [winding[0], winding[i], winding[i + 1]];
+    let c = cross(sub(points[0], points[1]), sub(points[2], points[1]));
+    if dot(c, normal) < 0.0 {
+        Some([points[2], points[1], points[0]])
+    } else {
+        Some(points)
+    }
+}
+
+/// Formats a number without trailing zeros, snapping values very close to an integer
+fn fmt_num(x: f64) -> String {
+    let rounded = x.round();
+    if (x - rounded).abs() < 0.001 {
+        return format!("{}", rounded + 0.0);
+    }
+    let s = format!("{:.3}", x);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn fmt_point(p: Vec3) -> String {
+    format!("({} {} {})", fmt_num(p[0]), fmt_num(p[1]), fmt_num(p[2]))
+}
+
+/// Whether a key-value pair is an entity output, e.g. "OnTrigger" "door,Open,,0,-1"
+fn is_output(key: &str, value: &str) -> bool {
+    (key.starts_with("On") || key.starts_with("Out"))
+        && (value.split('\x1b').count() == 5 || value.split(',').count() == 5)
+}
+
+/// Builds KeyValues text in the format Hammer uses
+struct VmfWriter {
+    out: String,
+    depth: usize,
+    next_id: u32,
+}
+
+impl VmfWriter {
+    fn begin(&mut self, name: &str) {
+        self.line(name);
+        self.line("{");
+        self.depth += 1;
+    }
+
+    fn end(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out.push('\t');
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn kv(&mut self, key: &str, value: &str) {
+        self.line(&format!("\"{}\" \"{}\"", key, value));
+    }
+
+    fn id(&mut self) {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+        self.kv("id", &id);
+    }
+}
+
+/// Map data needed to write brushes
+struct BrushData {
+    planes: Vec<Plane>,
+    brushes: Vec<Brush>,
+    sides: Vec<BrushSide>,
+    texinfo: Vec<TexInfo>,
+    /// Material name of each texdata
+    materials: Vec<String>,
+}
+
+impl BrushData {
+    fn material(&self, info: Option<&TexInfo>) -> String {
+        info.and_then(|info| usize::try_from(info.texdata).ok())
+            .and_then(|i| self.materials.get(i))
+            .cloned()
+            .unwrap_or_else(|| "TOOLS/TOOLSNODRAW".to_string())
+    }
+
+    /// Returns three points on each face of given brush, translated by origin, or
+    /// None if the brush can't be reconstructed into a solid
+    fn solid_faces(&self, brush: usize, origin: Vec3) -> Option<Vec<([Vec3; 3], &BrushSide)>> {
+        let brush = &self.brushes[brush];
+        let first = brush.first_side.max(0) as usize;
+        let sides: Vec<&BrushSide> = self
+            .sides
+            .get(first..first + brush.num_sides.max(0) as usize)
+            .unwrap_or_default()
+            .iter()
+            .filter(|side| !side.bevel)
+            .collect();
+
+        let planes: Vec<(Vec3, f64)> = sides
+            .iter()
+            .filter_map(|side| self.planes.get(side.plane_num as usize))
+            .map(|plane| {
+                let normal = to_vec3(plane.normal);
+                (normal, plane.dist as f64 + dot(normal, origin))
+            })
+            .collect();
+        if planes.len() != sides.len() {
+            return None;
+        }
+
+        let faces: Vec<([Vec3; 3], &BrushSide)> = (0..planes.len())
+            .filter_map(|i| Some((side_points(&planes, i)?, sides[i])))
+            .collect();
+        if faces.len() < 4 {
+            return None;
+        }
+        Some(faces)
+    }
+
+    /// Writes faces returned by `solid_faces` as a solid
+    fn write_solid(
+        &self,
+        writer: &mut VmfWriter,
+        faces: Vec<([Vec3; 3], &BrushSide)>,
+        origin: Vec3,
+    ) {
+        writer.begin("solid");
+        writer.id();
+        for (points, side) in faces {
+            let info = usize::try_from(side.texinfo)
+                .ok()
+                .and_then(|i| self.texinfo.get(i));
+
+            writer.begin("side");
+            writer.id();
+            writer.kv(
+                "plane",
+                &format!(
+                    "{} {} {}",
+                    fmt_point(points[0]),
+                    fmt_point(points[1]),
+                    fmt_point(points[2])
+                ),
+            );
+            writer.kv("material", &self.material(info));
+
+            let (uaxis, vaxis, lightmap_scale) = match info {
+                Some(info) => (
+                    texture_axis(info.texture_vecs[0], origin),
+                    texture_axis(info.texture_vecs[1], origin),
+                    lightmap_scale(info.lightmap_vecs[0]),
+                ),
+                None => (
+                    "[1 0 0 0] 0.25".to_string(),
+                    "[0 -1 0 0] 0.25".to_string(),
+                    DEFAULT_LIGHTMAP_SCALE,
+                ),
+            };
+            writer.kv("uaxis", &uaxis);
+            writer.kv("vaxis", &vaxis);
+            writer.kv("rotation", "0");
+            writer.kv("lightmapscale", &lightmap_scale.to_string());
+            writer.kv("smoothing_groups", "0");
+            writer.end();
+        }
+        writer.end();
+    }
+}
+
+/// Converts a texinfo texture vector (axis divided by scale, and offset) into
+/// VMF "[x y z offset] scale" format
+fn texture_axis(vec: [f32; 4], origin: Vec3) -> String {
+    let axis = [vec[0] as f64, vec[1] as f64, vec[2] as f64];
+    let len = dot(axis, axis).sqrt();
+    if len == 0.0 {
+        return "[1 0 0 0] 0.25".to_string();
+    }
+
+    let offset = vec[3] as f64 - dot(axis, origin);
+    format!(
+        "[{} {} {} {}] {}",
+        fmt_num(axis[0] / len),
+        fmt_num(axis[1] / len),
+        fmt_num(axis[2] / len),
+        fmt_num(offset),
+        fmt_num(1.0 / len)
+    )
+}
+
+/// Converts a texinfo lightmap vector (luxels per unit) into units per luxel
+fn lightmap_scale(vec: [f32; 4]) -> i32 {
+    let len = (vec[0] * vec[0] + vec[1] * vec[1] + vec[2] * vec[2]).sqrt();
+    if len == 0.0 {
+        DEFAULT_LIGHTMAP_SCALE
+    } else {
+        (1.0 / len).round() as i32
+    }
+}
+
+fn parse_origin(entity: &Entity) -> Vec3 {
+    let mut origin = [0.0; 3];
+    if let Some(value) = entity.get("origin") {
+        for (o, v) in origin.iter_mut().zip(value.split_whitespace()) {
+            *o = v.parse().unwrap_or(0.0);
+        }
+    }
+    origin
+}
+
+/// Writes entity key-values, with outputs in a connections block
+fn write_properties(writer: &mut VmfWriter, entity: &Entity) {
+    let mut outputs = vec![];
+    for (key, value) in &entity.properties {
+        if key.eq_ignore_ascii_case("model") && value.starts_with('*') {
+            continue;
+        }
+        if is_output(key, value) {
+            outputs.push((key, value));
+        } else {
+            writer.kv(key, value);
+        }
+    }
+
+    if !outputs.is_empty() {
+        writer.begin("connections");
+        for (key, value) in outputs {
+            writer.kv(key, value);
+        }
+        writer.end();
+    }
+}
+
+impl BufferedBSP {
+    /// Reconstructs a Hammer map from brushes and entities.
+    ///
+    /// Brush entities get the brushes of their model back, and detail brushes of
+    /// the world are written as func_detail entities. Displacements, overlays and
+    /// static props are not recovered
+    pub fn to_vmf(&self) -> Result<String, Error> {
+        let strings = self.texdata_strings()?;
+        let data = BrushData {
+            planes: self.planes()?,
+            brushes: self.brushes()?,
+            sides: self.brush_sides()?,
+            texinfo: self.texinfo()?,
+            materials: self
+                .texdata()?
+                .iter()
+                .map(|texdata| {
+                    usize::try_from(texdata.name_string_table_id)
+                        .ok()
+                        .and_then(|i| strings.get(i))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect(),
+        };
+        let model_brushes = self.model_brushes()?;
+        let entities = entities::parse(&self.lump_data(LumpIndex::LUMP_ENTITIES)?)?;
+
+        let mut writer = VmfWriter {
+            out: String::new(),
+            depth: 0,
+            next_id: 0,
+        };
+
+        writer.begin("versioninfo");
+        writer.kv("editorversion", "400");
+        writer.kv("editorbuild", "0");
+        writer.kv("mapversion", &self.header.map_revision.to_string());
+        writer.kv("formatversion", "100");
+        writer.kv("prefab", "0");
+        writer.end();
+
+        // brush entity brushes should never be in the world tree, but make sure
+        // they are not written twice
+        let entity_brushes: BTreeSet<usize> =
+            model_brushes.iter().skip(1).flatten().copied().collect();
+        let world_brushes: Vec<usize> = model_brushes
+            .first()
+            .map(|brushes| brushes.difference(&entity_brushes).copied().collect())
+            .unwrap_or_default();
+        let (detail_brushes, world_brushes): (Vec<usize>, Vec<usize>) =
+            world_brushes.into_iter().partition(|b| {
+                data.brushes
+                    .get(*b)
+                    .is_some_and(|brush| brush.contents & CONTENTS_DETAIL != 0)
+            });
+
+        writer.begin("world");
+        writer.id();
+        match entities
+            .iter()
+            .find(|e| e.classname() == Some("worldspawn"))
+        {
+            Some(worldspawn) => write_properties(&mut writer, worldspawn),
+            None => writer.kv("classname", "worldspawn"),
+        }
+        for brush in world_brushes
+            .into_iter()
+            .filter(|b| *b < data.brushes.len())
+        {
+            if let Some(faces) = data.solid_faces(brush, [0.0; 3]) {
+                data.write_solid(&mut writer, faces, [0.0; 3]);
+            }
+        }
+        writer.end();
+
+        for entity in &entities {
+            if entity.classname() == Some("worldspawn") {
+                continue;
+            }
+
+            writer.begin("entity");
+            writer.id();
+            write_properties(&mut writer, entity);
+
+            let model = entity
+                .get("model")
+                .and_then(|m| m.strip_prefix('*'))
+                .and_then(|m| m.parse::<usize>().ok());
+            if let Some(brushes) = model.filter(|m| *m > 0).and_then(|m| model_brushes.get(m)) {
+                let origin = parse_origin(entity);
+                for brush in brushes.iter().filter(|b| **b < data.brushes.len()) {
+                    if let Some(faces) = data.solid_faces(*brush, origin) {
+                        data.write_solid(&mut writer, faces, origin);
+                    }
+                }
+            }
+            writer.end();
+        }
+
+        for brush in detail_brushes {
+            // skip brushes that can't be reconstructed instead of writing empty entities
+            let faces = match data.solid_faces(brush, [0.0; 3]) {
+                Some(faces) => faces,
+                None => continue,
+            };
+            writer.begin("entity");
+            writer.id();
+            writer.kv("classname", "func_detail");
+            data.write_solid(&mut writer, faces, [0.0; 3]);
+            writer.end();
+        }
+
+        Ok(writer.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconstructs_cube_side() {
+        // 64 unit cube centered at origin
+        let planes: Vec<(Vec3, f64)> = vec![
+            ([1.0, 0.0, 0.0], 32.0),
+            ([-1.0, 0.0, 0.0], 32.0),
+            ([0.0, 1.0, 0.0], 32.0),
+            ([0.0, -1.0, 0.0], 32.0),
+            ([0.0, 0.0, 1.0], 32.0),
+            ([0.0, 0.0, -1.0], 32.0),
+        ];
+
+        let points = side_points(&planes, 4).unwrap();
+        assert!(points.iter().all(|p| p[2] == 32.0));
+        let normal = cross(sub(points[0], points[1]), sub(points[2], points[1]));
+        assert!(normal[2] > 0.0);
+    }
+}
@@ -19,6 +19,10 @@ impl <Node: Debug + Clone, Edge: Debug + Clone> DependencyGraph<Node, Edge> {
         }
     }
 
+    pub fn root(&self) -> NodeRef {
+        self.root_ref
+    }
+
     pub fn dot(&self) -> String {
         format!("{:?}", Dot::with_config(&self.graph, &[Config::EdgeNoLabel]))
     }
@@ -65,6 +69,10 @@ impl <Node: Debug + Clone, Edge: Debug + Clone> DependencyGraph<Node, Edge> {
         self.remove_unconnected_dependencies();
     }
 
+    pub fn node_mut(&mut self, r: NodeRef) -> &mut Node {
+        &mut self.graph[r]
+    }
+
     pub fn insert(&mut self, n: Node, e: Edge) -> NodeRef {
         let r = self.graph.add_node(n);
         self.graph.add_edge(self.root_ref, r, e);
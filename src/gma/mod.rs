@@ -1,5 +1,5 @@
 mod read;
-pub use read::read_gma;
+pub use read::{read_gma, read_gma_file, read_gma_from};
 
 mod write;
 pub use write::write_gma;
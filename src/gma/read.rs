@@ -3,64 +3,86 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use std::io;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
 
-fn read_nt_string<R: Read + BufRead>(handle: &mut R) -> String {
+fn read_nt_string<R: Read + BufRead>(handle: &mut R) -> Result<String, io::Error> {
     let mut buf = Vec::new();
-    handle.read_until(0, &mut buf).unwrap();
+    handle.read_until(0, &mut buf)?;
+    if buf.pop() != Some(0) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
 
-    // don't include null byte
-    return std::str::from_utf8(&buf[0..buf.len() - 1])
-        .unwrap()
-        .to_owned();
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
 pub fn read_gma<F>(input: &str, read_entry: F) -> GMAFile where
     F: Fn(&str) -> bool {
 
-    let mut handle: Box<dyn BufRead> = match input {
+    let result = match input {
         "" | "-" => {
-            Box::new(BufReader::new(io::stdin()))
+            read_gma_from(&mut BufReader::new(io::stdin()), read_entry)
         },
         x => {
-            let f = File::open(x).unwrap();
-            Box::new(BufReader::new(f))
+            read_gma_file(Path::new(x), read_entry)
         }
     };
-    
+
+    match result {
+        Ok(gma) => gma,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a .gma, returning an error instead of exiting if it can't be read
+pub fn read_gma_file<F>(path: &Path, read_entry: F) -> Result<GMAFile, io::Error> where
+    F: Fn(&str) -> bool {
+
+    let f = File::open(path)?;
+    read_gma_from(&mut BufReader::new(f), read_entry)
+}
+
+pub fn read_gma_from<R, F>(handle: &mut R, read_entry: F) -> Result<GMAFile, io::Error> where
+    R: BufRead,
+    F: Fn(&str) -> bool {
+
     let mut magic_buf = [0; 4];
-    handle.read_exact(&mut magic_buf).unwrap();
+    handle.read_exact(&mut magic_buf)?;
 
     if &magic_buf != GMA_HEADER {
-        eprintln!("header not GMAD??");
-        std::process::exit(1);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "header not GMAD"));
     }
 
-    let fmt_version = handle.read_u8().unwrap();
+    let fmt_version = handle.read_u8()?;
     if fmt_version != SUPPORTED_GMA_VERSION {
-        eprintln!("unsupported gma version");
-        std::process::exit(1);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported gma version {}", fmt_version),
+        ));
     }
 
-    let _steamid = handle.read_u64::<LittleEndian>().unwrap();
-    let _timestamp = handle.read_u64::<LittleEndian>().unwrap();
+    let _steamid = handle.read_u64::<LittleEndian>()?;
+    let _timestamp = handle.read_u64::<LittleEndian>()?;
 
-    let mut dumb_string = read_nt_string(&mut handle);
+    let mut dumb_string = read_nt_string(handle)?;
     while dumb_string.len() > 0 {
-        dumb_string = read_nt_string(&mut handle);
+        dumb_string = read_nt_string(handle)?;
     }
 
-    let name = read_nt_string(&mut handle);
-    let desc = read_nt_string(&mut handle);
-    let author = read_nt_string(&mut handle);
+    let name = read_nt_string(handle)?;
+    let desc = read_nt_string(handle)?;
+    let author = read_nt_string(handle)?;
 
-    let _addon_version = handle.read_u32::<LittleEndian>().unwrap();
+    let _addon_version = handle.read_u32::<LittleEndian>()?;
 
     let mut entries = vec!();
 
-    while handle.read_u32::<LittleEndian>().unwrap() != 0 {
-        let entry_name = read_nt_string(&mut handle);
-        let entry_size = handle.read_i64::<LittleEndian>().unwrap();
-        let entry_crc = handle.read_u32::<LittleEndian>().unwrap();
+    while handle.read_u32::<LittleEndian>()? != 0 {
+        let entry_name = read_nt_string(handle)?;
+        let entry_size = handle.read_i64::<LittleEndian>()?;
+        let entry_crc = handle.read_u32::<LittleEndian>()?;
 
         let entry = GMAEntry {
             name: entry_name,
@@ -74,13 +96,16 @@ pub fn read_gma<F>(input: &str, read_entry: F) -> GMAFile where
     // Read file contents
     for mut e in &mut entries {
         if read_entry(&e.name) {
-            let mut buf = vec![0; e.size as usize];
-            handle.read_exact(&mut buf).unwrap();
+            // read through take, as the size may be bogus
+            let mut buf = Vec::new();
+            handle.take(e.size).read_to_end(&mut buf)?;
+            if buf.len() as u64 != e.size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
             e.contents = Some(buf);
         } else {
             // Pipe to sink
-            let mut_handle = &mut handle;
-            io::copy(&mut mut_handle.take(e.size), &mut io::sink()).unwrap();
+            io::copy(&mut handle.take(e.size), &mut io::sink())?;
         }
     }
 
@@ -88,15 +113,15 @@ pub fn read_gma<F>(input: &str, read_entry: F) -> GMAFile where
     // Hence, we shouldn't unwrap the following since it may fail
     let _addon_crc = handle.read_u32::<LittleEndian>();
 
-    let remaining = io::copy(&mut handle, &mut io::sink()).unwrap();
+    let remaining = io::copy(handle, &mut io::sink())?;
     if remaining != 0 {
         eprintln!("Warning: GMA file had {} bytes of extra _after_ the entries", remaining);
     }
 
-    GMAFile {
+    Ok(GMAFile {
         name: name,
         description: desc,
         author: author,
         entries: entries
-    }
+    })
 }
\ No newline at end of file
@@ -1,7 +1,10 @@
 pub mod bsp;
 pub mod dependency;
 pub mod gma;
+pub mod mesh;
 pub mod mdl;
+pub mod nav;
+pub mod search_path;
 pub mod vmt;
 pub mod vtf;
 pub mod workshop;
@@ -0,0 +1,325 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use super::binary::{Animation, Bone, StudioHeader};
+use super::Error;
+
+/// Animation flag for animations relative to another animation
+pub const STUDIO_DELTA: i32 = 0x4;
+/// Animation flag for animations without data, leaving every bone in its bind pose
+pub const STUDIO_ALLZEROS: i32 = 0x20;
+
+const ANIM_RAWPOS: u8 = 0x01;
+const ANIM_RAWROT: u8 = 0x02;
+const ANIM_ANIMPOS: u8 = 0x04;
+const ANIM_ANIMROT: u8 = 0x08;
+const ANIM_DELTA: u8 = 0x10;
+const ANIM_RAWROT2: u8 = 0x20;
+
+/// Position and rotation of a bone relative to its parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BonePose {
+    pub position: [f32; 3],
+    /// Quaternion (x, y, z, w)
+    pub rotation: [f32; 4],
+}
+
+impl BonePose {
+    fn bind(bone: &Bone) -> BonePose {
+        BonePose {
+            position: bone.position,
+            rotation: bone.quaternion,
+        }
+    }
+
+    fn zero() -> BonePose {
+        BonePose {
+            position: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Converts radian euler angles (roll, pitch, yaw) to a quaternion
+pub fn euler_to_quaternion(angles: [f32; 3]) -> [f32; 4] {
+    let (sr, cr) = (angles[0] * 0.5).sin_cos();
+    let (sp, cp) = (angles[1] * 0.5).sin_cos();
+    let (sy, cy) = (angles[2] * 0.5).sin_cos();
+    [
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+        cr * cp * cy + sr * sp * sy,
+    ]
+}
+
+/// Converts a rotation matrix to radian euler angles (roll, pitch, yaw)
+pub fn matrix_to_euler(m: [[f32; 3]; 3]) -> [f32; 3] {
+    let forward = [m[0][0], m[1][0], m[2][0]];
+    let left = [m[0][1], m[1][1], m[2][1]];
+    let xy_dist = (forward[0] * forward[0] + forward[1] * forward[1]).sqrt();
+    let pitch = (-forward[2]).atan2(xy_dist);
+    if xy_dist > 0.001 {
+        [left[2].atan2(m[2][2]), pitch, forward[1].atan2(forward[0])]
+    } else {
+        [0.0, pitch, (-left[0]).atan2(left[1])]
+    }
+}
+
+/// Converts a quaternion to a rotation matrix
+pub fn quaternion_to_matrix(q: [f32; 4]) -> [[f32; 3]; 3] {
+    let [x, y, z, w] = q;
+    [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - w * z),
+            2.0 * (x * z + w * y),
+        ],
+        [
+            2.0 * (x * y + w * z),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - w * x),
+        ],
+        [
+            2.0 * (x * z - w * y),
+            2.0 * (y * z + w * x),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ]
+}
+
+/// Converts a quaternion to radian euler angles (roll, pitch, yaw)
+pub fn quaternion_to_euler(q: [f32; 4]) -> [f32; 3] {
+    matrix_to_euler(quaternion_to_matrix(q))
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn read_quaternion48(reader: &mut Cursor<&[u8]>) -> Result<[f32; 4], Error> {
+    let x = reader.read_u16::<LittleEndian>()?;
+    let y = reader.read_u16::<LittleEndian>()?;
+    let z_w = reader.read_u16::<LittleEndian>()?;
+    let x = (x as f32 - 32768.0) / 32768.0;
+    let y = (y as f32 - 32768.0) / 32768.0;
+    let z = ((z_w & 0x7fff) as f32 - 16384.0) / 16384.0;
+    let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+    Ok([x, y, z, if z_w & 0x8000 != 0 { -w } else { w }])
+}
+
+fn read_quaternion64(reader: &mut Cursor<&[u8]>) -> Result<[f32; 4], Error> {
+    let bits = reader.read_u64::<LittleEndian>()?;
+    let component = |shift: u32| ((bits >> shift) & 0x1f_ffff) as f32;
+    let x = (component(0) - 1_048_576.0) / 1_048_576.5;
+    let y = (component(21) - 1_048_576.0) / 1_048_576.5;
+    let z = (component(42) - 1_048_576.0) / 1_048_576.5;
+    let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+    Ok([x, y, z, if bits >> 63 != 0 { -w } else { w }])
+}
+
+fn read_vector48(reader: &mut Cursor<&[u8]>) -> Result<[f32; 3], Error> {
+    let mut v = [0.0; 3];
+    for c in &mut v {
+        *c = half_to_f32(reader.read_u16::<LittleEndian>()?);
+    }
+    Ok(v)
+}
+
+/// Reads a run-length encoded value (`mstudioanimvalue_t`) of given frame
+fn read_anim_value(reader: &mut Cursor<&[u8]>, start: u64, frame: i32) -> Result<f32, Error> {
+    let mut pos = start;
+    let mut k = frame;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        let valid = reader.read_u8()? as i32;
+        let total = reader.read_u8()? as i32;
+        if total == 0 {
+            return Ok(0.0);
+        }
+        if total > k {
+            let index = if valid > k { k + 1 } else { valid };
+            reader.seek(SeekFrom::Start(pos + 2 * index as u64))?;
+            return Ok(reader.read_i16::<LittleEndian>()? as f32);
+        }
+        k -= total;
+        pos += 2 * (valid as u64 + 1);
+    }
+}
+
+/// Reads the three values of a `mstudioanim_valueptr_t` at the reader position
+fn read_anim_values(
+    reader: &mut Cursor<&[u8]>,
+    frame: i32,
+    scale: [f32; 3],
+) -> Result<[f32; 3], Error> {
+    let base = reader.position();
+    let mut offsets = [0; 3];
+    reader.read_i16_into::<LittleEndian>(&mut offsets)?;
+
+    let mut values = [0.0; 3];
+    for i in 0..3 {
+        if offsets[i] > 0 {
+            values[i] = read_anim_value(reader, base + offsets[i] as u64, frame)? * scale[i];
+        }
+    }
+    Ok(values)
+}
+
+/// Applies the animation data at `start` for given frame to `poses`
+fn read_frame(
+    reader: &mut Cursor<&[u8]>,
+    bones: &[Bone],
+    start: u64,
+    frame: i32,
+    poses: &mut [BonePose],
+) -> Result<(), Error> {
+    let mut pos = start;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        let index = reader.read_u8()? as usize;
+        let flags = reader.read_u8()?;
+        let next = reader.read_i16::<LittleEndian>()?;
+        let data = pos + 4;
+
+        let bone = bones
+            .get(index)
+            .ok_or(Error::IndexOutOfRange("bone", index, bones.len()))?;
+        let delta = flags & ANIM_DELTA != 0;
+        let pose = &mut poses[index];
+
+        if flags & ANIM_RAWROT != 0 {
+            pose.rotation = read_quaternion48(reader)?;
+        } else if flags & ANIM_RAWROT2 != 0 {
+            pose.rotation = read_quaternion64(reader)?;
+        } else if flags & ANIM_ANIMROT != 0 {
+            let mut angles = read_anim_values(reader, frame, bone.rotation_scale)?;
+            if !delta {
+                for (angle, rotation) in angles.iter_mut().zip(&bone.rotation) {
+                    *angle += rotation;
+                }
+            }
+            pose.rotation = euler_to_quaternion(angles);
+        }
+
+        if flags & ANIM_RAWPOS != 0 {
+            let mut offset = data;
+            if flags & ANIM_RAWROT != 0 {
+                offset += 6;
+            }
+            if flags & ANIM_RAWROT2 != 0 {
+                offset += 8;
+            }
+            reader.seek(SeekFrom::Start(offset))?;
+            pose.position = read_vector48(reader)?;
+        } else if flags & ANIM_ANIMPOS != 0 {
+            let offset = if flags & ANIM_ANIMROT != 0 {
+                data + 6
+            } else {
+                data
+            };
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut position = read_anim_values(reader, frame, bone.position_scale)?;
+            if !delta {
+                for (p, bind) in position.iter_mut().zip(&bone.position) {
+                    *p += bind;
+                }
+            }
+            pose.position = position;
+        }
+
+        if next <= 0 {
+            return Ok(());
+        }
+        pos += next as u64;
+    }
+}
+
+/// Decodes every frame of an animation stored in the mdl, returning a pose for
+/// each bone per frame. Delta animations are relative to the bind pose instead
+/// of including it
+pub fn read_frames(
+    bytes: &[u8],
+    header: &StudioHeader,
+    animation: &Animation,
+) -> Result<Vec<Vec<BonePose>>, Error> {
+    let mut reader = Cursor::new(bytes);
+    let initial: Vec<BonePose> = if animation.flags & STUDIO_DELTA != 0 {
+        header.bones.iter().map(|_| BonePose::zero()).collect()
+    } else {
+        header.bones.iter().map(BonePose::bind).collect()
+    };
+
+    let frame_count = animation.frame_count.max(1);
+    let mut frames = vec![];
+    for frame in 0..frame_count {
+        let mut poses = initial.clone();
+        if animation.flags & STUDIO_ALLZEROS == 0 {
+            let (block, index, local_frame) = if animation.section_frames > 0 {
+                let section = frame / animation.section_frames;
+                let offset = animation.offset as u64
+                    + animation.section_index.max(0) as u64
+                    + section as u64 * 8;
+                reader.seek(SeekFrom::Start(offset))?;
+                let block = reader.read_i32::<LittleEndian>()?;
+                let index = reader.read_i32::<LittleEndian>()?;
+                (block, index, frame - section * animation.section_frames)
+            } else {
+                (animation.anim_block, animation.anim_index, frame)
+            };
+
+            if block != 0 {
+                return Err(Error::ExternalAnimation(animation.name.clone()));
+            }
+            let start = animation.offset as i64 + index as i64;
+            if start < 0 || start as usize >= bytes.len() {
+                return Err(Error::BadOffset("animation", start));
+            }
+            read_frame(
+                &mut reader,
+                &header.bones,
+                start as u64,
+                local_frame,
+                &mut poses,
+            )?;
+        }
+        frames.push(poses);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_euler_and_quaternion() {
+        let angles = [0.3, -0.5, 1.2];
+        let converted = quaternion_to_euler(euler_to_quaternion(angles));
+        for (a, b) in angles.iter().zip(&converted) {
+            assert!((a - b).abs() < 1e-5, "{:?} != {:?}", angles, converted);
+        }
+    }
+
+    #[test]
+    fn reads_run_length_encoded_values() {
+        // two frames with values 5 and 6, then three frames repeating 7
+        let values: Vec<u8> = [0x0202i16, 5, 6, 0x0301, 7, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let mut reader = Cursor::new(&values[..]);
+        let frames: Vec<f32> = (0..6)
+            .map(|f| read_anim_value(&mut reader, 0, f).unwrap())
+            .collect();
+        assert_eq!(frames, vec![5.0, 6.0, 7.0, 7.0, 7.0, 0.0]);
+    }
+}
@@ -1,109 +1,768 @@
-use std::io::Read;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::io;
+use std::io::Cursor;
+use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::io::Cursor;
-use std::ffi::CStr;
-use byteorder::{LittleEndian, ReadBytesExt};
-use err_derive::Error;
-use std::collections::HashSet;
 
-#[derive(Debug)]
-pub struct PartialMDL {
+use super::Error;
+
+const BONE_LEN: u64 = 216;
+const BONE_CONTROLLER_LEN: u64 = 56;
+const HITBOX_SET_LEN: u64 = 12;
+const HITBOX_LEN: u64 = 68;
+const ANIM_DESC_LEN: u64 = 100;
+const SEQ_DESC_LEN: u64 = 212;
+const EVENT_LEN: u64 = 80;
+const TEXTURE_LEN: u64 = 64;
+const BODY_PART_LEN: u64 = 16;
+const MODEL_LEN: u64 = 148;
+const MESH_LEN: u64 = 116;
+const ATTACHMENT_LEN: u64 = 92;
+const FLEX_CONTROLLER_LEN: u64 = 20;
+const MODEL_GROUP_LEN: u64 = 8;
+
+/// Versions of `studiohdr_t` this parser understands
+pub const SUPPORTED_VERSIONS: std::ops::RangeInclusive<i32> = 44..=49;
+
+/// Sequence flag for looping animations
+pub const STUDIO_LOOPING: i32 = 0x1;
+
+#[derive(Debug, Clone)]
+pub struct Bone {
+    pub name: String,
+    /// Index of the parent bone, or -1 for root bones
+    pub parent: i32,
+    pub bone_controllers: [i32; 6],
+    pub position: [f32; 3],
+    pub quaternion: [f32; 4],
+    /// Rotation as radian euler angles
+    pub rotation: [f32; 3],
+    /// Scales for compressed animation data
+    pub position_scale: [f32; 3],
+    pub rotation_scale: [f32; 3],
+    pub pose_to_bone: [[f32; 4]; 3],
+    pub alignment: [f32; 4],
+    pub flags: i32,
+    pub procedural_type: i32,
+    pub physics_bone: i32,
+    pub surface_prop: String,
+    pub contents: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BoneController {
+    pub bone: i32,
+    /// Type and axis flags (`STUDIO_X`, `STUDIO_XR` etc.)
+    pub kind: i32,
+    pub start: f32,
+    pub end: f32,
+    pub rest: i32,
+    pub input_field: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hitbox {
+    pub bone: i32,
+    pub group: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HitboxSet {
+    pub name: String,
+    pub hitboxes: Vec<Hitbox>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// Offset of the animation description in the mdl
+    pub offset: usize,
+    pub name: String,
+    pub fps: f32,
+    pub flags: i32,
+    pub frame_count: i32,
+    /// Animation block the data is in. Block 0 is the mdl itself, others are in the .ani
+    pub anim_block: i32,
+    /// Offset of the animation data, relative to the description or the animation block
+    pub anim_index: i32,
+    pub section_index: i32,
+    pub section_frames: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub cycle: f32,
+    pub event: i32,
+    pub kind: i32,
+    pub options: String,
+    /// Name of the event. Older models only have the numeric event
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    pub label: String,
+    pub activity_name: String,
+    pub flags: i32,
+    pub activity: i32,
+    pub activity_weight: i32,
+    pub events: Vec<Event>,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    /// Blend grid size, with `animations` listing local animation indices row by row
+    pub group_size: [i32; 2],
+    pub animations: Vec<i16>,
+    pub param_index: [i32; 2],
+    pub param_start: [f32; 2],
+    pub param_end: [f32; 2],
+    pub fade_in_time: f32,
+    pub fade_out_time: f32,
+    pub entry_node: i32,
+    pub exit_node: i32,
+    pub node_flags: i32,
+    pub keyvalues: String,
+    /// Only present in version 49
+    pub activity_modifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    /// Index of the material in the current skin
+    pub material: i32,
+    pub vertex_count: i32,
+    /// Index of the first vertex, relative to the model
+    pub vertex_offset: i32,
+    pub flex_count: i32,
+    pub material_type: i32,
+    pub material_param: i32,
+    pub id: i32,
+    pub center: [f32; 3],
+    pub lod_vertex_counts: [i32; 8],
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub name: String,
+    pub kind: i32,
+    pub bounding_radius: f32,
+    pub meshes: Vec<Mesh>,
+    pub vertex_count: i32,
+    /// Offsets of the vertices and tangents of the model in the .vvd, in bytes
+    pub vertex_index: i32,
+    pub tangents_index: i32,
+    pub eyeball_count: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BodyPart {
+    pub name: String,
+    /// Multiplier of the model index in the body value
+    pub base: i32,
+    pub models: Vec<Model>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub flags: u32,
+    pub bone: i32,
+    /// Transform relative to the bone
+    pub local: [[f32; 4]; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct FlexController {
+    pub kind: String,
     pub name: String,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// `$includemodel`, a model that animations are shared from
+#[derive(Debug, Clone)]
+pub struct IncludeModel {
+    pub label: String,
+    pub name: String,
+}
+
+/// Secondary header (`studiohdr2_t`)
+#[derive(Debug, Clone)]
+pub struct StudioHeader2 {
+    pub src_bone_transform_count: i32,
+    pub illum_position_attachment: i32,
+    pub max_eye_deflection: f32,
+    /// Full model name, in case it doesn't fit the 64 bytes of the main header
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MDLSkin(pub Vec<u16>);
+
+/// Contents of `studiohdr_t` and the structures it refers to
+#[derive(Debug, Clone)]
+pub struct StudioHeader {
+    pub version: i32,
+    /// Shared with the .vvd, .vtx and .phy of the model
+    pub checksum: i32,
+    pub name: String,
+    pub length: i32,
+
+    pub eye_position: [f32; 3],
+    pub illum_position: [f32; 3],
+    pub hull_min: [f32; 3],
+    pub hull_max: [f32; 3],
+    pub view_bbmin: [f32; 3],
+    pub view_bbmax: [f32; 3],
+    pub flags: i32,
+
+    pub bones: Vec<Bone>,
+    pub bone_controllers: Vec<BoneController>,
+    pub hitbox_sets: Vec<HitboxSet>,
+    pub animations: Vec<Animation>,
+    pub sequences: Vec<Sequence>,
 
     pub texture_names: Vec<String>,
     pub texture_dirs: Vec<String>,
 
     pub skin_count: u32,
     pub texture_slot_count: u32,
-    pub skins: Vec<MDLSkin>
+    pub skins: Vec<MDLSkin>,
+
+    pub body_parts: Vec<BodyPart>,
+    pub attachments: Vec<Attachment>,
+    pub flex_descs: Vec<String>,
+    pub flex_controllers: Vec<FlexController>,
+    pub surface_prop: String,
+    pub keyvalues: String,
+    pub mass: f32,
+    pub contents: i32,
+    pub include_models: Vec<IncludeModel>,
+    /// Name of the .ani file holding animation blocks, if any
+    pub anim_block_name: String,
+    pub anim_block_count: i32,
+
+    pub header2: Option<StudioHeader2>,
 }
 
-#[derive(Debug)]
-pub struct MDLSkin(pub Vec<u16>);
+trait ReadStudioExt: ReadBytesExt {
+    fn read_vector(&mut self) -> Result<[f32; 3], io::Error> {
+        Ok([
+            self.read_f32::<LittleEndian>()?,
+            self.read_f32::<LittleEndian>()?,
+            self.read_f32::<LittleEndian>()?,
+        ])
+    }
+
+    fn read_quaternion(&mut self) -> Result<[f32; 4], io::Error> {
+        let mut q = [0.0; 4];
+        self.read_f32_into::<LittleEndian>(&mut q)?;
+        Ok(q)
+    }
+
+    fn read_matrix(&mut self) -> Result<[[f32; 4]; 3], io::Error> {
+        let mut m = [[0.0; 4]; 3];
+        for row in &mut m {
+            self.read_f32_into::<LittleEndian>(row)?;
+        }
+        Ok(m)
+    }
 
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error(display = "IO error: {}", _0)]
-    Io(#[error(source)] std::io::Error),
-    #[error(display = "File does not have a valid mdl header")]
-    InvalidHeader
+    fn read_count_index(&mut self) -> Result<(i32, i32), io::Error> {
+        Ok((
+            self.read_i32::<LittleEndian>()?,
+            self.read_i32::<LittleEndian>()?,
+        ))
+    }
 }
+impl<R: io::Read> ReadStudioExt for R {}
+
+type Reader<'a> = Cursor<&'a [u8]>;
 
-trait ReadPaddedCStrExt: Read {
-    fn read_padded_cstr(&mut self, size: usize) -> Result<String, io::Error> {
-        let mut buf = Vec::with_capacity(size);
-        self.take(size as u64).read_to_end(&mut buf)?;
-        
-        let end = buf.iter().position(|&b| b == 0).map_or(0, |i| i + 1);
-        Ok(CStr::from_bytes_with_nul(&buf[..end]).unwrap().to_str().unwrap().to_owned())
+/// Checks that `count` structures of `len` bytes at `offset` are inside the file,
+/// returning the count and offset. Negative counts are treated as empty tables
+fn table(
+    reader: &Reader,
+    what: &'static str,
+    count: i64,
+    offset: i64,
+    len: u64,
+) -> Result<(u64, u64), Error> {
+    if count <= 0 {
+        return Ok((0, 0));
+    }
+    let end = (count as u64)
+        .checked_mul(len)
+        .and_then(|l| l.checked_add(offset as u64));
+    match end {
+        Some(end) if offset >= 0 && end <= reader.get_ref().len() as u64 => {
+            Ok((count as u64, offset as u64))
+        }
+        _ => Err(Error::BadOffset(what, offset)),
     }
 }
-impl<W: io::Read> ReadPaddedCStrExt for W {}
 
+/// Reads a null terminated string from a field of at most `size` bytes
+fn read_padded_cstr(reader: &mut Reader, size: usize) -> Result<String, Error> {
+    let pos = reader.position();
+    let mut buf = vec![];
+    reader.take(size as u64).read_to_end(&mut buf)?;
 
-pub fn read(bytes: &mut Vec<u8>) -> Result<PartialMDL, Box<dyn std::error::Error>> {
-    let mut reader = Cursor::new(bytes);
+    let end = buf
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(Error::BadString(pos))?;
+    buf.truncate(end);
+    String::from_utf8(buf).map_err(|_| Error::BadString(pos))
+}
 
-    let mut magic_buf = [0; 4];
-    reader.read_exact(&mut magic_buf)?;
-    if &magic_buf != b"IDST" {
-        return Err(Box::new(Error::InvalidHeader));
+/// Reads null terminated string at given offset. Offsets in studio structures are
+/// relative to the structure, so `base` is the offset of the structure and
+/// `index` the offset stored in it. A zero index means no string
+fn read_string_at(reader: &Reader, base: u64, index: i32) -> Result<String, Error> {
+    if index == 0 {
+        return Ok(String::new());
     }
+    let off = base as i64 + index as i64;
+    // search the underlying data, so only the string itself is copied
+    let bytes = usize::try_from(off)
+        .ok()
+        .and_then(|off| reader.get_ref().get(off..))
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or(Error::BadOffset("string", off))?;
+    let end = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(Error::BadString(off as u64))?;
+    std::str::from_utf8(&bytes[..end])
+        .map(str::to_string)
+        .map_err(|_| Error::BadString(off as u64))
+}
 
-    reader.read_u32::<LittleEndian>()?; // version
-    reader.read_u32::<LittleEndian>()?; // checksum
+/// Reads keyvalue text of `size` bytes at given offset
+fn read_keyvalues(reader: &mut Reader, offset: i64, size: i32) -> Result<String, Error> {
+    let (size, offset) = table(reader, "keyvalue", size as i64, offset, 1)?;
+    if size == 0 {
+        return Ok(String::new());
+    }
+    reader.seek(SeekFrom::Start(offset))?;
+    read_padded_cstr(reader, size as usize)
+}
 
-    let name = reader.read_padded_cstr(64)?;
+fn read_bones(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<Bone>, Error> {
+    let mut bones = vec![];
+    for i in 0..count {
+        let base = index + i * BONE_LEN;
+        reader.seek(SeekFrom::Start(base))?;
 
-    // skip to texture_count
-    reader.seek(SeekFrom::Current((
-        // size + vectors + flags
-        4+12+12+12+12+12+12+4 +
-        // misc offsets
-        8+8+8+8+8+8
-    ) as i64))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        let parent = reader.read_i32::<LittleEndian>()?;
+        let mut bone_controllers = [0; 6];
+        reader.read_i32_into::<LittleEndian>(&mut bone_controllers)?;
+        let position = reader.read_vector()?;
+        let quaternion = reader.read_quaternion()?;
+        let rotation = reader.read_vector()?;
+        let position_scale = reader.read_vector()?;
+        let rotation_scale = reader.read_vector()?;
+        let pose_to_bone = reader.read_matrix()?;
+        let alignment = reader.read_quaternion()?;
+        let flags = reader.read_i32::<LittleEndian>()?;
+        let procedural_type = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // procindex
+        let physics_bone = reader.read_i32::<LittleEndian>()?;
+        let surface_prop_index = reader.read_i32::<LittleEndian>()?;
+        let contents = reader.read_i32::<LittleEndian>()?;
 
-    let texture_count = reader.read_u32::<LittleEndian>()?;
-    let texture_offset = reader.read_u32::<LittleEndian>()?;
+        bones.push(Bone {
+            name: read_string_at(reader, base, name_index)?,
+            parent,
+            bone_controllers,
+            position,
+            quaternion,
+            rotation,
+            position_scale,
+            rotation_scale,
+            pose_to_bone,
+            alignment,
+            flags,
+            procedural_type,
+            physics_bone,
+            surface_prop: read_string_at(reader, base, surface_prop_index)?,
+            contents,
+        });
+    }
+    Ok(bones)
+}
 
-    let texturedir_count = reader.read_u32::<LittleEndian>()?;
-    let texturedir_offset = reader.read_u32::<LittleEndian>()?;
+fn read_bone_controllers(
+    reader: &mut Reader,
+    count: u64,
+    index: u64,
+) -> Result<Vec<BoneController>, Error> {
+    let mut controllers = vec![];
+    for i in 0..count {
+        reader.seek(SeekFrom::Start(index + i * BONE_CONTROLLER_LEN))?;
+        controllers.push(BoneController {
+            bone: reader.read_i32::<LittleEndian>()?,
+            kind: reader.read_i32::<LittleEndian>()?,
+            start: reader.read_f32::<LittleEndian>()?,
+            end: reader.read_f32::<LittleEndian>()?,
+            rest: reader.read_i32::<LittleEndian>()?,
+            input_field: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+    Ok(controllers)
+}
 
-    let skinreference_count = reader.read_u32::<LittleEndian>()?;
-    let skinrfamily_count = reader.read_u32::<LittleEndian>()?;
-    let skinreference_index = reader.read_u32::<LittleEndian>()?;
+fn read_hitbox_sets(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<HitboxSet>, Error> {
+    let mut sets = vec![];
+    for i in 0..count {
+        let base = index + i * HITBOX_SET_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        let (hitbox_count, hitbox_index) = reader.read_count_index()?;
+        let (hitbox_count, hitbox_index) = table(
+            reader,
+            "hitbox",
+            hitbox_count as i64,
+            base as i64 + hitbox_index as i64,
+            HITBOX_LEN,
+        )?;
 
-    let mut texture_names = vec!();
-    for i in 0..texture_count {
-        let off: u64 = (texture_offset + i*16*4).into();
-        reader.seek(SeekFrom::Start(off))?;
+        let mut hitboxes = vec![];
+        for j in 0..hitbox_count {
+            let hitbox_base = hitbox_index + j * HITBOX_LEN;
+            reader.seek(SeekFrom::Start(hitbox_base))?;
+            let bone = reader.read_i32::<LittleEndian>()?;
+            let group = reader.read_i32::<LittleEndian>()?;
+            let mins = reader.read_vector()?;
+            let maxs = reader.read_vector()?;
+            let hitbox_name_index = reader.read_i32::<LittleEndian>()?;
+            hitboxes.push(Hitbox {
+                bone,
+                group,
+                mins,
+                maxs,
+                name: read_string_at(reader, hitbox_base, hitbox_name_index)?,
+            });
+        }
 
-        let name_offset = reader.read_u32::<LittleEndian>()?;
-        reader.seek(SeekFrom::Current(name_offset as i64 - 4))?;
+        sets.push(HitboxSet {
+            name: read_string_at(reader, base, name_index)?,
+            hitboxes,
+        });
+    }
+    Ok(sets)
+}
+
+fn read_animations(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<Animation>, Error> {
+    let mut animations = vec![];
+    for i in 0..count {
+        let base = index + i * ANIM_DESC_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        reader.read_i32::<LittleEndian>()?; // baseptr
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        let fps = reader.read_f32::<LittleEndian>()?;
+        let flags = reader.read_i32::<LittleEndian>()?;
+        let frame_count = reader.read_i32::<LittleEndian>()?;
+        // movements and unused
+        reader.seek(SeekFrom::Current(8 + 24))?;
+        let anim_block = reader.read_i32::<LittleEndian>()?;
+        let anim_index = reader.read_i32::<LittleEndian>()?;
+        // ik rules and local hierarchy
+        reader.seek(SeekFrom::Current(20))?;
+        let section_index = reader.read_i32::<LittleEndian>()?;
+        let section_frames = reader.read_i32::<LittleEndian>()?;
 
-        let tex_name = reader.read_padded_cstr(256)?;
-        texture_names.push(tex_name);
+        animations.push(Animation {
+            offset: base as usize,
+            name: read_string_at(reader, base, name_index)?,
+            fps,
+            flags,
+            frame_count,
+            anim_block,
+            anim_index,
+            section_index,
+            section_frames,
+        });
     }
+    Ok(animations)
+}
 
-    let mut texture_dirs = vec!();
-    for i in 0..texturedir_count {
-        let off: u64 = (texturedir_offset + i*4).into();
-        reader.seek(SeekFrom::Start(off))?;
+fn read_events(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<Event>, Error> {
+    let mut events = vec![];
+    for i in 0..count {
+        let base = index + i * EVENT_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let cycle = reader.read_f32::<LittleEndian>()?;
+        let event = reader.read_i32::<LittleEndian>()?;
+        let kind = reader.read_i32::<LittleEndian>()?;
+        let options = read_padded_cstr(reader, 64)?;
+        reader.seek(SeekFrom::Start(base + 76))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        events.push(Event {
+            cycle,
+            event,
+            kind,
+            options,
+            name: read_string_at(reader, base, name_index)?,
+        });
+    }
+    Ok(events)
+}
+
+fn read_sequences(
+    reader: &mut Reader,
+    count: u64,
+    index: u64,
+    version: i32,
+) -> Result<Vec<Sequence>, Error> {
+    let mut sequences = vec![];
+    for i in 0..count {
+        let base = index + i * SEQ_DESC_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        reader.read_i32::<LittleEndian>()?; // baseptr
+        let label_index = reader.read_i32::<LittleEndian>()?;
+        let activity_name_index = reader.read_i32::<LittleEndian>()?;
+        let flags = reader.read_i32::<LittleEndian>()?;
+        let activity = reader.read_i32::<LittleEndian>()?;
+        let activity_weight = reader.read_i32::<LittleEndian>()?;
+        let (event_count, event_index) = reader.read_count_index()?;
+        let mins = reader.read_vector()?;
+        let maxs = reader.read_vector()?;
+        reader.read_i32::<LittleEndian>()?; // numblends
+        let anim_index_index = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // movementindex
+        let group_size = [
+            reader.read_i32::<LittleEndian>()?,
+            reader.read_i32::<LittleEndian>()?,
+        ];
+        let param_index = [
+            reader.read_i32::<LittleEndian>()?,
+            reader.read_i32::<LittleEndian>()?,
+        ];
+        let param_start = [
+            reader.read_f32::<LittleEndian>()?,
+            reader.read_f32::<LittleEndian>()?,
+        ];
+        let param_end = [
+            reader.read_f32::<LittleEndian>()?,
+            reader.read_f32::<LittleEndian>()?,
+        ];
+        reader.read_i32::<LittleEndian>()?; // paramparent
+        let fade_in_time = reader.read_f32::<LittleEndian>()?;
+        let fade_out_time = reader.read_f32::<LittleEndian>()?;
+        let entry_node = reader.read_i32::<LittleEndian>()?;
+        let exit_node = reader.read_i32::<LittleEndian>()?;
+        let node_flags = reader.read_i32::<LittleEndian>()?;
+
+        reader.seek(SeekFrom::Start(base + 172))?;
+        let keyvalue_index = reader.read_i32::<LittleEndian>()?;
+        let keyvalue_size = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // cycleposeindex
+        let modifier_index = reader.read_i32::<LittleEndian>()?;
+        let modifier_count = reader.read_i32::<LittleEndian>()?;
+
+        let (anim_count, anim_index) = table(
+            reader,
+            "sequence animation",
+            group_size[0].max(0) as i64 * group_size[1].max(0) as i64,
+            base as i64 + anim_index_index as i64,
+            2,
+        )?;
+        reader.seek(SeekFrom::Start(anim_index))?;
+        let mut animations = vec![0; anim_count as usize];
+        reader.read_i16_into::<LittleEndian>(&mut animations)?;
+
+        let mut activity_modifiers = vec![];
+        if version >= 49 {
+            let (modifier_count, modifier_index) = table(
+                reader,
+                "activity modifier",
+                modifier_count as i64,
+                base as i64 + modifier_index as i64,
+                4,
+            )?;
+            for j in 0..modifier_count {
+                let modifier_base = modifier_index + j * 4;
+                reader.seek(SeekFrom::Start(modifier_base))?;
+                let name_index = reader.read_i32::<LittleEndian>()?;
+                activity_modifiers.push(read_string_at(reader, modifier_base, name_index)?);
+            }
+        }
+
+        let keyvalues = read_keyvalues(reader, base as i64 + keyvalue_index as i64, keyvalue_size)?;
+        let (event_count, event_index) = table(
+            reader,
+            "event",
+            event_count as i64,
+            base as i64 + event_index as i64,
+            EVENT_LEN,
+        )?;
+
+        sequences.push(Sequence {
+            label: read_string_at(reader, base, label_index)?,
+            activity_name: read_string_at(reader, base, activity_name_index)?,
+            flags,
+            activity,
+            activity_weight,
+            events: read_events(reader, event_count, event_index)?,
+            mins,
+            maxs,
+            group_size,
+            animations,
+            param_index,
+            param_start,
+            param_end,
+            fade_in_time,
+            fade_out_time,
+            entry_node,
+            exit_node,
+            node_flags,
+            keyvalues,
+            activity_modifiers,
+        });
+    }
+    Ok(sequences)
+}
+
+fn read_meshes(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<Mesh>, Error> {
+    let mut meshes = vec![];
+    for i in 0..count {
+        reader.seek(SeekFrom::Start(index + i * MESH_LEN))?;
+        let material = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // modelindex
+        let vertex_count = reader.read_i32::<LittleEndian>()?;
+        let vertex_offset = reader.read_i32::<LittleEndian>()?;
+        let flex_count = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // flexindex
+        let material_type = reader.read_i32::<LittleEndian>()?;
+        let material_param = reader.read_i32::<LittleEndian>()?;
+        let id = reader.read_i32::<LittleEndian>()?;
+        let center = reader.read_vector()?;
+        reader.read_i32::<LittleEndian>()?; // modelvertexdata
+        let mut lod_vertex_counts = [0; 8];
+        reader.read_i32_into::<LittleEndian>(&mut lod_vertex_counts)?;
+
+        meshes.push(Mesh {
+            material,
+            vertex_count,
+            vertex_offset,
+            flex_count,
+            material_type,
+            material_param,
+            id,
+            center,
+            lod_vertex_counts,
+        });
+    }
+    Ok(meshes)
+}
 
-        let abs_offset = reader.read_u32::<LittleEndian>()?;
-        reader.seek(SeekFrom::Start(abs_offset as u64))?;
+fn read_body_parts(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<BodyPart>, Error> {
+    let mut body_parts = vec![];
+    for i in 0..count {
+        let base = index + i * BODY_PART_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        let model_count = reader.read_i32::<LittleEndian>()?;
+        let part_base = reader.read_i32::<LittleEndian>()?;
+        let model_index = reader.read_i32::<LittleEndian>()?;
+        let (model_count, model_index) = table(
+            reader,
+            "model",
+            model_count as i64,
+            base as i64 + model_index as i64,
+            MODEL_LEN,
+        )?;
 
-        let texdir_name = reader.read_padded_cstr(256)?;
+        let mut models = vec![];
+        for j in 0..model_count {
+            let model_base = model_index + j * MODEL_LEN;
+            reader.seek(SeekFrom::Start(model_base))?;
+            let name = read_padded_cstr(reader, 64)?;
+            reader.seek(SeekFrom::Start(model_base + 64))?;
+            let kind = reader.read_i32::<LittleEndian>()?;
+            let bounding_radius = reader.read_f32::<LittleEndian>()?;
+            let (mesh_count, mesh_index) = reader.read_count_index()?;
+            let vertex_count = reader.read_i32::<LittleEndian>()?;
+            let vertex_index = reader.read_i32::<LittleEndian>()?;
+            let tangents_index = reader.read_i32::<LittleEndian>()?;
+            reader.read_count_index()?; // attachments
+            let eyeball_count = reader.read_i32::<LittleEndian>()?;
+            let (mesh_count, mesh_index) = table(
+                reader,
+                "mesh",
+                mesh_count as i64,
+                model_base as i64 + mesh_index as i64,
+                MESH_LEN,
+            )?;
 
-        texture_dirs.push(texdir_name);
+            models.push(Model {
+                name,
+                kind,
+                bounding_radius,
+                meshes: read_meshes(reader, mesh_count, mesh_index)?,
+                vertex_count,
+                vertex_index,
+                tangents_index,
+                eyeball_count,
+            });
+        }
+
+        body_parts.push(BodyPart {
+            name: read_string_at(reader, base, name_index)?,
+            base: part_base,
+            models,
+        });
     }
+    Ok(body_parts)
+}
 
-    reader.seek(SeekFrom::Start(skinreference_index.into()))?;
+fn read_attachments(reader: &mut Reader, count: u64, index: u64) -> Result<Vec<Attachment>, Error> {
+    let mut attachments = vec![];
+    for i in 0..count {
+        let base = index + i * ATTACHMENT_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        let bone = reader.read_i32::<LittleEndian>()?;
+        let local = reader.read_matrix()?;
+        attachments.push(Attachment {
+            name: read_string_at(reader, base, name_index)?,
+            flags,
+            bone,
+            local,
+        });
+    }
+    Ok(attachments)
+}
 
-    let width = skinrfamily_count as usize;
-    let height = skinreference_count as usize;
+/// Reads the skin table, returning skins and the number of texture slots they replace
+fn read_skins(
+    reader: &mut Reader,
+    skinreference_count: i32,
+    skinrfamily_count: i32,
+    skinreference_index: i32,
+) -> Result<(Vec<MDLSkin>, usize), Error> {
+    let width = skinrfamily_count.max(0) as usize;
+    let height = skinreference_count.max(0) as usize;
+    if width == 0 || height == 0 {
+        return Ok(((0..width).map(|_| MDLSkin(vec![])).collect(), 0));
+    }
+
+    let (_, skinreference_index) = table(
+        reader,
+        "skin",
+        width as i64 * height as i64,
+        skinreference_index as i64,
+        2,
+    )?;
+    reader.seek(SeekFrom::Start(skinreference_index))?;
 
     // Base 1d array
     // Vector of 'width' elements slices
@@ -124,7 +783,7 @@ pub fn read(bytes: &mut Vec<u8>) -> Result<PartialMDL, Box<dyn std::error::Error
     let last_column = {
         let mut last_different_column = 0;
         let mut last_unique_column = 0;
-        let mut unseen_indexes: HashSet<u16> = (0..(skinreference_count as u16)).collect();
+        let mut unseen_indexes: HashSet<u16> = (0..height).map(|i| i as u16).collect();
         for x in 0..height {
             for y in 0..width {
                 if skin_table[x][0] != skin_table[x][y] {
@@ -139,21 +798,307 @@ pub fn read(bytes: &mut Vec<u8>) -> Result<PartialMDL, Box<dyn std::error::Error
         last_different_column.max(last_unique_column)
     };
 
-    let mut skins = vec!();
-    for skin_index in 0..skinrfamily_count {
-        let mut skin = vec!();
-        for replaced_tex_id in 0..=last_column {
-            skin.push(skin_table[replaced_tex_id as usize][skin_index as usize]);
-        }
-        skins.push(MDLSkin(skin));
+    let skins = (0..width)
+        .map(|skin_index| {
+            MDLSkin(
+                skin_table[..=last_column]
+                    .iter()
+                    .map(|row| row[skin_index])
+                    .collect(),
+            )
+        })
+        .collect();
+
+    Ok((skins, last_column + 1))
+}
+
+pub fn read(bytes: &[u8]) -> Result<StudioHeader, Error> {
+    let mut reader = Cursor::new(bytes);
+
+    let mut magic_buf = [0; 4];
+    reader.read_exact(&mut magic_buf)?;
+    if &magic_buf != b"IDST" {
+        return Err(Error::InvalidHeader);
     }
 
-    Ok(PartialMDL {
-        name: name,
-        texture_names: texture_names,
-        texture_dirs: texture_dirs,
-        skin_count: skinrfamily_count,
-        texture_slot_count: last_column as u32 + 1,
-        skins: skins
+    let version = reader.read_i32::<LittleEndian>()?;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let checksum = reader.read_i32::<LittleEndian>()?;
+
+    let name = read_padded_cstr(&mut reader, 64)?;
+    reader.seek(SeekFrom::Start(76))?;
+    let length = reader.read_i32::<LittleEndian>()?;
+
+    let eye_position = reader.read_vector()?;
+    let illum_position = reader.read_vector()?;
+    let hull_min = reader.read_vector()?;
+    let hull_max = reader.read_vector()?;
+    let view_bbmin = reader.read_vector()?;
+    let view_bbmax = reader.read_vector()?;
+    let flags = reader.read_i32::<LittleEndian>()?;
+
+    let bones = reader.read_count_index()?;
+    let bone_controllers = reader.read_count_index()?;
+    let hitbox_sets = reader.read_count_index()?;
+    let anims = reader.read_count_index()?;
+    let seqs = reader.read_count_index()?;
+    reader.read_i32::<LittleEndian>()?; // activitylistversion
+    reader.read_i32::<LittleEndian>()?; // eventsindexed
+
+    let textures = reader.read_count_index()?;
+    let texture_dirs = reader.read_count_index()?;
+
+    let skinreference_count = reader.read_i32::<LittleEndian>()?;
+    let skinrfamily_count = reader.read_i32::<LittleEndian>()?;
+    let skinreference_index = reader.read_i32::<LittleEndian>()?;
+
+    let body_parts = reader.read_count_index()?;
+    let attachments = reader.read_count_index()?;
+    // nodes
+    reader.seek(SeekFrom::Current(12))?;
+    let flex_descs = reader.read_count_index()?;
+    let flex_controllers = reader.read_count_index()?;
+    // flex rules, ik chains, mouths and pose parameters
+    reader.seek(SeekFrom::Current(32))?;
+    let surface_prop_index = reader.read_i32::<LittleEndian>()?;
+    let keyvalue_index = reader.read_i32::<LittleEndian>()?;
+    let keyvalue_size = reader.read_i32::<LittleEndian>()?;
+    // ik autoplay locks
+    reader.seek(SeekFrom::Current(8))?;
+    let mass = reader.read_f32::<LittleEndian>()?;
+    let contents = reader.read_i32::<LittleEndian>()?;
+    let include_models = reader.read_count_index()?;
+    reader.read_i32::<LittleEndian>()?; // virtualModel
+    let anim_block_name_index = reader.read_i32::<LittleEndian>()?;
+    let anim_block_count = reader.read_i32::<LittleEndian>()?;
+    reader.seek(SeekFrom::Start(400))?;
+    let header2_index = reader.read_i32::<LittleEndian>()?;
+
+    let table_at = |reader: &Reader, what, (count, index): (i32, i32), len| {
+        table(reader, what, count as i64, index as i64, len)
+    };
+    let (bone_count, bone_index) = table_at(&reader, "bone", bones, BONE_LEN)?;
+    let (bone_controller_count, bone_controller_index) = table_at(
+        &reader,
+        "bone controller",
+        bone_controllers,
+        BONE_CONTROLLER_LEN,
+    )?;
+    let (hitbox_set_count, hitbox_set_index) =
+        table_at(&reader, "hitbox set", hitbox_sets, HITBOX_SET_LEN)?;
+    let (anim_count, anim_index) = table_at(&reader, "animation", anims, ANIM_DESC_LEN)?;
+    let (seq_count, seq_index) = table_at(&reader, "sequence", seqs, SEQ_DESC_LEN)?;
+    let (texture_count, texture_offset) = table_at(&reader, "texture", textures, TEXTURE_LEN)?;
+    let (texturedir_count, texturedir_offset) = table_at(&reader, "texture dir", texture_dirs, 4)?;
+    let (body_part_count, body_part_index) =
+        table_at(&reader, "body part", body_parts, BODY_PART_LEN)?;
+    let (attachment_count, attachment_index) =
+        table_at(&reader, "attachment", attachments, ATTACHMENT_LEN)?;
+    let (flex_desc_count, flex_desc_index) = table_at(&reader, "flex", flex_descs, 4)?;
+    let (flex_controller_count, flex_controller_index) = table_at(
+        &reader,
+        "flex controller",
+        flex_controllers,
+        FLEX_CONTROLLER_LEN,
+    )?;
+    let (include_model_count, include_model_index) =
+        table_at(&reader, "include model", include_models, MODEL_GROUP_LEN)?;
+
+    let mut texture_names = vec![];
+    for i in 0..texture_count {
+        let off = texture_offset + i * TEXTURE_LEN;
+        reader.seek(SeekFrom::Start(off))?;
+        let name_offset = reader.read_i32::<LittleEndian>()?;
+        texture_names.push(read_string_at(&reader, off, name_offset)?);
+    }
+
+    let mut texture_dirs = vec![];
+    for i in 0..texturedir_count {
+        reader.seek(SeekFrom::Start(texturedir_offset + i * 4))?;
+        let abs_offset = reader.read_i32::<LittleEndian>()?;
+        texture_dirs.push(read_string_at(&reader, 0, abs_offset)?);
+    }
+
+    let (skins, texture_slot_count) = read_skins(
+        &mut reader,
+        skinreference_count,
+        skinrfamily_count,
+        skinreference_index,
+    )?;
+
+    let mut flex_descs = vec![];
+    for i in 0..flex_desc_count {
+        let base = flex_desc_index + i * 4;
+        reader.seek(SeekFrom::Start(base))?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        flex_descs.push(read_string_at(&reader, base, name_index)?);
+    }
+
+    let mut flex_controllers = vec![];
+    for i in 0..flex_controller_count {
+        let base = flex_controller_index + i * FLEX_CONTROLLER_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let kind_index = reader.read_i32::<LittleEndian>()?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // localToGlobal
+        let min = reader.read_f32::<LittleEndian>()?;
+        let max = reader.read_f32::<LittleEndian>()?;
+        flex_controllers.push(FlexController {
+            kind: read_string_at(&reader, base, kind_index)?,
+            name: read_string_at(&reader, base, name_index)?,
+            min,
+            max,
+        });
+    }
+
+    let mut include_models = vec![];
+    for i in 0..include_model_count {
+        let base = include_model_index + i * MODEL_GROUP_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let label_index = reader.read_i32::<LittleEndian>()?;
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        include_models.push(IncludeModel {
+            label: read_string_at(&reader, base, label_index)?,
+            name: read_string_at(&reader, base, name_index)?,
+        });
+    }
+
+    let keyvalues = read_keyvalues(&mut reader, keyvalue_index as i64, keyvalue_size)?;
+
+    let header2 = if header2_index > 0 {
+        let (_, header2_index) = table(&reader, "studiohdr2", 1, header2_index as i64, 24)?;
+        reader.seek(SeekFrom::Start(header2_index))?;
+        let src_bone_transform_count = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // srcbonetransformindex
+        let illum_position_attachment = reader.read_i32::<LittleEndian>()?;
+        let max_eye_deflection = reader.read_f32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // linearboneindex
+        let name_index = reader.read_i32::<LittleEndian>()?;
+        Some(StudioHeader2 {
+            src_bone_transform_count,
+            illum_position_attachment,
+            max_eye_deflection,
+            name: read_string_at(&reader, header2_index, name_index)?,
+        })
+    } else {
+        None
+    };
+
+    Ok(StudioHeader {
+        version,
+        checksum,
+        name,
+        length,
+        eye_position,
+        illum_position,
+        hull_min,
+        hull_max,
+        view_bbmin,
+        view_bbmax,
+        flags,
+        bones: read_bones(&mut reader, bone_count, bone_index)?,
+        bone_controllers: read_bone_controllers(
+            &mut reader,
+            bone_controller_count,
+            bone_controller_index,
+        )?,
+        hitbox_sets: read_hitbox_sets(&mut reader, hitbox_set_count, hitbox_set_index)?,
+        animations: read_animations(&mut reader, anim_count, anim_index)?,
+        sequences: read_sequences(&mut reader, seq_count, seq_index, version)?,
+        texture_names,
+        texture_dirs,
+        skin_count: skinrfamily_count as u32,
+        texture_slot_count: texture_slot_count as u32,
+        skins,
+        body_parts: read_body_parts(&mut reader, body_part_count, body_part_index)?,
+        attachments: read_attachments(&mut reader, attachment_count, attachment_index)?,
+        flex_descs,
+        flex_controllers,
+        surface_prop: read_string_at(&reader, 0, surface_prop_index)?,
+        keyvalues,
+        mass,
+        contents,
+        include_models,
+        anim_block_name: read_string_at(&reader, 0, anim_block_name_index)?,
+        anim_block_count,
+        header2,
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    fn put_i32(buf: &mut [u8], off: usize, v: i32) {
+        buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    fn put_str(buf: &mut Vec<u8>, s: &str) -> i32 {
+        let off = buf.len() as i32;
+        buf.extend_from_slice(s.as_bytes());
+        buf.push(0);
+        off
+    }
+
+    pub(in crate::mdl) fn test_mdl() -> Vec<u8> {
+        let mut buf = vec![0; 408 + BONE_LEN as usize + TEXTURE_LEN as usize + 4];
+        buf[0..4].copy_from_slice(b"IDST");
+        put_i32(&mut buf, 4, 48);
+        buf[12..19].copy_from_slice(b"foo.mdl");
+
+        let bone = 408;
+        put_i32(&mut buf, 156, 1);
+        put_i32(&mut buf, 160, bone);
+        put_i32(&mut buf, bone as usize + 4, -1);
+
+        let texture = bone + BONE_LEN as i32;
+        put_i32(&mut buf, 204, 1);
+        put_i32(&mut buf, 208, texture);
+
+        let skins = texture + TEXTURE_LEN as i32;
+        put_i32(&mut buf, 220, 1);
+        put_i32(&mut buf, 224, 1);
+        put_i32(&mut buf, 228, skins);
+
+        let bone_name = put_str(&mut buf, "root");
+        put_i32(&mut buf, bone as usize, bone_name - bone);
+        let texture_name = put_str(&mut buf, "metal");
+        put_i32(&mut buf, texture as usize, texture_name - texture);
+        let surface_prop = put_str(&mut buf, "metal_box");
+        put_i32(&mut buf, 308, surface_prop);
+        buf[328..332].copy_from_slice(&5.0f32.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn reads_header() {
+        let header = read(&test_mdl()).unwrap();
+        assert_eq!(header.name, "foo.mdl");
+        assert_eq!(header.bones.len(), 1);
+        assert_eq!(header.bones[0].name, "root");
+        assert_eq!(header.bones[0].parent, -1);
+        assert_eq!(header.texture_names, vec!["metal"]);
+        assert_eq!(header.skins.len(), 1);
+        assert_eq!(header.surface_prop, "metal_box");
+        assert_eq!(header.mass, 5.0);
+        assert!(header.header2.is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let mut buf = test_mdl();
+        put_i32(&mut buf, 4, 37);
+        assert!(matches!(read(&buf), Err(Error::UnsupportedVersion(37))));
+
+        let mut buf = test_mdl();
+        put_i32(&mut buf, 156, i32::MAX);
+        assert!(matches!(read(&buf), Err(Error::BadOffset("bone", 408))));
+
+        let mut buf = test_mdl();
+        let len = buf.len();
+        buf[len - 1] = b'x';
+        assert!(matches!(read(&buf), Err(Error::BadString(_))));
+    }
+}
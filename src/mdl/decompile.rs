@@ -0,0 +1,401 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::animation::STUDIO_DELTA;
+use super::binary::{Bone, STUDIO_LOOPING};
+use super::phy::PHY;
+use super::{smd, Error, MDLFile};
+use crate::mesh::{Mesh, Primitive};
+
+/// Model flag for models compiled with `$staticprop`
+pub const STUDIO_STATIC_PROP: i32 = 0x10;
+
+/// Default fade in and out time of sequences
+const DEFAULT_FADE_TIME: f32 = 0.2;
+
+/// Returns a file name without directories or characters unsafe in QC strings,
+/// made unique among `used`
+fn file_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let base = base
+        .strip_suffix(".smd")
+        .or_else(|| base.strip_suffix(".dmx"))
+        .unwrap_or(base);
+    let base: String = base
+        .trim_start_matches('@')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = if base.is_empty() { "unnamed" } else { &base };
+
+    let mut name = format!("{}.smd", base);
+    let mut i = 1;
+    while !used.insert(name.to_lowercase()) {
+        name = format!("{}_{}.smd", base, i);
+        i += 1;
+    }
+    name
+}
+
+fn write_file<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut BufWriter<fs::File>) -> Result<(), std::io::Error>,
+{
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns the bind pose transform of each bone relative to the model
+fn bone_to_pose(bones: &[Bone]) -> Vec<[[f32; 4]; 3]> {
+    bones
+        .iter()
+        .map(|bone| {
+            // pose_to_bone is a rotation and translation, so the inverse
+            // rotation is the transpose
+            let m = bone.pose_to_bone;
+            let mut inverse = [[0.0; 4]; 3];
+            for (r, row) in inverse.iter_mut().enumerate() {
+                for (c, value) in row.iter_mut().take(3).enumerate() {
+                    *value = m[c][r];
+                }
+                row[3] = -(0..3).map(|i| m[i][r] * m[i][3]).sum::<f32>();
+            }
+            inverse
+        })
+        .collect()
+}
+
+/// Builds a mesh of the convex pieces of the collision model, each solid
+/// weighted to its bone
+fn physics_mesh(phy: &PHY, bones: &[Bone]) -> Mesh {
+    let solid_bones = phy.solid_bones();
+    let transforms = bone_to_pose(bones);
+    let mut mesh = Mesh::default();
+    let mut primitive = Primitive {
+        material: "phy".to_string(),
+        ..Default::default()
+    };
+
+    for (solid, bone_name) in phy.solids.iter().zip(&solid_bones) {
+        let bone = bone_name
+            .as_ref()
+            .and_then(|name| bones.iter().position(|b| b.name.eq_ignore_ascii_case(name)))
+            .unwrap_or(0);
+        // a single solid is in model space, ragdoll solids are relative to their bones
+        let transform = transforms.get(bone).filter(|_| phy.solids.len() > 1);
+        let to_model = |p: [f32; 3]| match transform {
+            Some(m) => {
+                let mut out = [0.0; 3];
+                for (o, row) in out.iter_mut().zip(m) {
+                    *o = row[0] * p[0] + row[1] * p[1] + row[2] * p[2] + row[3];
+                }
+                out
+            }
+            None => p,
+        };
+
+        for convex in &solid.convexes {
+            for triangle in &convex.triangles {
+                let p: Vec<[f32; 3]> = triangle
+                    .iter()
+                    .map(|i| to_model(convex.vertices[*i as usize]))
+                    .collect();
+                let (a, b) = (sub(p[1], p[0]), sub(p[2], p[0]));
+                let normal = normalize([
+                    a[1] * b[2] - a[2] * b[1],
+                    a[2] * b[0] - a[0] * b[2],
+                    a[0] * b[1] - a[1] * b[0],
+                ]);
+                for position in p {
+                    let index = mesh.add_vertex(position, normal, [0.0, 0.0]);
+                    mesh.joints.push([bone as u8, 0, 0, 0]);
+                    mesh.weights.push([1.0, 0.0, 0.0, 0.0]);
+                    primitive.indices.push(index);
+                }
+            }
+        }
+    }
+
+    mesh.primitives.push(primitive);
+    mesh
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        [v[0] / length, v[1] / length, v[2] / length]
+    } else {
+        v
+    }
+}
+
+impl MDLFile {
+    /// Writes reference SMDs of each body group model, a physics SMD if the
+    /// model has a .phy, animation SMDs and a QC compiling them into `out`.
+    /// Returns the paths of the written files
+    pub fn decompile(&self, out: &Path) -> Result<Vec<PathBuf>, Error> {
+        let header = &self.header;
+        let stem = self
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "model".to_string());
+        let mut used_names = HashSet::new();
+        let mut written = vec![];
+        let mut qc = String::new();
+
+        fs::create_dir_all(out)?;
+
+        let name = header
+            .header2
+            .as_ref()
+            .map(|h| &h.name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&header.name);
+        writeln!(qc, "$modelname \"{}\"", name).unwrap();
+        for dir in &header.texture_dirs {
+            writeln!(qc, "$cdmaterials \"{}\"", dir).unwrap();
+        }
+        if header.flags & STUDIO_STATIC_PROP != 0 {
+            writeln!(qc, "$staticprop").unwrap();
+        }
+        if !header.surface_prop.is_empty() {
+            writeln!(qc, "$surfaceprop \"{}\"", header.surface_prop).unwrap();
+        }
+        for include in &header.include_models {
+            writeln!(qc, "$includemodel \"{}\"", include.name).unwrap();
+        }
+        writeln!(qc).unwrap();
+
+        // reference meshes
+        let scene = self.scene(0)?;
+        for (body_part, node) in header.body_parts.iter().zip(&scene.nodes) {
+            writeln!(qc, "$bodygroup \"{}\"\n{{", body_part.name).unwrap();
+            for child in &node.children {
+                match &child.mesh {
+                    Some(mesh) => {
+                        let name = file_name(&child.name, &mut used_names);
+                        let path = out.join(&name);
+                        write_file(&path, |w| smd::write_reference(w, &header.bones, mesh))?;
+                        written.push(path);
+                        writeln!(qc, "\tstudio \"{}\"", name).unwrap();
+                    }
+                    None => writeln!(qc, "\tblank").unwrap(),
+                }
+            }
+            writeln!(qc, "}}").unwrap();
+        }
+
+        let skins = self.skins_with_material_names()?;
+        if skins.len() > 1 {
+            writeln!(qc, "\n$texturegroup \"skinfamilies\"\n{{").unwrap();
+            for skin in &skins {
+                let names: Vec<String> = skin.iter().map(|n| format!("\"{}\"", n)).collect();
+                writeln!(qc, "\t{{ {} }}", names.join(" ")).unwrap();
+            }
+            writeln!(qc, "}}").unwrap();
+        }
+
+        let attachments = self.attachments();
+        if !attachments.is_empty() {
+            writeln!(qc).unwrap();
+        }
+        for attachment in &attachments {
+            let [x, y, z] = attachment.position;
+            let [pitch, yaw, roll] = attachment.angles;
+            writeln!(
+                qc,
+                "$attachment \"{}\" \"{}\" {} {} {} rotate {} {} {}",
+                attachment.name, attachment.bone, x, y, z, pitch, yaw, roll
+            )
+            .unwrap();
+        }
+
+        for set in &header.hitbox_sets {
+            writeln!(qc).unwrap();
+            if header.hitbox_sets.len() > 1 || set.name != "default" {
+                writeln!(qc, "$hboxset \"{}\"", set.name).unwrap();
+            }
+            for hitbox in &set.hitboxes {
+                writeln!(
+                    qc,
+                    "$hbox {} \"{}\" {} {} {} {} {} {}",
+                    hitbox.group,
+                    self.bone_name(hitbox.bone),
+                    hitbox.mins[0],
+                    hitbox.mins[1],
+                    hitbox.mins[2],
+                    hitbox.maxs[0],
+                    hitbox.maxs[1],
+                    hitbox.maxs[2]
+                )
+                .unwrap();
+            }
+        }
+
+        // collision model
+        match self.phy() {
+            Ok(phy) => {
+                let name = file_name(&format!("{}_physics", stem), &mut used_names);
+                let path = out.join(&name);
+                let mesh = physics_mesh(&phy, &header.bones);
+                write_file(&path, |w| smd::write_reference(w, &header.bones, &mesh))?;
+                written.push(path);
+
+                let sections = phy.sections();
+                let mass = sections
+                    .iter()
+                    .find(|s| s.name == "editparams")
+                    .and_then(|s| s.get("totalmass"));
+                let command = if phy.solids.len() > 1 {
+                    "$collisionjoints"
+                } else {
+                    "$collisionmodel"
+                };
+                writeln!(qc, "\n{} \"{}\"\n{{", command, name).unwrap();
+                if let Some(mass) = mass {
+                    writeln!(qc, "\t$mass {}", mass).unwrap();
+                }
+                if phy.solids.iter().any(|s| s.convexes.len() > 1) {
+                    writeln!(qc, "\t$concave").unwrap();
+                }
+                writeln!(qc, "}}").unwrap();
+            }
+            Err(Error::MissingFile(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        // animations, referred to by sequences
+        let anims_path = out.join("anims");
+        let mut animation_files = vec![];
+        for (i, animation) in header.animations.iter().enumerate() {
+            let frames = match self.animation_frames(i) {
+                Ok(frames) => frames,
+                Err(Error::ExternalAnimation(_)) => {
+                    animation_files.push(None);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            fs::create_dir_all(&anims_path)?;
+            let name = file_name(&animation.name, &mut used_names);
+            let path = anims_path.join(&name);
+            write_file(&path, |w| smd::write_animation(w, &header.bones, &frames))?;
+            written.push(path);
+            animation_files.push(Some(format!("anims/{}", name)));
+        }
+
+        for sequence in &header.sequences {
+            writeln!(qc).unwrap();
+            let index = sequence.animations.first().map_or(0, |i| *i as usize);
+            let (animation, file) = match (
+                header.animations.get(index),
+                animation_files.get(index).cloned().flatten(),
+            ) {
+                (Some(animation), Some(file)) => (animation, file),
+                _ => {
+                    writeln!(
+                        qc,
+                        "// $sequence \"{}\" uses an animation stored outside the model",
+                        sequence.label
+                    )
+                    .unwrap();
+                    continue;
+                }
+            };
+
+            writeln!(qc, "$sequence \"{}\"\n{{", sequence.label).unwrap();
+            if sequence.animations.len() > 1 {
+                writeln!(
+                    qc,
+                    "\t// blend of {} animations, only the first is included",
+                    sequence.animations.len()
+                )
+                .unwrap();
+            }
+            writeln!(qc, "\t\"{}\"", file).unwrap();
+            writeln!(qc, "\tfps {}", animation.fps).unwrap();
+            if sequence.flags & STUDIO_LOOPING != 0 {
+                writeln!(qc, "\tloop").unwrap();
+            }
+            if animation.flags & STUDIO_DELTA != 0 {
+                writeln!(qc, "\tdelta").unwrap();
+            }
+            if !sequence.activity_name.is_empty() {
+                writeln!(
+                    qc,
+                    "\tactivity \"{}\" {}",
+                    sequence.activity_name, sequence.activity_weight
+                )
+                .unwrap();
+            }
+            if (sequence.fade_in_time - DEFAULT_FADE_TIME).abs() > 1e-4 {
+                writeln!(qc, "\tfadein {}", sequence.fade_in_time).unwrap();
+            }
+            if (sequence.fade_out_time - DEFAULT_FADE_TIME).abs() > 1e-4 {
+                writeln!(qc, "\tfadeout {}", sequence.fade_out_time).unwrap();
+            }
+            let last_frame = (animation.frame_count - 1).max(0) as f32;
+            for event in &sequence.events {
+                let name = if event.name.is_empty() {
+                    event.event.to_string()
+                } else {
+                    event.name.clone()
+                };
+                writeln!(
+                    qc,
+                    "\t{{ event {} {} \"{}\" }}",
+                    name,
+                    (event.cycle * last_frame).round(),
+                    event.options
+                )
+                .unwrap();
+            }
+            writeln!(qc, "}}").unwrap();
+        }
+
+        let keyvalues = header
+            .keyvalues
+            .trim()
+            .strip_prefix("mdlkeyvalue")
+            .map(|kv| kv.trim())
+            .and_then(|kv| kv.strip_prefix('{'))
+            .and_then(|kv| kv.strip_suffix('}'));
+        if let Some(keyvalues) = keyvalues {
+            writeln!(qc, "\n$keyvalues\n{{\n\t{}\n}}", keyvalues.trim()).unwrap();
+        }
+
+        let qc_path = out.join(format!("{}.qc", stem));
+        fs::write(&qc_path, qc)?;
+        written.push(qc_path);
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makes_unique_file_names() {
+        let mut used = HashSet::new();
+        assert_eq!(file_name("models\\box.smd", &mut used), "box.smd");
+        assert_eq!(file_name("Box", &mut used), "Box_1.smd");
+        assert_eq!(file_name("@idle run", &mut used), "idle_run.smd");
+    }
+}
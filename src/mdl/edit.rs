@@ -0,0 +1,158 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::fs;
+use std::path::Path;
+
+use super::{binary, Error, MDLFile};
+
+const NAME_OFFSET: usize = 12;
+const NAME_LEN: usize = 64;
+const LENGTH_OFFSET: usize = 76;
+const TEXTURE_INDEX_OFFSET: usize = 208;
+const TEXTURE_DIR_COUNT_OFFSET: usize = 212;
+const TEXTURE_DIR_INDEX_OFFSET: usize = 216;
+const HEADER2_INDEX_OFFSET: usize = 400;
+/// Offset of `sznameindex` in `studiohdr2_t`
+const HEADER2_NAME_OFFSET: usize = 20;
+const TEXTURE_LEN: usize = 64;
+
+/// Edits of the model are made by appending new strings and tables to the end
+/// of the file and pointing the offsets at them, leaving the old ones unused.
+/// The checksum is kept, so the .vvd, .vtx and .phy still match the model
+impl MDLFile {
+    fn read_i32(&self, offset: usize) -> i32 {
+        LittleEndian::read_i32(&self.bytes[offset..offset + 4])
+    }
+
+    fn write_i32(&mut self, offset: usize, value: i32) {
+        LittleEndian::write_i32(&mut self.bytes[offset..offset + 4], value);
+    }
+
+    /// Pads the file to a multiple of 4 bytes and returns its length
+    fn align(&mut self) -> usize {
+        while !self.bytes.len().is_multiple_of(4) {
+            self.bytes.push(0);
+        }
+        self.bytes.len()
+    }
+
+    /// Appends a null-terminated string, returning its offset in the file
+    fn append_string(&mut self, string: &str) -> usize {
+        let offset = self.bytes.len();
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+
+    /// Updates the length in the header and parses the edited model again
+    fn reparse(&mut self) -> Result<(), Error> {
+        let length = self.align() as i32;
+        self.write_i32(LENGTH_OFFSET, length);
+        self.header = binary::read(&self.bytes)?;
+        Ok(())
+    }
+
+    /// Replaces the folders materials are looked up from, i.e. `$cdmaterials`.
+    /// Folders get a trailing slash if they don't have one
+    pub fn set_texture_dirs(&mut self, dirs: &[String]) -> Result<(), Error> {
+        let mut offsets = vec![];
+        for dir in dirs {
+            let dir = if dir.ends_with('/') || dir.ends_with('\\') {
+                dir.to_string()
+            } else {
+                format!("{}/", dir)
+            };
+            offsets.push(self.append_string(&dir) as i32);
+        }
+
+        let table = self.align();
+        for offset in offsets {
+            self.bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        self.write_i32(TEXTURE_DIR_COUNT_OFFSET, dirs.len() as i32);
+        self.write_i32(TEXTURE_DIR_INDEX_OFFSET, table as i32);
+        self.reparse()
+    }
+
+    /// Renames every texture (material name) matching `from` regardless of case
+    pub fn rename_texture(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        let matching: Vec<usize> = self
+            .header
+            .texture_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| name.eq_ignore_ascii_case(from))
+            .map(|(i, _)| i)
+            .collect();
+        if matching.is_empty() {
+            return Err(Error::TextureNotFound(from.to_string()));
+        }
+
+        let name = self.append_string(to);
+        let texture_index = self.read_i32(TEXTURE_INDEX_OFFSET) as usize;
+        for i in matching {
+            let texture = texture_index + i * TEXTURE_LEN;
+            self.write_i32(texture, (name - texture) as i32);
+        }
+        self.reparse()
+    }
+
+    /// Sets the name the model is known by in the engine, e.g. "props/crate.mdl".
+    /// Names longer than the header allows are stored in the secondary header
+    pub fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        let header2 = self.read_i32(HEADER2_INDEX_OFFSET);
+        if name.len() >= NAME_LEN && header2 <= 0 {
+            return Err(Error::NameTooLong(name.to_string()));
+        }
+
+        // the header keeps as much of the name as fits
+        let truncated = &name.as_bytes()[..name.len().min(NAME_LEN - 1)];
+        let field = &mut self.bytes[NAME_OFFSET..NAME_OFFSET + NAME_LEN];
+        field.fill(0);
+        field[..truncated.len()].copy_from_slice(truncated);
+
+        if header2 > 0 {
+            let header2 = header2 as usize;
+            let offset = self.append_string(name);
+            self.write_i32(header2 + HEADER2_NAME_OFFSET, (offset - header2) as i32);
+        }
+        self.reparse()
+    }
+
+    /// Writes the model, e.g. after editing it
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, &self.bytes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_strings() {
+        let mut mdl = MDLFile::from_bytes(binary::tests::test_mdl()).unwrap();
+        let checksum = mdl.header.checksum;
+
+        mdl.set_texture_dirs(&["models/ourteam".to_string(), "shared\\".to_string()])
+            .unwrap();
+        mdl.rename_texture("METAL", "steel").unwrap();
+        mdl.set_name("ourteam/foo.mdl").unwrap();
+
+        let header = binary::read(&mdl.bytes).unwrap();
+        assert_eq!(header.texture_dirs, vec!["models/ourteam/", "shared\\"]);
+        assert_eq!(header.texture_names, vec!["steel"]);
+        assert_eq!(header.name, "ourteam/foo.mdl");
+        assert_eq!(header.checksum, checksum);
+        assert_eq!(header.length as usize, mdl.bytes.len());
+
+        assert!(matches!(
+            mdl.rename_texture("wood", "oak"),
+            Err(Error::TextureNotFound(_))
+        ));
+        assert!(matches!(
+            mdl.set_name(&"x".repeat(64)),
+            Err(Error::NameTooLong(_))
+        ));
+    }
+}
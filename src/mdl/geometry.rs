@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use super::binary;
+use super::vtx;
+use super::vvd;
+use super::{Error, MDLFile};
+use crate::mesh::{Joint, Mesh, Node, Primitive, Scene};
+
+const VVD_VERTEX_LEN: i32 = 48;
+
+/// Returns joints and weights of a vertex, with weights summing to 1
+fn skin_weights(weights: &vvd::BoneWeights) -> ([u8; 4], [f32; 4]) {
+    let mut joints = [0; 4];
+    let mut normalized = [0.0; 4];
+    let count = (weights.bone_count as usize).min(3);
+    let total: f32 = weights.weights[..count].iter().sum();
+    if total <= 0.0 {
+        normalized[0] = 1.0;
+        return (joints, normalized);
+    }
+    for i in 0..count {
+        joints[i] = weights.bones[i];
+        normalized[i] = weights.weights[i] / total;
+    }
+    (joints, normalized)
+}
+
+impl MDLFile {
+    /// Materials of a mesh in each skin
+    fn mesh_materials(&self, skins: &[Vec<String>], mesh: &binary::Mesh) -> Vec<String> {
+        let slot = mesh.material.max(0) as usize;
+        if skins.is_empty() {
+            return self
+                .header
+                .texture_names
+                .get(slot)
+                .cloned()
+                .into_iter()
+                .collect();
+        }
+        skins
+            .iter()
+            .map(|skin| skin.get(slot).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    fn model_mesh(
+        &self,
+        model: &binary::Model,
+        vtx_lod: &vtx::ModelLod,
+        vertices: &[vvd::Vertex],
+        skins: &[Vec<String>],
+    ) -> Result<Mesh, Error> {
+        let mut mesh = Mesh::default();
+        // vertex indices are relative to the first vertex of the model
+        let model_start = (model.vertex_index / VVD_VERTEX_LEN).max(0) as usize;
+
+        for (i, mdl_mesh) in model.meshes.iter().enumerate() {
+            let vtx_mesh = vtx_lod.meshes.get(i).ok_or(Error::IndexOutOfRange(
+                "vtx mesh",
+                i,
+                vtx_lod.meshes.len(),
+            ))?;
+            let mesh_start = model_start + mdl_mesh.vertex_offset.max(0) as usize;
+
+            let materials = self.mesh_materials(skins, mdl_mesh);
+            let mut primitive = Primitive {
+                material: materials.first().cloned().unwrap_or_default(),
+                ..Default::default()
+            };
+            if materials.iter().any(|m| *m != primitive.material) {
+                primitive.variant_materials = materials;
+            }
+
+            // strip groups of a mesh share the mdl mesh vertices
+            let mut mesh_vertices = HashMap::new();
+            for group in &vtx_mesh.strip_groups {
+                let mut group_vertices = vec![];
+                for vertex in &group.vertices {
+                    let index = mesh_start + vertex.original_mesh_vertex as usize;
+                    let v = vertices.get(index).ok_or(Error::IndexOutOfRange(
+                        "vertex",
+                        index,
+                        vertices.len(),
+                    ))?;
+                    let mesh_index = *mesh_vertices.entry(index).or_insert_with(|| {
+                        let (joints, weights) = skin_weights(&v.bone_weights);
+                        mesh.joints.push(joints);
+                        mesh.weights.push(weights);
+                        mesh.add_vertex(v.position, v.normal, v.uv)
+                    });
+                    group_vertices.push(mesh_index);
+                }
+
+                let group_vertex = |i: u16| {
+                    group_vertices
+                        .get(i as usize)
+                        .copied()
+                        .ok_or(Error::IndexOutOfRange(
+                            "strip group vertex",
+                            i as usize,
+                            group_vertices.len(),
+                        ))
+                };
+                for [a, b, c] in group.triangles() {
+                    let (a, b, c) = (group_vertex(a)?, group_vertex(b)?, group_vertex(c)?);
+                    // triangles are wound clockwise in Source
+                    primitive.indices.extend_from_slice(&[a, c, b]);
+                }
+            }
+
+            mesh.primitives.push(primitive);
+        }
+
+        Ok(mesh)
+    }
+
+    /// Builds a scene of given LOD from the model and its .vvd and .vtx. Each
+    /// body part is a node with a child node per model, skinned to the bones of
+    /// the model. Materials are those of the first skin, with every skin as a
+    /// material variant
+    pub fn scene(&self, lod: usize) -> Result<Scene, Error> {
+        let vvd = self.vvd()?;
+        let vtx = self.vtx()?;
+        let lod_count = vvd.lod_count.max(0) as usize;
+        if lod >= lod_count {
+            return Err(Error::IndexOutOfRange("LOD", lod, lod_count));
+        }
+        let vertices = vvd.lod_vertices(lod)?;
+        let skins = self.skins_with_material_names()?;
+
+        let mut nodes = vec![];
+        for (i, body_part) in self.header.body_parts.iter().enumerate() {
+            let vtx_body_part = vtx.body_parts.get(i).ok_or(Error::IndexOutOfRange(
+                "vtx body part",
+                i,
+                vtx.body_parts.len(),
+            ))?;
+
+            let mut children = vec![];
+            for (j, model) in body_part.models.iter().enumerate() {
+                let mesh = if model.meshes.is_empty() {
+                    None
+                } else {
+                    let vtx_lod = vtx_body_part
+                        .models
+                        .get(j)
+                        .and_then(|m| m.lods.get(lod))
+                        .ok_or(Error::IndexOutOfRange(
+                            "vtx model",
+                            j,
+                            vtx_body_part.models.len(),
+                        ))?;
+                    Some(self.model_mesh(model, vtx_lod, &vertices, &skins)?)
+                };
+
+                children.push(Node {
+                    name: if model.name.is_empty() {
+                        format!("{} {}", body_part.name, j)
+                    } else {
+                        model.name.clone()
+                    },
+                    mesh,
+                    children: vec![],
+                });
+            }
+
+            nodes.push(Node {
+                name: body_part.name.clone(),
+                mesh: None,
+                children,
+            });
+        }
+
+        let joints = self
+            .header
+            .bones
+            .iter()
+            .map(|bone| Joint {
+                name: bone.name.clone(),
+                parent: usize::try_from(bone.parent).ok(),
+                translation: bone.position,
+                rotation: bone.quaternion,
+                inverse_bind: bone.pose_to_bone,
+            })
+            .collect();
+
+        let variants = if skins.len() > 1 {
+            (0..skins.len()).map(|i| format!("skin {}", i)).collect()
+        } else {
+            vec![]
+        };
+
+        Ok(Scene {
+            nodes,
+            joints,
+            variants,
+            textures: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_bone_weights() {
+        let weights = vvd::BoneWeights {
+            weights: [0.5, 1.5, 0.7],
+            bones: [3, 4, 5],
+            bone_count: 2,
+        };
+        assert_eq!(
+            skin_weights(&weights),
+            ([3, 4, 0, 0], [0.25, 0.75, 0.0, 0.0])
+        );
+        assert_eq!(
+            skin_weights(&vvd::BoneWeights::default()),
+            ([0; 4], [1.0, 0.0, 0.0, 0.0])
+        );
+    }
+}
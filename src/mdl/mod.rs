@@ -1,141 +1,642 @@
+use crate::dependency::{DependencyGraph, NodeRef};
+use crate::search_path::{self, normalize_path, ContentLocation, DirectoryIndex, SearchPath};
+use err_derive::Error;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
-use crate::dependency::DependencyGraph;
 
-mod binary;
+pub mod animation;
+pub mod binary;
+pub mod decompile;
+pub mod edit;
+pub mod geometry;
+pub mod phy;
+pub mod smd;
+pub mod summary;
+pub mod vtx;
+pub mod vvd;
 
-#[derive(Debug, Clone)]
-pub enum MDLDependency {
+pub use binary::StudioHeader;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(display = "IO error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "File does not have a valid header")]
+    InvalidHeader,
+    #[error(display = "Unsupported version {}", _0)]
+    UnsupportedVersion(i32),
+    #[error(display = "{} offset {} is outside the file", _0, _1)]
+    BadOffset(&'static str, i64),
+    #[error(
+        display = "String at offset {} is not terminated or not valid UTF-8",
+        _0
+    )]
+    BadString(u64),
+    #[error(display = "{} index {} is out of range ({} available)", _0, _1, _2)]
+    IndexOutOfRange(&'static str, usize, usize),
+    #[error(display = "No {} found next to the model", _0)]
+    MissingFile(&'static str),
+    #[error(
+        display = "Checksum of {} ({:08x}) does not match the model ({:08x})",
+        _0,
+        _1,
+        _2
+    )]
+    ChecksumMismatch(String, i32, i32),
+    #[error(display = "Animation {} is stored outside the model", _0)]
+    ExternalAnimation(String),
+    #[error(display = "No texture named {}", _0)]
+    TextureNotFound(String),
+    #[error(
+        display = "Name {} is longer than 63 characters, which the model has no room for",
+        _0
+    )]
+    NameTooLong(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MDLDependencyKind {
     /// The .mdl itself and auxiliary files
-    Model(PathBuf),
-    Material(PathBuf),
-    Texture(PathBuf),
+    Model,
+    /// Collision model (.phy)
+    Physics,
+    /// Animation blocks loaded on demand (.ani)
+    AnimationBlock,
+    /// Model included with `$includemodel`, usually holding shared animations
+    IncludedModel,
+    /// Gib or ragdoll model referenced by keyvalues or the collision model
+    Gib,
+    Material,
+    Texture,
 }
-impl MDLDependency {
-    pub fn path(&self) -> &Path {
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MDLDependencyStatus {
+    /// In the same assets folder as the model
+    Local(PathBuf),
+    /// Found from a search path root
+    Found(ContentLocation),
+    Missing,
+}
+
+impl fmt::Display for MDLDependencyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            MDLDependency::Model(p) => p,
-            MDLDependency::Material(p) => p,
-            MDLDependency::Texture(p) => p,
+            MDLDependencyStatus::Local(_) => write!(f, "local"),
+            MDLDependencyStatus::Found(_) => write!(f, "found"),
+            MDLDependencyStatus::Missing => write!(f, "missing"),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct MDLDependency {
+    pub kind: MDLDependencyKind,
+    /// Path relative to the assets folder, e.g. "materials/models/props/crate.vmt"
+    pub path: String,
+    pub status: MDLDependencyStatus,
+    /// Why the file couldn't be read, e.g. an included model with an unsupported
+    /// version. Dependencies of such files are not included
+    pub error: Option<String>,
+}
+impl MDLDependency {
+    /// Returns the file on disk, unless the dependency is missing or inside an archive
+    pub fn file(&self) -> Option<&Path> {
+        match &self.status {
+            MDLDependencyStatus::Local(p) => Some(p),
+            MDLDependencyStatus::Found(ContentLocation::File { path, .. }) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the file was found with a name that differs in case
+    pub fn case_differs(&self) -> bool {
+        match &self.status {
+            MDLDependencyStatus::Local(p) => search_path::case_differs(&self.path, p),
+            MDLDependencyStatus::Found(location) => location.case_differs(&self.path),
+            MDLDependencyStatus::Missing => false,
+        }
+    }
+}
+
+/// Inserts a dependency under `parent` unless its path is in `seen`
+fn insert_unseen(
+    deps: &mut DependencyGraph<MDLDependency, MDLDependencyType>,
+    seen: &mut HashSet<String>,
+    parent: NodeRef,
+    dep: MDLDependency,
+    edge: MDLDependencyType,
+) -> Option<NodeRef> {
+    if seen.insert(normalize_path(&dep.path)) {
+        Some(deps.insert_sub(parent, dep, edge))
+    } else {
+        None
+    }
+}
+
+/// Converts a model name as written in keyvalues or QC commands, e.g.
+/// "gibs\\crate_gib1", to a path relative to the assets folder
+fn model_path(name: &str) -> String {
+    let mut path = normalize_path(name);
+    if !path.starts_with("models/") {
+        path = format!("models/{}", path);
+    }
+    if !path.ends_with(".mdl") {
+        path.push_str(".mdl");
+    }
+    path
+}
+
 #[derive(Debug, Clone)]
 pub enum MDLDependencyType {
     Direct,
-    Indirect
+    Indirect,
 }
 
 pub struct MDLFile {
     path: PathBuf,
-    partial: binary::PartialMDL,
+    header: StudioHeader,
+    bytes: Vec<u8>,
 }
 impl MDLFile {
-    pub fn open(p: &Path) -> Result<MDLFile, Box<dyn std::error::Error>> {
-        let mut file = File::open(p)?;
-
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let partial = binary::read(&mut buf)?;
+    pub fn open(p: &Path) -> Result<MDLFile, Error> {
+        let mut mdl = MDLFile::from_bytes(fs::read(p)?)?;
+        mdl.path = p.to_path_buf();
+        Ok(mdl)
+    }
 
+    /// Parses an mdl from memory. As the model has no path, dependencies
+    /// only include materials relative to the working directory
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<MDLFile, Error> {
         Ok(MDLFile {
-            path: p.to_path_buf(),
-            partial: partial,
+            path: PathBuf::new(),
+            header: binary::read(&bytes)?,
+            bytes,
         })
     }
 
+    /// Returns the parsed studio header
+    pub fn header(&self) -> &StudioHeader {
+        &self.header
+    }
+
+    /// Name of the bone at `index`, or an empty string if there's no such bone
+    fn bone_name(&self, index: i32) -> &str {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.header.bones.get(i))
+            .map_or("", |b| b.name.as_str())
+    }
+
+    /// Finds the file next to the model with given extension, regardless of case
+    fn sibling(&self, extension: &str) -> Option<PathBuf> {
+        let path = self.path.with_extension(extension);
+        let name = path.file_name()?.to_string_lossy();
+        DirectoryIndex::new().find(self.path.parent()?, &name)
+    }
+
+    /// Reads the vertex data (.vvd) next to the model
+    pub fn vvd(&self) -> Result<vvd::VVD, Error> {
+        let path = self.sibling("vvd").ok_or(Error::MissingFile("vvd"))?;
+        let vvd = vvd::read(&fs::read(&path)?)?;
+        self.check_checksum(path, vvd.checksum)?;
+        Ok(vvd)
+    }
+
+    /// Reads the mesh data (.vtx) next to the model, preferring the DirectX 9 version
+    pub fn vtx(&self) -> Result<vtx::VTX, Error> {
+        let path = ["dx90.vtx", "vtx", "dx80.vtx", "sw.vtx"]
+            .iter()
+            .find_map(|ext| self.sibling(ext))
+            .ok_or(Error::MissingFile("vtx"))?;
+        let vtx = vtx::read(&fs::read(&path)?)?;
+        self.check_checksum(path, vtx.checksum)?;
+        Ok(vtx)
+    }
+
+    /// Reads the collision model (.phy) next to the model
+    pub fn phy(&self) -> Result<phy::PHY, Error> {
+        let path = self.sibling("phy").ok_or(Error::MissingFile("phy"))?;
+        let phy = phy::read(&fs::read(&path)?)?;
+        self.check_checksum(path, phy.checksum)?;
+        Ok(phy)
+    }
+
+    /// Decodes frames of the local animation at `index`, see [`animation::read_frames`]
+    pub fn animation_frames(&self, index: usize) -> Result<Vec<Vec<animation::BonePose>>, Error> {
+        let animation = self
+            .header
+            .animations
+            .get(index)
+            .ok_or(Error::IndexOutOfRange(
+                "animation",
+                index,
+                self.header.animations.len(),
+            ))?;
+        animation::read_frames(&self.bytes, &self.header, animation)
+    }
+
+    fn check_checksum(&self, path: PathBuf, checksum: i32) -> Result<(), Error> {
+        if checksum != self.header.checksum {
+            return Err(Error::ChecksumMismatch(
+                path.display().to_string(),
+                checksum,
+                self.header.checksum,
+            ));
+        }
+        Ok(())
+    }
+
     pub fn assets_path(&self) -> &Path {
-        let cleaned_name = self.partial.name.replace("\\", "/");
+        let cleaned_name = self.header.name.replace("\\", "/");
 
         let parent_traverse_count = cleaned_name.matches("/").count() + 1;
         let mut assets_path = self.path.as_path();
         for _ in 0..=parent_traverse_count {
-            assets_path = assets_path.parent().unwrap();
+            assets_path = assets_path.parent().unwrap_or_else(|| Path::new(""));
         }
         assets_path
     }
 
-    fn discover_texture_path(&self, base_path: &Path, tex_name: &str) -> Option<PathBuf> {
-        for dir in &self.partial.texture_dirs {
-            let s = &format!("{}{}.vmt", dir, tex_name).replace("\\", "/").to_lowercase();
-            let path = base_path.join(s);
-            if path.exists() {
-                return Some(path.to_path_buf());
-            }
+    /// Returns the paths a material may be at, one per texture dir
+    pub fn material_paths(&self, name: &str) -> Vec<String> {
+        self.header
+            .texture_dirs
+            .iter()
+            .map(|dir| normalize_path(&format!("materials/{}{}.vmt", dir, name)))
+            .collect()
+    }
+
+    fn discover_texture_path(&self, index: &DirectoryIndex, tex_name: &str) -> Option<PathBuf> {
+        self.material_paths(tex_name)
+            .iter()
+            .find_map(|p| index.find(self.assets_path(), p))
+    }
+
+    /// Returns path of a file relative to the assets folder
+    fn relative_path(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(self.assets_path()).unwrap_or(path);
+        relative.to_string_lossy().replace('\\', "/")
+    }
+
+    /// Looks up a path relative to the assets folder, first next to the model
+    /// and then from the search path
+    fn locate(&self, path: &str, search_path: &SearchPath) -> MDLDependencyStatus {
+        if let Some(local) = search_path.find_in_folder(self.assets_path(), path) {
+            MDLDependencyStatus::Local(local)
+        } else if let Some(location) = search_path.find(path) {
+            MDLDependencyStatus::Found(location)
+        } else {
+            MDLDependencyStatus::Missing
         }
-        None
     }
 
-    pub fn dependencies(&self) -> Result<DependencyGraph<MDLDependency, MDLDependencyType>, Box<dyn std::error::Error>> {
-        let assets_path = self.assets_path();
-        let materials_path = assets_path.join("materials");
+    /// Names of gib and ragdoll models in the keyvalues and the `break`
+    /// sections of the collision model
+    fn gib_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = phy::tokenize(&self.header.keyvalues)
+            .into_iter()
+            .filter(|t| t.to_lowercase().ends_with(".mdl"))
+            .collect();
+        // the .phy is a sibling dependency even if it can't be read
+        if let Ok(phy) = self.phy() {
+            names.extend(
+                phy.sections()
+                    .iter()
+                    .filter(|s| s.name == "break")
+                    .filter_map(|s| s.get("model"))
+                    .map(|m| m.to_string()),
+            );
+        }
+        names
+    }
 
-        let mut deps = DependencyGraph::new(MDLDependency::Model(self.path.to_path_buf()));
+    /// Resolves files the model needs: auxiliary files next to it, animation
+    /// blocks, materials and their textures, and included and gib models with
+    /// their own dependencies.
+    ///
+    /// Files are looked up from the assets folder of the model first and then
+    /// from the search path. Files found in neither are included as missing
+    pub fn dependencies(
+        &self,
+        search_path: &SearchPath,
+    ) -> Result<DependencyGraph<MDLDependency, MDLDependencyType>, Error> {
+        let path = self.relative_path(&self.path);
+        let mut deps = DependencyGraph::new(MDLDependency {
+            kind: MDLDependencyKind::Model,
+            path: path.clone(),
+            status: MDLDependencyStatus::Local(self.path.to_path_buf()),
+            error: None,
+        });
+        let root = deps.root();
+        let mut seen = HashSet::new();
+        seen.insert(normalize_path(&path));
+        self.add_dependencies(&mut deps, root, search_path, &mut seen)?;
+        Ok(deps)
+    }
 
-        let mdl_containing_folder = self.path.parent().unwrap();
-        let mdl_stem = self.path.file_stem().unwrap().to_str().unwrap();
-        for entry in fs::read_dir(mdl_containing_folder)? {
-            let path = entry?.path();
-            if path.is_file()
-                && path.file_name().unwrap().to_str().unwrap().starts_with(mdl_stem)
-                && path != self.path
-            {
-                deps.insert(MDLDependency::Model(path.to_path_buf()), MDLDependencyType::Direct);
+    /// Adds dependencies of this model under `node`, recursing into included
+    /// and gib models. Paths in `seen` are skipped
+    fn add_dependencies(
+        &self,
+        deps: &mut DependencyGraph<MDLDependency, MDLDependencyType>,
+        node: NodeRef,
+        search_path: &SearchPath,
+        seen: &mut HashSet<String>,
+    ) -> Result<(), Error> {
+        if let (Some(mdl_containing_folder), Some(mdl_stem)) =
+            (self.path.parent(), self.path.file_stem())
+        {
+            // auxiliary files are named e.g. "model.dx90.vtx"
+            let prefix = format!("{}.", mdl_stem.to_string_lossy().to_lowercase());
+            for entry in fs::read_dir(mdl_containing_folder)? {
+                let path = entry?.path();
+                if path.is_file()
+                    && path
+                        .file_name()
+                        .is_some_and(|n| n.to_string_lossy().to_lowercase().starts_with(&prefix))
+                {
+                    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+                    let kind = match extension.as_deref() {
+                        Some("phy") => MDLDependencyKind::Physics,
+                        Some("ani") => MDLDependencyKind::AnimationBlock,
+                        _ => MDLDependencyKind::Model,
+                    };
+                    insert_unseen(
+                        deps,
+                        seen,
+                        node,
+                        MDLDependency {
+                            kind,
+                            path: self.relative_path(&path),
+                            status: MDLDependencyStatus::Local(path),
+                            error: None,
+                        },
+                        MDLDependencyType::Direct,
+                    );
+                }
             }
         }
 
-        let mut discovered_textures = HashSet::new();
-        for mat_name in &self.partial.texture_names {
-            let discovered = self.discover_texture_path(&materials_path, mat_name);
+        if !self.header.anim_block_name.is_empty() {
+            let path = normalize_path(&self.header.anim_block_name);
+            let status = self.locate(&path, search_path);
+            insert_unseen(
+                deps,
+                seen,
+                node,
+                MDLDependency {
+                    kind: MDLDependencyKind::AnimationBlock,
+                    path,
+                    status,
+                    error: None,
+                },
+                MDLDependencyType::Direct,
+            );
+        }
+
+        for mat_name in &self.header.texture_names {
+            let candidates = self.material_paths(mat_name);
+            let (path, status) = candidates
+                .iter()
+                .map(|p| (p.clone(), self.locate(p, search_path)))
+                .find(|(_, status)| *status != MDLDependencyStatus::Missing)
+                .unwrap_or_else(|| {
+                    let path = candidates
+                        .first()
+                        .cloned()
+                        .unwrap_or_else(|| normalize_path(&format!("materials/{}.vmt", mat_name)));
+                    (path, MDLDependencyStatus::Missing)
+                });
+
+            if seen.contains(&path) {
+                continue;
+            }
+            let contents = match &status {
+                MDLDependencyStatus::Local(p) => Some(fs::read(p)),
+                MDLDependencyStatus::Found(location) => Some(search_path.read(location)),
+                MDLDependencyStatus::Missing => None,
+            };
+            let error = match &contents {
+                Some(Err(e)) => Some(e.to_string()),
+                _ => None,
+            };
+            let mat_dep = match insert_unseen(
+                deps,
+                seen,
+                node,
+                MDLDependency {
+                    kind: MDLDependencyKind::Material,
+                    path,
+                    status,
+                    error,
+                },
+                MDLDependencyType::Indirect,
+            ) {
+                Some(mat_dep) => mat_dep,
+                None => continue,
+            };
 
-            if let Some(mat_path) = discovered {
-                let mat_dep = deps.insert(MDLDependency::Material(mat_path.to_path_buf()), MDLDependencyType::Indirect);
-                let vmt = crate::vmt::read(&mat_path)?;
+            if let Some(Ok(contents)) = contents {
+                let vmt = crate::vmt::parse(&String::from_utf8_lossy(&contents));
                 for tex in vmt.textures {
-                    let cleaned_up = tex.replace("\\", "/").to_lowercase();
-                    let tex_path = materials_path.join(format!("{}.vtf", cleaned_up));
-                    if tex_path.exists() && !discovered_textures.contains(&tex_path) {
-                        deps.insert_sub(mat_dep, MDLDependency::Texture(tex_path.to_path_buf()), MDLDependencyType::Indirect);
-                        discovered_textures.insert(tex_path);
+                    let tex = normalize_path(&tex);
+                    // render targets and cubemap placeholders are not files
+                    if tex.starts_with("_rt_") || tex == "env_cubemap" {
+                        continue;
                     }
+                    let tex_path = format!("materials/{}.vtf", tex.trim_end_matches(".vtf"));
+                    let status = self.locate(&tex_path, search_path);
+                    insert_unseen(
+                        deps,
+                        seen,
+                        mat_dep,
+                        MDLDependency {
+                            kind: MDLDependencyKind::Texture,
+                            path: tex_path,
+                            status,
+                            error: None,
+                        },
+                        MDLDependencyType::Indirect,
+                    );
                 }
             }
         }
 
-        Ok(deps)
+        let mut models: Vec<(MDLDependencyKind, String)> = self
+            .header
+            .include_models
+            .iter()
+            .map(|m| (MDLDependencyKind::IncludedModel, model_path(&m.name)))
+            .collect();
+        models.extend(
+            self.gib_names()
+                .iter()
+                .map(|name| (MDLDependencyKind::Gib, model_path(name))),
+        );
+        for (kind, path) in models {
+            if seen.contains(&path) {
+                continue;
+            }
+            let status = self.locate(&path, search_path);
+            // models inside archives can't be opened, so their dependencies are not included
+            let mdl = match &status {
+                MDLDependencyStatus::Local(p) => Some(MDLFile::open(p)),
+                MDLDependencyStatus::Found(ContentLocation::File { path, .. }) => {
+                    Some(MDLFile::open(path))
+                }
+                _ => None,
+            };
+            let error = match &mdl {
+                Some(Err(e)) => Some(e.to_string()),
+                _ => None,
+            };
+            let mdl_dep = insert_unseen(
+                deps,
+                seen,
+                node,
+                MDLDependency {
+                    kind,
+                    path,
+                    status,
+                    error,
+                },
+                MDLDependencyType::Indirect,
+            );
+            // a model that can't be read is listed, but doesn't stop the others
+            if let (Some(mdl_dep), Some(Ok(mdl))) = (mdl_dep, mdl) {
+                mdl.add_dependencies(deps, mdl_dep, search_path, seen)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn texture_name(&self, slot: u16) -> Result<&str, Error> {
+        self.header
+            .texture_names
+            .get(slot as usize)
+            .map(|n| n.as_str())
+            .ok_or(Error::IndexOutOfRange(
+                "texture",
+                slot as usize,
+                self.header.texture_names.len(),
+            ))
     }
 
     /// Return each skin as a vector of simple material names
-    pub fn skins_with_material_names(&self) -> Vec<Vec<String>> {
-        self.partial.skins
+    pub fn skins_with_material_names(&self) -> Result<Vec<Vec<String>>, Error> {
+        self.header
+            .skins
             .iter()
             .map(|s| {
                 s.0.iter()
-                    .map(|slot| self.partial.texture_names[*slot as usize].clone())
+                    .map(|slot| Ok(self.texture_name(*slot)?.to_string()))
                     .collect()
             })
             .collect()
     }
 
     /// Return each skin as a vector of absolute paths to the materials
-    pub fn skins_with_material_paths(&self) -> Vec<Vec<Option<PathBuf>>> {
-        let assets_path = self.assets_path();
-        let materials_path = assets_path.join("materials");
+    pub fn skins_with_material_paths(&self) -> Result<Vec<Vec<Option<PathBuf>>>, Error> {
+        let index = DirectoryIndex::new();
 
-        self.partial.skins
+        self.header
+            .skins
             .iter()
             .map(|s| {
                 s.0.iter()
                     .map(|slot| {
-                        let tex_name = &self.partial.texture_names[*slot as usize];
-                        self.discover_texture_path(&materials_path, &tex_name)
+                        let tex_name = self.texture_name(*slot)?;
+                        Ok(self.discover_texture_path(&index, tex_name))
                     })
                     .collect()
             })
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gma::{self, GMAEntry, GMAFile};
+
+    #[test]
+    fn converts_model_names_to_paths() {
+        assert_eq!(model_path("gibs\\Crate_Gib1"), "models/gibs/crate_gib1.mdl");
+        assert_eq!(
+            model_path("models/props/crate_anims.mdl"),
+            "models/props/crate_anims.mdl"
+        );
+    }
+
+    #[test]
+    fn finds_dependencies_from_nested_and_gma_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("game");
+        let addon = game.join("addons/foo");
+        fs::create_dir_all(addon.join("materials/props")).unwrap();
+        fs::write(
+            addon.join("materials/props/metal.vmt"),
+            "\"VertexLitGeneric\"\n{\n\"$basetexture\" \"props/metal\"\n\"$bumpmap\" \"props/metal_n\"\n}\n",
+        )
+        .unwrap();
+
+        // the gma also has the material, but the addon folder comes first
+        let entry = |name: &str, contents: &[u8]| GMAEntry {
+            name: name.to_string(),
+            size: contents.len() as u64,
+            crc: 0,
+            contents: Some(contents.to_vec()),
+        };
+        let gma_path = dir.path().join("content.gma");
+        let mut buf = vec![];
+        gma::write_gma(
+            &GMAFile {
+                name: "content".to_string(),
+                description: String::new(),
+                author: String::new(),
+                entries: vec![
+                    entry("materials/props/metal.vmt", b""),
+                    entry("materials/props/metal.vtf", b"VTF"),
+                ],
+            },
+            &mut buf,
+        )
+        .unwrap();
+        fs::write(&gma_path, buf).unwrap();
+
+        let mdl_path = dir.path().join("model/models/foo.mdl");
+        fs::create_dir_all(mdl_path.parent().unwrap()).unwrap();
+        fs::write(&mdl_path, binary::tests::test_mdl()).unwrap();
+        let mut mdl = MDLFile::open(&mdl_path).unwrap();
+        mdl.header.texture_dirs.push("props/".to_string());
+
+        let mut search_path = SearchPath::new();
+        search_path.add(&game).unwrap();
+        search_path.add(&addon).unwrap();
+        search_path.add(&gma_path).unwrap();
+
+        let deps = mdl.dependencies(&search_path).unwrap().flatten();
+        let root_of = |path: &str| {
+            let dep = deps.iter().find(|d| d.path == path).unwrap();
+            match &dep.status {
+                MDLDependencyStatus::Found(location) => search_path.root_index(location),
+                _ => None,
+            }
+        };
+        assert_eq!(root_of("materials/props/metal.vmt"), Some(1));
+        assert_eq!(root_of("materials/props/metal.vtf"), Some(2));
+
+        let missing: Vec<&str> = deps
+            .iter()
+            .filter(|d| d.status == MDLDependencyStatus::Missing)
+            .map(|d| d.path.as_str())
+            .collect();
+        assert_eq!(missing, vec!["materials/props/metal_n.vtf"]);
+    }
+}
@@ -0,0 +1,336 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use super::Error;
+
+const HEADER_LEN: u64 = 16;
+const SURFACE_HEADER_LEN: u64 = 28;
+const COMPACT_SURFACE_LEN: u64 = 48;
+const LEDGE_TREE_OFFSET: u64 = 32;
+const LEDGE_LEN: u64 = 16;
+const TRIANGLE_LEN: u64 = 16;
+const POINT_LEN: u64 = 16;
+
+/// Physics engine units are meters
+const INCHES_PER_METER: f32 = 1.0 / 0.0254;
+
+/// Convex piece of a collision solid
+#[derive(Debug, Clone, Default)]
+pub struct Convex {
+    /// Vertices in Source coordinates, relative to the bone of the solid
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[u16; 3]>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Solid {
+    pub convexes: Vec<Convex>,
+}
+
+/// Key-value section of the text part, e.g. `solid` or `editparams`
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub values: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Collision model of a model (.phy)
+#[derive(Debug, Clone)]
+pub struct PHY {
+    /// Should match the checksum of the mdl
+    pub checksum: i32,
+    pub solids: Vec<Solid>,
+    /// Text part describing solids, constraints and physics parameters
+    pub text: String,
+}
+
+impl PHY {
+    /// Returns sections of the text part, skipping nested blocks
+    pub fn sections(&self) -> Vec<Section> {
+        let mut tokens = tokenize(&self.text).into_iter();
+        let mut sections = vec![];
+        while let Some(name) = tokens.next() {
+            if tokens.next().as_deref() != Some("{") {
+                continue;
+            }
+            let mut section = Section {
+                name,
+                values: vec![],
+            };
+            while let Some(key) = tokens.next() {
+                match key.as_str() {
+                    "}" => break,
+                    "{" => {
+                        // nested blocks are not used by the sections we need
+                        let mut depth = 1;
+                        for t in tokens.by_ref() {
+                            match t.as_str() {
+                                "{" => depth += 1,
+                                "}" if depth == 1 => break,
+                                "}" => depth -= 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Some(value) = tokens.next() {
+                            section.values.push((key, value));
+                        }
+                    }
+                }
+            }
+            sections.push(section);
+        }
+        sections
+    }
+
+    /// Returns the bone name of each solid, from the `solid` sections
+    pub fn solid_bones(&self) -> Vec<Option<String>> {
+        let mut bones = vec![None; self.solids.len()];
+        for section in self.sections().iter().filter(|s| s.name == "solid") {
+            let index = section.get("index").and_then(|i| i.parse::<usize>().ok());
+            if let (Some(index), Some(name)) = (index, section.get("name")) {
+                if let Some(bone) = bones.get_mut(index) {
+                    *bone = Some(name.to_string());
+                }
+            }
+        }
+        bones
+    }
+}
+
+pub(super) fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => tokens.push(c.to_string()),
+            '"' => {
+                let mut token = String::new();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+            c if c.is_whitespace() || c == '\0' => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '"' || c == '\0' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    tokens
+}
+
+/// Converts a physics engine point to Source coordinates
+fn to_source(p: [f32; 3]) -> [f32; 3] {
+    [
+        p[0] * INCHES_PER_METER,
+        p[2] * INCHES_PER_METER,
+        -p[1] * INCHES_PER_METER,
+    ]
+}
+
+/// Reads the convex pieces (ledges) of a compact surface at `surface`
+fn read_surface(reader: &mut Cursor<&[u8]>, surface: u64, end: u64) -> Result<Solid, Error> {
+    // offset_ledgetree_root, after mass center, rotation inertia, radius and byte size
+    reader.seek(SeekFrom::Start(surface + LEDGE_TREE_OFFSET))?;
+    let ledge_tree = reader.read_i32::<LittleEndian>()?;
+    let ledges_end = (surface as i64 + ledge_tree as i64).clamp(0, end as i64) as u64;
+
+    let mut solid = Solid::default();
+    // ledges are followed by the points they share
+    let mut ledge = surface + COMPACT_SURFACE_LEN;
+    let mut points_start = ledges_end;
+    while ledge + LEDGE_LEN <= points_start {
+        reader.seek(SeekFrom::Start(ledge))?;
+        let point_offset = reader.read_i32::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?; // client data
+        reader.read_u32::<LittleEndian>()?; // flags and size
+        let triangle_count = reader.read_i16::<LittleEndian>()?.max(0) as u64;
+        reader.read_i16::<LittleEndian>()?;
+
+        let points = ledge as i64 + point_offset as i64;
+        if points < 0 || points as u64 > ledges_end {
+            return Err(Error::BadOffset("physics point", points));
+        }
+        points_start = points_start.min(points as u64);
+
+        let mut triangles = vec![];
+        for i in 0..triangle_count {
+            reader.seek(SeekFrom::Start(ledge + LEDGE_LEN + i * TRIANGLE_LEN + 4))?;
+            let mut triangle = [0; 3];
+            for index in &mut triangle {
+                *index = (reader.read_u32::<LittleEndian>()? & 0xffff) as u16;
+            }
+            triangles.push(triangle);
+        }
+
+        // points are shared, keep only the ones this ledge uses
+        let mut convex = Convex::default();
+        let mut remap = std::collections::HashMap::new();
+        for triangle in &triangles {
+            let mut mapped = [0; 3];
+            for (m, index) in mapped.iter_mut().zip(triangle) {
+                let point = points as u64 + *index as u64 * POINT_LEN;
+                if point + POINT_LEN > ledges_end {
+                    return Err(Error::BadOffset("physics point", point as i64));
+                }
+                *m = match remap.get(index) {
+                    Some(m) => *m,
+                    None => {
+                        reader.seek(SeekFrom::Start(point))?;
+                        let mut p = [0.0; 3];
+                        reader.read_f32_into::<LittleEndian>(&mut p)?;
+                        convex.vertices.push(to_source(p));
+                        let m = (convex.vertices.len() - 1) as u16;
+                        remap.insert(*index, m);
+                        m
+                    }
+                };
+            }
+            convex.triangles.push(mapped);
+        }
+        solid.convexes.push(convex);
+
+        ledge += LEDGE_LEN + triangle_count * TRIANGLE_LEN;
+    }
+    Ok(solid)
+}
+
+pub fn read(bytes: &[u8]) -> Result<PHY, Error> {
+    let mut reader = Cursor::new(bytes);
+
+    let header_len = reader.read_i32::<LittleEndian>()?;
+    if header_len as u64 != HEADER_LEN {
+        return Err(Error::InvalidHeader);
+    }
+    reader.read_i32::<LittleEndian>()?; // id
+    let solid_count = reader.read_i32::<LittleEndian>()?.max(0);
+    let checksum = reader.read_i32::<LittleEndian>()?;
+
+    let mut solids = vec![];
+    let mut pos = HEADER_LEN;
+    for _ in 0..solid_count {
+        reader.seek(SeekFrom::Start(pos))?;
+        let size = reader.read_i32::<LittleEndian>()?;
+        let start = pos + 4;
+        let end = start + size.max(0) as u64;
+        if end > bytes.len() as u64 {
+            return Err(Error::BadOffset("solid", pos as i64));
+        }
+
+        let mut id = [0; 4];
+        reader.read_exact(&mut id)?;
+        let surface = if &id == b"VPHY" {
+            start + SURFACE_HEADER_LEN
+        } else {
+            // older models have no header before the surface
+            start
+        };
+        solids.push(read_surface(&mut reader, surface, end)?);
+        pos = end;
+    }
+
+    let text_bytes = bytes.get(pos as usize..).unwrap_or_default();
+    let text_end = text_bytes
+        .iter()
+        .position(|b| *b == 0)
+        .unwrap_or(text_bytes.len());
+    let text = String::from_utf8_lossy(&text_bytes[..text_end]).into_owned();
+
+    Ok(PHY {
+        checksum,
+        solids,
+        text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_text_sections() {
+        let phy = PHY {
+            checksum: 0,
+            solids: vec![Solid::default(), Solid::default()],
+            text: "solid {\n\"index\" \"1\"\n\"name\" \"lid\"\n\"mass\" \"5.0\"\n}\n\
+                   editparams {\n\"rootname\" \"\"\n\"totalmass\" \"10\"\n\
+                   \"jointmerge\" \"a,b\"\n}\n"
+                .to_string(),
+        };
+        let sections = phy.sections();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1].get("totalmass"), Some("10"));
+        assert_eq!(phy.solid_bones(), vec![None, Some("lid".to_string())]);
+    }
+
+    #[test]
+    fn reads_compact_ledge() {
+        // header, then one solid: size, surface header, compact surface,
+        // a ledge with one triangle, its three points and the ledge tree
+        let mut buf = vec![];
+        for v in [16, 0, 1, 1234] {
+            buf.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        let solid = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(b"VPHY");
+        buf.extend_from_slice(&[0; 24]);
+
+        let surface = buf.len();
+        buf.resize(surface + COMPACT_SURFACE_LEN as usize, 0);
+        let tree = COMPACT_SURFACE_LEN + LEDGE_LEN + TRIANGLE_LEN + 3 * POINT_LEN;
+        let offset = surface + LEDGE_TREE_OFFSET as usize;
+        buf[offset..offset + 4].copy_from_slice(&(tree as i32).to_le_bytes());
+
+        // points follow the triangle
+        let points = (LEDGE_LEN + TRIANGLE_LEN) as i32;
+        for v in [points, 0, 0, 1] {
+            buf.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        for v in [0, 0, 1, 2] {
+            buf.extend_from_slice(&u32::to_le_bytes(v));
+        }
+        for p in [[0.0, 0.0, 0.0], [0.0254, 0.0, 0.0], [0.0, 0.0, 0.0254]] {
+            for v in [p[0], p[1], p[2], 0.0f32] {
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&[0; 16]);
+        let size = (buf.len() - solid - 4) as i32;
+        buf[solid..solid + 4].copy_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(b"solid {\n\"index\" \"0\"\n}\n\0");
+
+        let phy = read(&buf).unwrap();
+        assert_eq!(phy.checksum, 1234);
+        assert_eq!(phy.solids.len(), 1);
+        let convexes = &phy.solids[0].convexes;
+        assert_eq!(convexes.len(), 1);
+        assert_eq!(convexes[0].triangles, vec![[0, 1, 2]]);
+        assert_eq!(convexes[0].vertices.len(), 3);
+        assert!((convexes[0].vertices[1][0] - 1.0).abs() < 1e-4);
+        assert!((convexes[0].vertices[2][1] - 1.0).abs() < 1e-4);
+        assert!(phy.text.starts_with("solid"));
+    }
+}
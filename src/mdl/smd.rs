@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+use super::animation::{quaternion_to_euler, BonePose};
+use super::binary::Bone;
+use crate::mesh::Mesh;
+
+fn write_nodes<W: Write>(writer: &mut W, bones: &[Bone]) -> Result<(), io::Error> {
+    writeln!(writer, "version 1")?;
+    writeln!(writer, "nodes")?;
+    for (i, bone) in bones.iter().enumerate() {
+        writeln!(writer, "{} \"{}\" {}", i, bone.name, bone.parent)?;
+    }
+    writeln!(writer, "end")
+}
+
+fn write_bone<W: Write>(
+    writer: &mut W,
+    index: usize,
+    position: [f32; 3],
+    rotation: [f32; 3],
+) -> Result<(), io::Error> {
+    writeln!(
+        writer,
+        "{} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}",
+        index, position[0], position[1], position[2], rotation[0], rotation[1], rotation[2]
+    )
+}
+
+/// Writes a reference SMD with the bind pose of `bones` and the triangles of `mesh`
+pub fn write_reference<W: Write>(
+    writer: &mut W,
+    bones: &[Bone],
+    mesh: &Mesh,
+) -> Result<(), io::Error> {
+    write_nodes(writer, bones)?;
+
+    writeln!(writer, "skeleton")?;
+    writeln!(writer, "time 0")?;
+    for (i, bone) in bones.iter().enumerate() {
+        write_bone(writer, i, bone.position, bone.rotation)?;
+    }
+    writeln!(writer, "end")?;
+
+    writeln!(writer, "triangles")?;
+    for primitive in &mesh.primitives {
+        for triangle in primitive.indices.chunks_exact(3) {
+            writeln!(writer, "{}", primitive.material)?;
+            for i in triangle {
+                let i = *i as usize;
+                let p = mesh.positions[i];
+                let n = mesh.normals[i];
+                let uv = mesh.uvs[i];
+                let links: Vec<(u8, f32)> = match (mesh.joints.get(i), mesh.weights.get(i)) {
+                    (Some(joints), Some(weights)) => joints
+                        .iter()
+                        .zip(weights)
+                        .filter(|(_, w)| **w > 0.0)
+                        .map(|(j, w)| (*j, *w))
+                        .collect(),
+                    _ => vec![],
+                };
+
+                write!(
+                    writer,
+                    "{} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {}",
+                    links.first().map_or(0, |(j, _)| *j),
+                    p[0],
+                    p[1],
+                    p[2],
+                    n[0],
+                    n[1],
+                    n[2],
+                    uv[0],
+                    // SMD texture coordinates start from the bottom
+                    1.0 - uv[1],
+                    links.len()
+                )?;
+                for (joint, weight) in links {
+                    write!(writer, " {} {:.6}", joint, weight)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+    }
+    writeln!(writer, "end")
+}
+
+/// Writes an animation SMD with a pose of every bone per frame
+pub fn write_animation<W: Write>(
+    writer: &mut W,
+    bones: &[Bone],
+    frames: &[Vec<BonePose>],
+) -> Result<(), io::Error> {
+    write_nodes(writer, bones)?;
+
+    writeln!(writer, "skeleton")?;
+    for (time, poses) in frames.iter().enumerate() {
+        writeln!(writer, "time {}", time)?;
+        for (i, pose) in poses.iter().enumerate() {
+            write_bone(writer, i, pose.position, quaternion_to_euler(pose.rotation))?;
+        }
+    }
+    writeln!(writer, "end")
+}
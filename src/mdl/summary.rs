@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::convert::TryFrom;
+
+use super::animation::matrix_to_euler;
+use super::binary::STUDIO_LOOPING;
+use super::MDLFile;
+
+/// Body group (body part) and the names of the models that can be chosen for it
+#[derive(Debug, Clone, Serialize)]
+pub struct BodyGroupSummary {
+    pub index: usize,
+    pub name: String,
+    /// Multiplier of the model index in the body value
+    pub base: i32,
+    pub models: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachmentSummary {
+    pub index: usize,
+    pub name: String,
+    pub bone: String,
+    /// Position relative to the bone
+    pub position: [f32; 3],
+    /// Pitch, yaw and roll relative to the bone in degrees
+    pub angles: [f32; 3],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SequenceSummary {
+    pub index: usize,
+    pub name: String,
+    /// Activity name, e.g. "ACT_IDLE", or empty if the sequence has none
+    pub activity: String,
+    pub activity_weight: i32,
+    /// Frame rate and frame count of the first animation of the sequence
+    pub fps: f32,
+    pub frames: i32,
+    pub looping: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HitboxSummary {
+    pub set: String,
+    pub name: String,
+    pub bone: String,
+    /// Hit group, e.g. 1 for head
+    pub group: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+impl MDLFile {
+    pub fn body_groups(&self) -> Vec<BodyGroupSummary> {
+        self.header
+            .body_parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| BodyGroupSummary {
+                index,
+                name: part.name.clone(),
+                base: part.base,
+                models: part.models.iter().map(|m| m.name.clone()).collect(),
+            })
+            .collect()
+    }
+
+    pub fn attachments(&self) -> Vec<AttachmentSummary> {
+        self.header
+            .attachments
+            .iter()
+            .enumerate()
+            .map(|(index, attachment)| {
+                let m = attachment.local;
+                let [roll, pitch, yaw] = matrix_to_euler([
+                    [m[0][0], m[0][1], m[0][2]],
+                    [m[1][0], m[1][1], m[1][2]],
+                    [m[2][0], m[2][1], m[2][2]],
+                ]);
+                AttachmentSummary {
+                    index,
+                    name: attachment.name.clone(),
+                    bone: self.bone_name(attachment.bone).to_string(),
+                    position: [m[0][3], m[1][3], m[2][3]],
+                    // adding zero turns -0 into 0
+                    angles: [pitch, yaw, roll].map(|a| a.to_degrees() + 0.0),
+                }
+            })
+            .collect()
+    }
+
+    pub fn sequences(&self) -> Vec<SequenceSummary> {
+        self.header
+            .sequences
+            .iter()
+            .enumerate()
+            .map(|(index, sequence)| {
+                let animation = sequence
+                    .animations
+                    .first()
+                    .and_then(|i| usize::try_from(*i).ok())
+                    .and_then(|i| self.header.animations.get(i));
+                SequenceSummary {
+                    index,
+                    name: sequence.label.clone(),
+                    activity: sequence.activity_name.clone(),
+                    activity_weight: sequence.activity_weight,
+                    fps: animation.map_or(0.0, |a| a.fps),
+                    frames: animation.map_or(0, |a| a.frame_count),
+                    looping: sequence.flags & STUDIO_LOOPING != 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the hitboxes of every hitbox set
+    pub fn hitboxes(&self) -> Vec<HitboxSummary> {
+        self.header
+            .hitbox_sets
+            .iter()
+            .flat_map(|set| {
+                set.hitboxes.iter().map(move |hitbox| HitboxSummary {
+                    set: set.name.clone(),
+                    name: hitbox.name.clone(),
+                    bone: self.bone_name(hitbox.bone).to_string(),
+                    group: hitbox.group,
+                    mins: hitbox.mins,
+                    maxs: hitbox.maxs,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::binary::{self, Animation, Attachment, Sequence};
+    use super::*;
+
+    #[test]
+    fn summarizes_attachments_and_sequences() {
+        let mut mdl = MDLFile::from_bytes(binary::tests::test_mdl()).unwrap();
+        mdl.header.attachments.push(Attachment {
+            name: "muzzle".to_string(),
+            flags: 0,
+            bone: 0,
+            local: [
+                [1.0, 0.0, 0.0, 4.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 2.0],
+            ],
+        });
+        mdl.header.animations.push(Animation {
+            offset: 0,
+            name: "@idle".to_string(),
+            fps: 15.0,
+            flags: 0,
+            frame_count: 10,
+            anim_block: 0,
+            anim_index: 0,
+            section_index: 0,
+            section_frames: 0,
+        });
+        mdl.header.sequences.push(Sequence {
+            label: "idle".to_string(),
+            activity_name: "ACT_IDLE".to_string(),
+            flags: STUDIO_LOOPING,
+            activity: 1,
+            activity_weight: 1,
+            events: vec![],
+            mins: [0.0; 3],
+            maxs: [0.0; 3],
+            group_size: [1, 1],
+            animations: vec![0],
+            param_index: [-1, -1],
+            param_start: [0.0; 2],
+            param_end: [0.0; 2],
+            fade_in_time: 0.2,
+            fade_out_time: 0.2,
+            entry_node: 0,
+            exit_node: 0,
+            node_flags: 0,
+            keyvalues: String::new(),
+            activity_modifiers: vec![],
+        });
+
+        let attachments = mdl.attachments();
+        assert_eq!(attachments[0].bone, "root");
+        assert_eq!(attachments[0].position, [4.0, 0.0, 2.0]);
+        assert_eq!(attachments[0].angles, [0.0, 0.0, 0.0]);
+
+        let sequences = mdl.sequences();
+        assert_eq!(sequences[0].activity, "ACT_IDLE");
+        assert_eq!(sequences[0].fps, 15.0);
+        assert_eq!(sequences[0].frames, 10);
+        assert!(sequences[0].looping);
+    }
+}
@@ -0,0 +1,338 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use super::Error;
+
+const BODY_PART_LEN: u64 = 8;
+const MODEL_LEN: u64 = 8;
+const MODEL_LOD_LEN: u64 = 12;
+const MESH_LEN: u64 = 9;
+const STRIP_GROUP_LEN: u64 = 25;
+const STRIP_LEN: u64 = 27;
+const VERTEX_LEN: u64 = 9;
+
+pub const STRIP_IS_TRILIST: u8 = 0x1;
+pub const STRIP_IS_TRISTRIP: u8 = 0x2;
+
+/// Vertex of a strip group, referring to a vertex of the mesh in the .vvd
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub bone_weight_indices: [u8; 3],
+    pub bone_count: u8,
+    /// Index of the vertex relative to the first vertex of the mdl mesh
+    pub original_mesh_vertex: u16,
+    /// Bones in the hardware bone palette of the strip
+    pub bone_ids: [i8; 3],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Strip {
+    pub index_count: i32,
+    pub index_offset: i32,
+    pub vertex_count: i32,
+    pub vertex_offset: i32,
+    pub bone_count: i16,
+    pub flags: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct StripGroup {
+    pub flags: u8,
+    pub vertices: Vec<Vertex>,
+    /// Indices into `vertices`
+    pub indices: Vec<u16>,
+    pub strips: Vec<Strip>,
+}
+
+impl StripGroup {
+    /// Returns triangles as indices into `vertices`, converting triangle strips to lists.
+    /// Strip indices already index the vertices of the whole strip group, and triangles
+    /// referring to vertices the strip group doesn't have are skipped
+    pub fn triangles(&self) -> Vec<[u16; 3]> {
+        let in_range = |t: &[u16; 3]| t.iter().all(|i| (*i as usize) < self.vertices.len());
+        let mut triangles = vec![];
+        for strip in &self.strips {
+            let start = strip.index_offset.max(0) as usize;
+            let end = start + strip.index_count.max(0) as usize;
+            let indices = match self.indices.get(start..end) {
+                Some(indices) => indices,
+                None => continue,
+            };
+            if strip.flags & STRIP_IS_TRISTRIP != 0 {
+                for (i, w) in indices.windows(3).enumerate() {
+                    let t = if i % 2 == 0 {
+                        [w[0], w[1], w[2]]
+                    } else {
+                        [w[1], w[0], w[2]]
+                    };
+                    // degenerate triangles join strips
+                    if t[0] != t[1] && t[1] != t[2] && t[0] != t[2] && in_range(&t) {
+                        triangles.push(t);
+                    }
+                }
+            } else {
+                for t in indices.chunks_exact(3) {
+                    let t = [t[0], t[1], t[2]];
+                    if in_range(&t) {
+                        triangles.push(t);
+                    }
+                }
+            }
+        }
+        triangles
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub flags: u8,
+    pub strip_groups: Vec<StripGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelLod {
+    /// Distance the LOD is used from
+    pub switch_point: f32,
+    /// Meshes in the same order as in the mdl model
+    pub meshes: Vec<Mesh>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub lods: Vec<ModelLod>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BodyPart {
+    pub models: Vec<Model>,
+}
+
+/// Optimized mesh data of a model (.vtx), with body parts and models in the same
+/// order as in the mdl
+#[derive(Debug, Clone)]
+pub struct VTX {
+    pub version: i32,
+    pub vertex_cache_size: i32,
+    pub max_bones_per_strip: u16,
+    pub max_bones_per_triangle: u16,
+    pub max_bones_per_vertex: i32,
+    /// Should match the checksum of the mdl
+    pub checksum: i32,
+    pub lod_count: i32,
+    pub body_parts: Vec<BodyPart>,
+}
+
+type Reader<'a> = Cursor<&'a [u8]>;
+
+/// Reads count and offset of a table, returning the count and absolute offset of
+/// the table if it is inside the file. Offsets are relative to the structure at `base`
+fn read_table(
+    reader: &mut Reader,
+    what: &'static str,
+    base: u64,
+    len: u64,
+) -> Result<(u64, u64), Error> {
+    let count = reader.read_i32::<LittleEndian>()?;
+    let offset = reader.read_i32::<LittleEndian>()?;
+    if count <= 0 {
+        return Ok((0, 0));
+    }
+
+    let start = base as i64 + offset as i64;
+    let end = (count as u64)
+        .checked_mul(len)
+        .and_then(|l| l.checked_add(start.max(0) as u64));
+    match end {
+        Some(end) if start >= 0 && end <= reader.get_ref().len() as u64 => {
+            Ok((count as u64, start as u64))
+        }
+        _ => Err(Error::BadOffset(what, start)),
+    }
+}
+
+fn read_strip_group(reader: &mut Reader, base: u64) -> Result<StripGroup, Error> {
+    reader.seek(SeekFrom::Start(base))?;
+    let (vertex_count, vertex_offset) = read_table(reader, "strip group vertex", base, VERTEX_LEN)?;
+    let (index_count, index_offset) = read_table(reader, "strip group index", base, 2)?;
+    let (strip_count, strip_offset) = read_table(reader, "strip", base, STRIP_LEN)?;
+    let flags = reader.read_u8()?;
+
+    reader.seek(SeekFrom::Start(vertex_offset))?;
+    let mut vertices = vec![];
+    for _ in 0..vertex_count {
+        let mut bone_weight_indices = [0; 3];
+        reader.read_exact(&mut bone_weight_indices)?;
+        let bone_count = reader.read_u8()?;
+        let original_mesh_vertex = reader.read_u16::<LittleEndian>()?;
+        let mut bone_ids = [0; 3];
+        reader.read_i8_into(&mut bone_ids)?;
+        vertices.push(Vertex {
+            bone_weight_indices,
+            bone_count,
+            original_mesh_vertex,
+            bone_ids,
+        });
+    }
+
+    reader.seek(SeekFrom::Start(index_offset))?;
+    let mut indices = vec![0; index_count as usize];
+    reader.read_u16_into::<LittleEndian>(&mut indices)?;
+
+    let mut strips = vec![];
+    for i in 0..strip_count {
+        reader.seek(SeekFrom::Start(strip_offset + i * STRIP_LEN))?;
+        strips.push(Strip {
+            index_count: reader.read_i32::<LittleEndian>()?,
+            index_offset: reader.read_i32::<LittleEndian>()?,
+            vertex_count: reader.read_i32::<LittleEndian>()?,
+            vertex_offset: reader.read_i32::<LittleEndian>()?,
+            bone_count: reader.read_i16::<LittleEndian>()?,
+            flags: reader.read_u8()?,
+        });
+    }
+
+    Ok(StripGroup {
+        flags,
+        vertices,
+        indices,
+        strips,
+    })
+}
+
+fn read_mesh(reader: &mut Reader, base: u64) -> Result<Mesh, Error> {
+    reader.seek(SeekFrom::Start(base))?;
+    let (count, offset) = read_table(reader, "strip group", base, STRIP_GROUP_LEN)?;
+    let flags = reader.read_u8()?;
+
+    let mut strip_groups = vec![];
+    for i in 0..count {
+        strip_groups.push(read_strip_group(reader, offset + i * STRIP_GROUP_LEN)?);
+    }
+    Ok(Mesh {
+        flags,
+        strip_groups,
+    })
+}
+
+fn read_model(reader: &mut Reader, base: u64) -> Result<Model, Error> {
+    reader.seek(SeekFrom::Start(base))?;
+    let (lod_count, lod_offset) = read_table(reader, "model LOD", base, MODEL_LOD_LEN)?;
+
+    let mut lods = vec![];
+    for i in 0..lod_count {
+        let lod_base = lod_offset + i * MODEL_LOD_LEN;
+        reader.seek(SeekFrom::Start(lod_base))?;
+        let (mesh_count, mesh_offset) = read_table(reader, "mesh", lod_base, MESH_LEN)?;
+        let switch_point = reader.read_f32::<LittleEndian>()?;
+
+        let mut meshes = vec![];
+        for j in 0..mesh_count {
+            meshes.push(read_mesh(reader, mesh_offset + j * MESH_LEN)?);
+        }
+        lods.push(ModelLod {
+            switch_point,
+            meshes,
+        });
+    }
+    Ok(Model { lods })
+}
+
+/// Reads a version 7 .vtx. Strip groups and strips are expected to be in the
+/// original format, without the topology fields of some later engine branches
+pub fn read(bytes: &[u8]) -> Result<VTX, Error> {
+    let mut reader = Cursor::new(bytes);
+
+    let version = reader.read_i32::<LittleEndian>()?;
+    if version != 7 {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let vertex_cache_size = reader.read_i32::<LittleEndian>()?;
+    let max_bones_per_strip = reader.read_u16::<LittleEndian>()?;
+    let max_bones_per_triangle = reader.read_u16::<LittleEndian>()?;
+    let max_bones_per_vertex = reader.read_i32::<LittleEndian>()?;
+    let checksum = reader.read_i32::<LittleEndian>()?;
+    let lod_count = reader.read_i32::<LittleEndian>()?;
+    reader.read_i32::<LittleEndian>()?; // materialReplacementListOffset
+    let (body_part_count, body_part_offset) =
+        read_table(&mut reader, "body part", 0, BODY_PART_LEN)?;
+
+    let mut body_parts = vec![];
+    for i in 0..body_part_count {
+        let base = body_part_offset + i * BODY_PART_LEN;
+        reader.seek(SeekFrom::Start(base))?;
+        let (model_count, model_offset) = read_table(&mut reader, "model", base, MODEL_LEN)?;
+
+        let mut models = vec![];
+        for j in 0..model_count {
+            models.push(read_model(&mut reader, model_offset + j * MODEL_LEN)?);
+        }
+        body_parts.push(BodyPart { models });
+    }
+
+    Ok(VTX {
+        version,
+        vertex_cache_size,
+        max_bones_per_strip,
+        max_bones_per_triangle,
+        max_bones_per_vertex,
+        checksum,
+        lod_count,
+        body_parts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(flags: u8, index_offset: i32, index_count: i32, vertex_offset: i32) -> Strip {
+        Strip {
+            index_count,
+            index_offset,
+            vertex_count: 0,
+            vertex_offset,
+            bone_count: 0,
+            flags,
+        }
+    }
+
+    fn vertices(count: u16) -> Vec<Vertex> {
+        (0..count)
+            .map(|i| Vertex {
+                bone_weight_indices: [0; 3],
+                bone_count: 1,
+                original_mesh_vertex: i,
+                bone_ids: [0; 3],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converts_strips_to_triangles() {
+        let group = StripGroup {
+            flags: 0,
+            vertices: vertices(7),
+            indices: vec![0, 1, 2, 3, 4, 5, 6],
+            strips: vec![
+                strip(STRIP_IS_TRILIST, 0, 3, 0),
+                strip(STRIP_IS_TRISTRIP, 3, 4, 0),
+            ],
+        };
+        assert_eq!(group.triangles(), vec![[0, 1, 2], [3, 4, 5], [5, 4, 6]]);
+    }
+
+    #[test]
+    fn ignores_strip_vertex_offsets() {
+        // the second strip starts at vertex 3, but its indices already account for that
+        let group = StripGroup {
+            flags: 0,
+            vertices: vertices(6),
+            indices: vec![0, 1, 2, 3, 4, 5, 3, 4, 65535],
+            strips: vec![
+                strip(STRIP_IS_TRILIST, 0, 3, 0),
+                strip(STRIP_IS_TRILIST, 3, 6, 3),
+            ],
+        };
+        assert_eq!(group.triangles(), vec![[0, 1, 2], [3, 4, 5]]);
+    }
+}
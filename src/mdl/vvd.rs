@@ -0,0 +1,252 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use super::Error;
+
+const FIXUP_LEN: u64 = 12;
+const VERTEX_LEN: u64 = 48;
+const TANGENT_LEN: u64 = 16;
+
+pub const MAX_LODS: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BoneWeights {
+    pub weights: [f32; 3],
+    pub bones: [u8; 3],
+    pub bone_count: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Vertex {
+    pub bone_weights: BoneWeights,
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Range of vertices used by LODs up to `lod`. Fixups are used when vertices of
+/// lower detail LODs were dropped, in which case vertices of a LOD are the ranges
+/// of all fixups with at least that LOD
+#[derive(Debug, Clone, Copy)]
+pub struct Fixup {
+    pub lod: i32,
+    pub source_vertex: i32,
+    pub vertex_count: i32,
+}
+
+/// Vertex data of a model (.vvd)
+#[derive(Debug, Clone)]
+pub struct VVD {
+    pub version: i32,
+    /// Should match the checksum of the mdl
+    pub checksum: i32,
+    pub lod_count: i32,
+    pub lod_vertex_counts: [i32; MAX_LODS],
+    pub fixups: Vec<Fixup>,
+    pub vertices: Vec<Vertex>,
+    /// Tangents with the sign of the binormal in w, one per vertex
+    pub tangents: Vec<[f32; 4]>,
+}
+
+impl VVD {
+    fn lod_ranges(&self, lod: usize) -> Vec<(usize, usize)> {
+        if self.fixups.is_empty() {
+            return vec![(0, self.vertices.len())];
+        }
+        self.fixups
+            .iter()
+            .filter(|f| f.lod >= lod as i32)
+            .map(|f| {
+                let start = f.source_vertex.max(0) as usize;
+                (start, start + f.vertex_count.max(0) as usize)
+            })
+            .collect()
+    }
+
+    /// Returns vertices of given LOD in the order mdl vertex indices refer to
+    pub fn lod_vertices(&self, lod: usize) -> Result<Vec<Vertex>, Error> {
+        let mut vertices = vec![];
+        for (start, end) in self.lod_ranges(lod) {
+            let range = self.vertices.get(start..end).ok_or(Error::IndexOutOfRange(
+                "vertex",
+                end,
+                self.vertices.len(),
+            ))?;
+            vertices.extend_from_slice(range);
+        }
+        Ok(vertices)
+    }
+
+    /// Returns tangents of given LOD, matching `lod_vertices`
+    pub fn lod_tangents(&self, lod: usize) -> Result<Vec<[f32; 4]>, Error> {
+        let mut tangents = vec![];
+        if self.tangents.is_empty() {
+            return Ok(tangents);
+        }
+        for (start, end) in self.lod_ranges(lod) {
+            let range = self.tangents.get(start..end).ok_or(Error::IndexOutOfRange(
+                "tangent",
+                end,
+                self.tangents.len(),
+            ))?;
+            tangents.extend_from_slice(range);
+        }
+        Ok(tangents)
+    }
+}
+
+fn read_vector<R: Read>(reader: &mut R) -> Result<[f32; 3], Error> {
+    let mut v = [0.0; 3];
+    reader.read_f32_into::<LittleEndian>(&mut v)?;
+    Ok(v)
+}
+
+/// Checks that `count` elements of `len` bytes at `offset` fit in `file_len` bytes
+fn check_table(
+    what: &'static str,
+    count: u64,
+    offset: i32,
+    len: u64,
+    file_len: u64,
+) -> Result<(), Error> {
+    let end = count
+        .checked_mul(len)
+        .and_then(|l| l.checked_add(offset.max(0) as u64));
+    match end {
+        Some(end) if offset >= 0 && end <= file_len => Ok(()),
+        _ => Err(Error::BadOffset(what, offset as i64)),
+    }
+}
+
+pub fn read(bytes: &[u8]) -> Result<VVD, Error> {
+    let mut reader = Cursor::new(bytes);
+    let file_len = bytes.len() as u64;
+
+    let mut magic_buf = [0; 4];
+    reader.read_exact(&mut magic_buf)?;
+    if &magic_buf != b"IDSV" {
+        return Err(Error::InvalidHeader);
+    }
+
+    let version = reader.read_i32::<LittleEndian>()?;
+    if version != 4 {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let checksum = reader.read_i32::<LittleEndian>()?;
+    let lod_count = reader.read_i32::<LittleEndian>()?;
+    let mut lod_vertex_counts = [0; MAX_LODS];
+    reader.read_i32_into::<LittleEndian>(&mut lod_vertex_counts)?;
+    let fixup_count = reader.read_i32::<LittleEndian>()?.max(0) as u64;
+    let fixup_start = reader.read_i32::<LittleEndian>()?;
+    let vertex_start = reader.read_i32::<LittleEndian>()?;
+    let tangent_start = reader.read_i32::<LittleEndian>()?;
+
+    check_table("fixup", fixup_count, fixup_start, FIXUP_LEN, file_len)?;
+    let mut fixups = vec![];
+    reader.seek(SeekFrom::Start(fixup_start.max(0) as u64))?;
+    for _ in 0..fixup_count {
+        fixups.push(Fixup {
+            lod: reader.read_i32::<LittleEndian>()?,
+            source_vertex: reader.read_i32::<LittleEndian>()?,
+            vertex_count: reader.read_i32::<LittleEndian>()?,
+        });
+    }
+
+    // LOD 0 includes every vertex in the file
+    let vertex_count = lod_vertex_counts[0].max(0) as u64;
+    check_table("vertex", vertex_count, vertex_start, VERTEX_LEN, file_len)?;
+    let mut vertices = vec![];
+    reader.seek(SeekFrom::Start(vertex_start as u64))?;
+    for _ in 0..vertex_count {
+        let mut weights = [0.0; 3];
+        reader.read_f32_into::<LittleEndian>(&mut weights)?;
+        let mut bones = [0; 3];
+        reader.read_exact(&mut bones)?;
+        let bone_count = reader.read_u8()?;
+        vertices.push(Vertex {
+            bone_weights: BoneWeights {
+                weights,
+                bones,
+                bone_count,
+            },
+            position: read_vector(&mut reader)?,
+            normal: read_vector(&mut reader)?,
+            uv: [
+                reader.read_f32::<LittleEndian>()?,
+                reader.read_f32::<LittleEndian>()?,
+            ],
+        });
+    }
+
+    let mut tangents = vec![];
+    if tangent_start > 0 {
+        check_table(
+            "tangent",
+            vertex_count,
+            tangent_start,
+            TANGENT_LEN,
+            file_len,
+        )?;
+        reader.seek(SeekFrom::Start(tangent_start as u64))?;
+        for _ in 0..vertex_count {
+            let mut tangent = [0.0; 4];
+            reader.read_f32_into::<LittleEndian>(&mut tangent)?;
+            tangents.push(tangent);
+        }
+    }
+
+    Ok(VVD {
+        version,
+        checksum,
+        lod_count,
+        lod_vertex_counts,
+        fixups,
+        vertices,
+        tangents,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32) -> Vertex {
+        Vertex {
+            position: [x, 0.0, 0.0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn applies_fixups() {
+        let vvd = VVD {
+            version: 4,
+            checksum: 0,
+            lod_count: 2,
+            lod_vertex_counts: [4, 2, 0, 0, 0, 0, 0, 0],
+            fixups: vec![
+                Fixup {
+                    lod: 1,
+                    source_vertex: 0,
+                    vertex_count: 2,
+                },
+                Fixup {
+                    lod: 0,
+                    source_vertex: 2,
+                    vertex_count: 2,
+                },
+            ],
+            vertices: (0..4).map(|i| vertex(i as f32)).collect(),
+            tangents: vec![],
+        };
+
+        let lod0: Vec<f32> = vvd
+            .lod_vertices(0)
+            .unwrap()
+            .iter()
+            .map(|v| v.position[0])
+            .collect();
+        assert_eq!(lod0, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(vvd.lod_vertices(1).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,585 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Triangles sharing a material
+#[derive(Debug, Clone, Default)]
+pub struct Primitive {
+    pub material: String,
+    /// Material for each variant of the scene, e.g. model skins. Empty if
+    /// `material` is used in every variant
+    pub variant_materials: Vec<String>,
+    /// Vertex indices, three per triangle. Front faces are wound counter-clockwise
+    pub indices: Vec<u32>,
+}
+
+/// Triangle mesh in Source coordinates (Z up, inches)
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    /// Joints and weights of each vertex in skinned meshes, empty otherwise
+    pub joints: Vec<[u8; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub primitives: Vec<Primitive>,
+}
+
+impl Mesh {
+    /// Returns primitive for given material, creating it if needed
+    pub fn primitive(&mut self, material: &str) -> &mut Primitive {
+        let i = match self
+            .primitives
+            .iter()
+            .position(|p| p.material.eq_ignore_ascii_case(material))
+        {
+            Some(i) => i,
+            None => {
+                self.primitives.push(Primitive {
+                    material: material.to_string(),
+                    ..Default::default()
+                });
+                self.primitives.len() - 1
+            }
+        };
+        &mut self.primitives[i]
+    }
+
+    /// Adds a vertex, returning its index
+    pub fn add_vertex(&mut self, position: [f32; 3], normal: [f32; 3], uv: [f32; 2]) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        (self.positions.len() - 1) as u32
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.primitives.iter().map(|p| p.indices.len() / 3).sum()
+    }
+}
+
+/// Bone of a skeleton in Source coordinates
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Bind pose translation relative to the parent
+    pub translation: [f32; 3],
+    /// Bind pose rotation relative to the parent as a quaternion (x, y, z, w)
+    pub rotation: [f32; 4],
+    /// Transforms vertices from model space to bone space in the bind pose
+    pub inverse_bind: [[f32; 4]; 3],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    pub mesh: Option<Mesh>,
+    pub children: Vec<Node>,
+}
+
+/// Tree of meshes, optionally skinned to a shared skeleton
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    /// Skeleton used by meshes with joints
+    pub joints: Vec<Joint>,
+    /// Names of material variants, e.g. model skins
+    pub variants: Vec<String>,
+    /// PNG base color textures by material name
+    pub textures: HashMap<String, Vec<u8>>,
+}
+
+impl Scene {
+    /// Returns scene with a single node
+    pub fn from_mesh(mesh: Mesh, name: &str) -> Scene {
+        Scene {
+            nodes: vec![Node {
+                name: name.to_string(),
+                mesh: Some(mesh),
+                children: vec![],
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Returns meshes with the names of their nodes, parents first
+    pub fn meshes(&self) -> Vec<(&str, &Mesh)> {
+        fn visit<'a>(node: &'a Node, meshes: &mut Vec<(&'a str, &'a Mesh)>) {
+            if let Some(mesh) = &node.mesh {
+                meshes.push((&node.name, mesh));
+            }
+            for child in &node.children {
+                visit(child, meshes);
+            }
+        }
+
+        let mut meshes = vec![];
+        for node in &self.nodes {
+            visit(node, &mut meshes);
+        }
+        meshes
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.meshes().iter().map(|(_, m)| m.triangle_count()).sum()
+    }
+}
+
+/// Converts Source coordinates (Z up) to glTF/OBJ coordinates (Y up)
+fn y_up(v: [f32; 3]) -> [f32; 3] {
+    // adding 0 turns -0 into 0
+    [v[0], v[2], -v[1] + 0.0]
+}
+
+/// Converts a row-major Source transform to a column-major glTF matrix with Y up
+fn y_up_matrix(m: [[f32; 4]; 3]) -> [f32; 16] {
+    // y_up as a matrix, R; the converted rotation part is R * M * R^T
+    const R: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]];
+
+    let mut out = [0.0; 16];
+    for (row, r_row) in R.iter().enumerate() {
+        for (col, r_col) in R.iter().enumerate() {
+            let mut sum = 0.0;
+            for (i, r) in r_row.iter().enumerate() {
+                for (j, c) in r_col.iter().enumerate() {
+                    sum += r * m[i][j] * c;
+                }
+            }
+            out[col * 4 + row] = sum + 0.0;
+        }
+    }
+    let [x, y, z] = y_up([m[0][3], m[1][3], m[2][3]]);
+    out[12] = x;
+    out[13] = y;
+    out[14] = z;
+    out[15] = 1.0;
+    out
+}
+
+pub fn write_obj<W: Write>(scene: &Scene, writer: &mut W) -> Result<(), io::Error> {
+    // OBJ indices are global and start from 1
+    let mut offset = 1;
+    for (name, mesh) in scene.meshes() {
+        writeln!(writer, "o {}", name)?;
+        for p in &mesh.positions {
+            let [x, y, z] = y_up(*p);
+            writeln!(writer, "v {} {} {}", x, y, z)?;
+        }
+        for uv in &mesh.uvs {
+            // OBJ texture coordinates start from the bottom
+            writeln!(writer, "vt {} {}", uv[0], 1.0 - uv[1])?;
+        }
+        for n in &mesh.normals {
+            let [x, y, z] = y_up(*n);
+            writeln!(writer, "vn {} {} {}", x, y, z)?;
+        }
+
+        for primitive in &mesh.primitives {
+            writeln!(writer, "usemtl {}", primitive.material)?;
+            for triangle in primitive.indices.chunks(3) {
+                write!(writer, "f")?;
+                for i in triangle {
+                    let i = i + offset;
+                    write!(writer, " {}/{}/{}", i, i, i)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        offset += mesh.positions.len() as u32;
+    }
+
+    Ok(())
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+fn float_bytes<const N: usize>(values: impl IntoIterator<Item = [f32; N]>) -> Vec<u8> {
+    values
+        .into_iter()
+        .flatten()
+        .flat_map(|f| f.to_le_bytes())
+        .collect()
+}
+
+/// Collects glTF JSON objects and the binary buffer they refer to
+struct GltfBuilder<'a> {
+    scene: &'a Scene,
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+    textures: Vec<Value>,
+    materials: Vec<Value>,
+    material_indices: HashMap<String, usize>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+}
+
+impl<'a> GltfBuilder<'a> {
+    fn add_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        while !self.buffer.len().is_multiple_of(4) {
+            self.buffer.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.buffer_views.push(view);
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.len() - 1
+    }
+
+    /// Adds `data` to the buffer with given accessor, returning the accessor index
+    fn add_accessor(&mut self, data: &[u8], target: u32, mut accessor: Value) -> usize {
+        accessor["bufferView"] = json!(self.add_view(data, Some(target)));
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn material(&mut self, name: &str) -> usize {
+        let key = name.to_lowercase();
+        if let Some(i) = self.material_indices.get(&key) {
+            return *i;
+        }
+
+        let mut material = json!({ "name": name });
+        if let Some(png) = self.scene.textures.get(name) {
+            let view = self.add_view(png, None);
+            self.images
+                .push(json!({ "bufferView": view, "mimeType": "image/png" }));
+            self.textures
+                .push(json!({ "source": self.images.len() - 1 }));
+            material["pbrMetallicRoughness"] = json!({
+                "baseColorTexture": { "index": self.textures.len() - 1 },
+                "metallicFactor": 0.0,
+            });
+        }
+        self.materials.push(material);
+        self.material_indices.insert(key, self.materials.len() - 1);
+        self.materials.len() - 1
+    }
+
+    /// Adds mesh, returning its index unless it has no triangles
+    fn mesh(&mut self, mesh: &'a Mesh, name: &str) -> Option<usize> {
+        if mesh.positions.is_empty() || mesh.triangle_count() == 0 {
+            return None;
+        }
+
+        let positions: Vec<[f32; 3]> = mesh.positions.iter().map(|p| y_up(*p)).collect();
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in &positions {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+
+        let mut attributes = json!({});
+        attributes["POSITION"] = json!(self.add_accessor(
+            &float_bytes(positions.iter().copied()),
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": positions.len(),
+                "type": "VEC3",
+                "min": min,
+                "max": max,
+            }),
+        ));
+        attributes["NORMAL"] = json!(self.add_accessor(
+            &float_bytes(mesh.normals.iter().map(|n| y_up(*n))),
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": mesh.normals.len(),
+                "type": "VEC3",
+            }),
+        ));
+        attributes["TEXCOORD_0"] = json!(self.add_accessor(
+            &float_bytes(mesh.uvs.iter().copied()),
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": mesh.uvs.len(),
+                "type": "VEC2",
+            }),
+        ));
+        if !mesh.joints.is_empty() && !self.scene.joints.is_empty() {
+            let bytes: Vec<u8> = mesh.joints.iter().flatten().copied().collect();
+            attributes["JOINTS_0"] = json!(self.add_accessor(
+                &bytes,
+                ARRAY_BUFFER,
+                json!({
+                    "componentType": UNSIGNED_BYTE,
+                    "count": mesh.joints.len(),
+                    "type": "VEC4",
+                }),
+            ));
+            attributes["WEIGHTS_0"] = json!(self.add_accessor(
+                &float_bytes(mesh.weights.iter().copied()),
+                ARRAY_BUFFER,
+                json!({
+                    "componentType": FLOAT,
+                    "count": mesh.weights.len(),
+                    "type": "VEC4",
+                }),
+            ));
+        }
+
+        let mut primitives = vec![];
+        for primitive in mesh.primitives.iter().filter(|p| !p.indices.is_empty()) {
+            let bytes: Vec<u8> = primitive
+                .indices
+                .iter()
+                .flat_map(|i| i.to_le_bytes())
+                .collect();
+            let indices = self.add_accessor(
+                &bytes,
+                ELEMENT_ARRAY_BUFFER,
+                json!({
+                    "componentType": UNSIGNED_INT,
+                    "count": primitive.indices.len(),
+                    "type": "SCALAR",
+                }),
+            );
+
+            let mut json = json!({
+                "attributes": attributes,
+                "indices": indices,
+                "material": self.material(&primitive.material),
+            });
+
+            if !primitive.variant_materials.is_empty() {
+                let mut mappings: Vec<(usize, Vec<usize>)> = vec![];
+                for (variant, material) in primitive.variant_materials.iter().enumerate() {
+                    let material = self.material(material);
+                    match mappings.iter_mut().find(|(m, _)| *m == material) {
+                        Some((_, variants)) => variants.push(variant),
+                        None => mappings.push((material, vec![variant])),
+                    }
+                }
+                let mappings: Vec<Value> = mappings
+                    .into_iter()
+                    .map(|(material, variants)| {
+                        json!({ "material": material, "variants": variants })
+                    })
+                    .collect();
+                json["extensions"] = json!({
+                    "KHR_materials_variants": { "mappings": mappings }
+                });
+            }
+            primitives.push(json);
+        }
+
+        self.meshes
+            .push(json!({ "name": name, "primitives": primitives }));
+        Some(self.meshes.len() - 1)
+    }
+
+    fn node(&mut self, node: &'a Node) -> usize {
+        let children: Vec<usize> = node.children.iter().map(|c| self.node(c)).collect();
+
+        let mut json = json!({ "name": node.name });
+        if let Some(mesh) = &node.mesh {
+            if let Some(index) = self.mesh(mesh, &node.name) {
+                json["mesh"] = json!(index);
+                if !mesh.joints.is_empty() && !self.scene.joints.is_empty() {
+                    json["skin"] = json!(0);
+                }
+            }
+        }
+        if !children.is_empty() {
+            json["children"] = json!(children);
+        }
+        self.nodes.push(json);
+        self.nodes.len() - 1
+    }
+
+    /// Adds joints as nodes, returning the skin using them and the root joint nodes
+    fn skin(&mut self) -> (Value, Vec<usize>) {
+        let joints = &self.scene.joints;
+        let first = self.nodes.len();
+        let parent = |joint: &Joint| joint.parent.filter(|p| *p < joints.len());
+
+        let mut roots = vec![];
+        for (i, joint) in joints.iter().enumerate() {
+            let [x, y, z, w] = joint.rotation;
+            let length = (x * x + y * y + z * z + w * w).sqrt();
+            let [x, y, z] = y_up([x, y, z]);
+            let rotation = if length > 0.0 {
+                [x / length, y / length, z / length, w / length]
+            } else {
+                [0.0, 0.0, 0.0, 1.0]
+            };
+
+            let mut json = json!({
+                "name": joint.name,
+                "translation": y_up(joint.translation),
+                "rotation": rotation,
+            });
+            let children: Vec<usize> = (0..joints.len())
+                .filter(|j| parent(&joints[*j]) == Some(i))
+                .map(|j| first + j)
+                .collect();
+            if !children.is_empty() {
+                json["children"] = json!(children);
+            }
+            self.nodes.push(json);
+
+            if parent(joint).is_none() {
+                roots.push(first + i);
+            }
+        }
+
+        let matrices = float_bytes(joints.iter().map(|j| y_up_matrix(j.inverse_bind)));
+        let inverse_bind_matrices = self.add_view(&matrices, None);
+        self.accessors.push(json!({
+            "bufferView": inverse_bind_matrices,
+            "componentType": FLOAT,
+            "count": joints.len(),
+            "type": "MAT4",
+        }));
+        let inverse_bind_matrices = self.accessors.len() - 1;
+
+        let joint_nodes: Vec<usize> = (first..first + joints.len()).collect();
+        let skin = json!({
+            "joints": joint_nodes,
+            "inverseBindMatrices": inverse_bind_matrices,
+        });
+        (skin, roots)
+    }
+}
+
+/// Builds glTF JSON and the binary buffer it refers to
+fn build_gltf(scene: &Scene, buffer_uri: Option<&str>) -> (Value, Vec<u8>) {
+    let mut builder = GltfBuilder {
+        scene,
+        buffer: vec![],
+        buffer_views: vec![],
+        accessors: vec![],
+        images: vec![],
+        textures: vec![],
+        materials: vec![],
+        material_indices: HashMap::new(),
+        meshes: vec![],
+        nodes: vec![],
+    };
+
+    let mut roots: Vec<usize> = scene.nodes.iter().map(|n| builder.node(n)).collect();
+    let mut skins = vec![];
+    if !scene.joints.is_empty() {
+        let (skin, joint_roots) = builder.skin();
+        skins.push(skin);
+        roots.extend(joint_roots);
+    }
+
+    let mut buffer_json = json!({ "byteLength": builder.buffer.len() });
+    if let Some(uri) = buffer_uri {
+        buffer_json["uri"] = json!(uri);
+    }
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "steamws" },
+        "scene": 0,
+        "scenes": [{ "nodes": roots }],
+        "nodes": builder.nodes,
+        "buffers": [buffer_json],
+    });
+    // glTF doesn't allow empty arrays
+    let arrays = [
+        ("meshes", builder.meshes),
+        ("materials", builder.materials),
+        ("textures", builder.textures),
+        ("images", builder.images),
+        ("skins", skins),
+        ("bufferViews", builder.buffer_views),
+        ("accessors", builder.accessors),
+    ];
+    for (key, array) in arrays {
+        if !array.is_empty() {
+            gltf[key] = json!(array);
+        }
+    }
+    if !scene.variants.is_empty() {
+        let variants: Vec<Value> = scene
+            .variants
+            .iter()
+            .map(|v| json!({ "name": v }))
+            .collect();
+        gltf["extensionsUsed"] = json!(["KHR_materials_variants"]);
+        gltf["extensions"] = json!({
+            "KHR_materials_variants": { "variants": variants }
+        });
+    }
+    (gltf, builder.buffer)
+}
+
+/// Writes scene as binary glTF (.glb)
+pub fn write_glb<W: Write>(scene: &Scene, writer: &mut W) -> Result<(), io::Error> {
+    let (gltf, mut buffer) = build_gltf(scene, None);
+    let mut json = serde_json::to_vec(&gltf)?;
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !buffer.len().is_multiple_of(4) {
+        buffer.push(0);
+    }
+
+    let len = 12 + 8 + json.len() + 8 + buffer.len();
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(len as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+
+    writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&buffer)?;
+
+    Ok(())
+}
+
+/// Writes scene as glTF JSON, with the binary buffer in a .bin file next to it
+pub fn write_gltf(scene: &Scene, path: &Path) -> Result<(), io::Error> {
+    let bin_path = path.with_extension("bin");
+    let bin_uri = bin_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let (gltf, buffer) = build_gltf(scene, Some(&bin_uri));
+    std::fs::write(&bin_path, buffer)?;
+    std::fs::write(path, serde_json::to_string_pretty(&gltf)?)?;
+    Ok(())
+}
+
+/// Writes scene in the format matching file extension: .obj, .glb or .gltf.
+/// OBJ files only include the meshes in their bind pose
+pub fn write_file(scene: &Scene, path: &Path) -> Result<(), io::Error> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "obj" => write_obj(scene, &mut io::BufWriter::new(std::fs::File::create(path)?)),
+        "glb" => write_glb(scene, &mut io::BufWriter::new(std::fs::File::create(path)?)),
+        "gltf" => write_gltf(scene, path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "output should be a .obj, .glb or .gltf file",
+        )),
+    }
+}
@@ -0,0 +1,477 @@
+//! Navigation mesh (.nav) files, which are shipped next to maps as `maps/<map>.nav`
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use err_derive::Error;
+use petgraph::unionfind::UnionFind;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+const NAV_MAGIC: u32 = 0xFEED_FACE;
+/// Newest version written by Source 2013 games (TF2, CS:GO, Garry's Mod)
+const MAX_NAV_VERSION: u32 = 16;
+/// Subversion used by Team Fortress 2, which stores extra area attributes
+const TF2_SUBVERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(display = "IO error: {}", _0)]
+    Io(#[error(source)] io::Error),
+    #[error(display = "Invalid magic {:#x}, not a nav file", _0)]
+    InvalidMagic(u32),
+    #[error(display = "Unsupported nav version {}", _0)]
+    UnsupportedVersion(u32),
+}
+
+/// Direction of an area connection or ladder, in the order they are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+}
+
+#[derive(Debug, Clone)]
+pub struct HidingSpot {
+    pub id: u32,
+    pub position: [f32; 3],
+    pub flags: u8,
+}
+
+/// Path used by bots to anticipate enemies, with spots along it
+#[derive(Debug, Clone)]
+pub struct EncounterPath {
+    pub from_area: u32,
+    pub from_direction: u8,
+    pub to_area: u32,
+    pub to_direction: u8,
+    /// (hiding spot id, distance along the path in 0-255)
+    pub spots: Vec<(u32, u8)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Area {
+    pub id: u32,
+    /// `NAV_MESH_*` attribute flags
+    pub flags: u32,
+    pub north_west: [f32; 3],
+    pub south_east: [f32; 3],
+    pub north_east_z: f32,
+    pub south_west_z: f32,
+    /// Connected area ids for each `Direction`
+    pub connections: [Vec<u32>; 4],
+    pub hiding_spots: Vec<HidingSpot>,
+    pub encounter_paths: Vec<EncounterPath>,
+    /// Index to `NavMesh::places` plus one, or 0 if the area has no place
+    pub place: u16,
+    /// Ladder ids going up and down from this area
+    pub ladders: [Vec<u32>; 2],
+}
+
+impl Area {
+    pub fn mins(&self) -> [f32; 3] {
+        let z = self.north_west[2]
+            .min(self.south_east[2])
+            .min(self.north_east_z)
+            .min(self.south_west_z);
+        [self.north_west[0], self.north_west[1], z]
+    }
+
+    pub fn maxs(&self) -> [f32; 3] {
+        let z = self.north_west[2]
+            .max(self.south_east[2])
+            .max(self.north_east_z)
+            .max(self.south_west_z);
+        [self.south_east[0], self.south_east[1], z]
+    }
+
+    /// Returns connected area ids in all directions
+    pub fn connected_areas(&self) -> impl Iterator<Item = u32> + '_ {
+        self.connections.iter().flatten().copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ladder {
+    pub id: u32,
+    pub width: f32,
+    pub top: [f32; 3],
+    pub bottom: [f32; 3],
+    pub length: f32,
+    pub direction: u32,
+    /// Areas connected to the top (forward, left, right, behind) and the bottom
+    pub top_areas: [u32; 4],
+    pub bottom_area: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct NavMesh {
+    pub version: u32,
+    pub subversion: u32,
+    /// Size of the bsp the mesh was generated for. The engine considers the
+    /// mesh out of date if the map size differs
+    pub bsp_size: Option<u32>,
+    pub analyzed: bool,
+    pub places: Vec<String>,
+    pub areas: Vec<Area>,
+    pub ladders: Vec<Ladder>,
+}
+
+fn read_vector<R: Read>(reader: &mut R) -> Result<[f32; 3], io::Error> {
+    Ok([
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+    ])
+}
+
+fn read_ids<R: Read>(reader: &mut R) -> Result<Vec<u32>, io::Error> {
+    let count = reader.read_u32::<LittleEndian>()?;
+    (0..count)
+        .map(|_| reader.read_u32::<LittleEndian>())
+        .collect()
+}
+
+fn skip<R: Read>(reader: &mut R, len: u64) -> Result<(), io::Error> {
+    io::copy(&mut reader.take(len), &mut io::sink())?;
+    Ok(())
+}
+
+fn read_area<R: Read>(reader: &mut R, version: u32, subversion: u32) -> Result<Area, io::Error> {
+    let id = reader.read_u32::<LittleEndian>()?;
+    let flags = match version {
+        0..=8 => reader.read_u8()? as u32,
+        9..=12 => reader.read_u16::<LittleEndian>()? as u32,
+        _ => reader.read_u32::<LittleEndian>()?,
+    };
+    let north_west = read_vector(reader)?;
+    let south_east = read_vector(reader)?;
+    let north_east_z = reader.read_f32::<LittleEndian>()?;
+    let south_west_z = reader.read_f32::<LittleEndian>()?;
+
+    let mut connections: [Vec<u32>; 4] = Default::default();
+    for connection in connections.iter_mut() {
+        *connection = read_ids(reader)?;
+    }
+
+    let mut hiding_spots = vec![];
+    for _ in 0..reader.read_u8()? {
+        hiding_spots.push(if version == 1 {
+            HidingSpot {
+                id: 0,
+                position: read_vector(reader)?,
+                flags: 0,
+            }
+        } else {
+            HidingSpot {
+                id: reader.read_u32::<LittleEndian>()?,
+                position: read_vector(reader)?,
+                flags: reader.read_u8()?,
+            }
+        });
+    }
+
+    // approach areas, which were removed in version 15
+    if version < 15 {
+        let count = reader.read_u8()?;
+        skip(reader, count as u64 * 14)?;
+    }
+
+    let mut encounter_paths = vec![];
+    for _ in 0..reader.read_u32::<LittleEndian>()? {
+        let from_area = reader.read_u32::<LittleEndian>()?;
+        let from_direction = reader.read_u8()?;
+        let to_area = reader.read_u32::<LittleEndian>()?;
+        let to_direction = reader.read_u8()?;
+        let mut spots = vec![];
+        for _ in 0..reader.read_u8()? {
+            spots.push((reader.read_u32::<LittleEndian>()?, reader.read_u8()?));
+        }
+        encounter_paths.push(EncounterPath {
+            from_area,
+            from_direction,
+            to_area,
+            to_direction,
+            spots,
+        });
+    }
+
+    let place = if version >= 5 {
+        reader.read_u16::<LittleEndian>()?
+    } else {
+        0
+    };
+
+    let mut ladders: [Vec<u32>; 2] = Default::default();
+    if version >= 7 {
+        for ladder in ladders.iter_mut() {
+            *ladder = read_ids(reader)?;
+        }
+    }
+
+    // earliest occupy times per team
+    if version >= 8 {
+        skip(reader, 2 * 4)?;
+    }
+    // light intensity per corner
+    if version >= 11 {
+        skip(reader, 4 * 4)?;
+    }
+    // potentially visible areas and the area visibility is inherited from
+    if version >= 16 {
+        let count = reader.read_u32::<LittleEndian>()?;
+        skip(reader, count as u64 * 5 + 4)?;
+    }
+    // game specific data
+    if version >= 16 && subversion == TF2_SUBVERSION {
+        skip(reader, 4)?;
+    }
+
+    Ok(Area {
+        id,
+        flags,
+        north_west,
+        south_east,
+        north_east_z,
+        south_west_z,
+        connections,
+        hiding_spots,
+        encounter_paths,
+        place,
+        ladders,
+    })
+}
+
+fn read_ladder<R: Read>(reader: &mut R, version: u32) -> Result<Ladder, io::Error> {
+    let id = reader.read_u32::<LittleEndian>()?;
+    let width = reader.read_f32::<LittleEndian>()?;
+    let top = read_vector(reader)?;
+    let bottom = read_vector(reader)?;
+    let length = reader.read_f32::<LittleEndian>()?;
+    let direction = reader.read_u32::<LittleEndian>()?;
+    // "dangling" flag, only stored in version 6
+    if version == 6 {
+        reader.read_u8()?;
+    }
+
+    let mut top_areas = [0; 4];
+    for area in top_areas.iter_mut() {
+        *area = reader.read_u32::<LittleEndian>()?;
+    }
+    let bottom_area = reader.read_u32::<LittleEndian>()?;
+
+    Ok(Ladder {
+        id,
+        width,
+        top,
+        bottom,
+        length,
+        direction,
+        top_areas,
+        bottom_area,
+    })
+}
+
+/// Reads a nav mesh. Game specific data after the ladders is ignored
+pub fn read<R: Read>(reader: &mut R) -> Result<NavMesh, Error> {
+    let magic = reader.read_u32::<LittleEndian>()?;
+    if magic != NAV_MAGIC {
+        return Err(Error::InvalidMagic(magic));
+    }
+
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version == 0 || version > MAX_NAV_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let subversion = if version >= 10 {
+        reader.read_u32::<LittleEndian>()?
+    } else {
+        0
+    };
+    let bsp_size = if version >= 4 {
+        Some(reader.read_u32::<LittleEndian>()?)
+    } else {
+        None
+    };
+    let analyzed = version >= 14 && reader.read_u8()? != 0;
+
+    let mut places = vec![];
+    if version >= 5 {
+        for _ in 0..reader.read_u16::<LittleEndian>()? {
+            let len = reader.read_u16::<LittleEndian>()?;
+            let mut buf = vec![0; len as usize];
+            reader.read_exact(&mut buf)?;
+            let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            places.push(String::from_utf8_lossy(&buf[..end]).into_owned());
+        }
+        // whether there are areas without a place
+        if version > 11 {
+            reader.read_u8()?;
+        }
+    }
+
+    let mut areas = vec![];
+    for _ in 0..reader.read_u32::<LittleEndian>()? {
+        areas.push(read_area(reader, version, subversion)?);
+    }
+
+    let mut ladders = vec![];
+    if version >= 6 {
+        for _ in 0..reader.read_u32::<LittleEndian>()? {
+            ladders.push(read_ladder(reader, version)?);
+        }
+    }
+
+    Ok(NavMesh {
+        version,
+        subversion,
+        bsp_size,
+        analyzed,
+        places,
+        areas,
+        ladders,
+    })
+}
+
+impl NavMesh {
+    /// Returns name of the place of given area
+    pub fn place_name(&self, area: &Area) -> Option<&str> {
+        let index = (area.place as usize).checked_sub(1)?;
+        self.places.get(index).map(|p| p.as_str())
+    }
+
+    /// Returns connection and ladder references to area ids that don't exist,
+    /// as (area id, referenced id)
+    pub fn dangling_references(&self) -> Vec<(u32, u32)> {
+        let ids: HashMap<u32, usize> = self
+            .areas
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.id, i))
+            .collect();
+
+        let mut dangling = vec![];
+        for area in &self.areas {
+            for id in area.connected_areas() {
+                if !ids.contains_key(&id) {
+                    dangling.push((area.id, id));
+                }
+            }
+        }
+        dangling
+    }
+
+    /// Returns ids of areas extending outside given bounds, e.g. those of the map
+    pub fn areas_outside(&self, mins: [f32; 3], maxs: [f32; 3]) -> Vec<u32> {
+        self.areas
+            .iter()
+            .filter(|a| {
+                let (a_mins, a_maxs) = (a.mins(), a.maxs());
+                (0..3).any(|i| a_mins[i] < mins[i] || a_maxs[i] > maxs[i])
+            })
+            .map(|a| a.id)
+            .collect()
+    }
+
+    /// Groups areas into islands that can't be reached from each other, by
+    /// walking or using ladders. Islands are sorted from largest to smallest
+    pub fn islands(&self) -> Vec<Vec<u32>> {
+        let ids: HashMap<u32, usize> = self
+            .areas
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.id, i))
+            .collect();
+
+        let mut union = UnionFind::<usize>::new(self.areas.len());
+        for (i, area) in self.areas.iter().enumerate() {
+            for id in area.connected_areas() {
+                if let Some(j) = ids.get(&id) {
+                    union.union(i, *j);
+                }
+            }
+        }
+        for ladder in &self.ladders {
+            let ends: Vec<usize> = ladder
+                .top_areas
+                .iter()
+                .chain(std::iter::once(&ladder.bottom_area))
+                .filter_map(|id| ids.get(id).copied())
+                .collect();
+            for pair in ends.windows(2) {
+                union.union(pair[0], pair[1]);
+            }
+        }
+
+        let mut islands: HashMap<usize, Vec<u32>> = HashMap::new();
+        for (i, area) in self.areas.iter().enumerate() {
+            islands.entry(union.find(i)).or_default().push(area.id);
+        }
+        let mut islands: Vec<Vec<u32>> = islands.into_values().collect();
+        islands.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+        islands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(id: u32, connections: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&id.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // flags
+        for f in [0f32, 0., 0., 10., 10., 0., 0., 0.] {
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        // all connections go north
+        for (i, _) in Direction::ALL.iter().enumerate() {
+            let ids: &[u32] = if i == 0 { connections } else { &[] };
+            bytes.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+            for id in ids {
+                bytes.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        bytes.push(0); // hiding spots
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // encounter paths
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // place
+        bytes.extend_from_slice(&[0; 8]); // ladders
+        bytes.extend_from_slice(&[0; 8 + 16]); // occupy times, light intensity
+        bytes.extend_from_slice(&[0; 4 + 4]); // visible areas
+        bytes
+    }
+
+    #[test]
+    fn reads_version_16_and_finds_islands() {
+        let mut bytes = vec![];
+        for v in [NAV_MAGIC, 16, 1, 1234] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.push(1); // analyzed
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(b"Mid\0");
+        bytes.push(0);
+
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend(area(1, &[2]));
+        bytes.extend(area(2, &[]));
+        bytes.extend(area(3, &[]));
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // ladders
+
+        let mesh = read(&mut &bytes[..]).unwrap();
+        assert_eq!(mesh.bsp_size, Some(1234));
+        assert_eq!(mesh.areas.len(), 3);
+        assert_eq!(mesh.place_name(&mesh.areas[0]), Some("Mid"));
+        assert_eq!(mesh.islands(), vec![vec![1, 2], vec![3]]);
+    }
+}
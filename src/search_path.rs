@@ -0,0 +1,293 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::gma;
+
+/// Single root in a search path
+pub enum ContentRoot {
+    Folder(PathBuf),
+    /// GMA archive, kept in memory. Entries are keyed by normalized path
+    Gma {
+        path: PathBuf,
+        entries: HashMap<String, Vec<u8>>,
+    },
+}
+
+impl ContentRoot {
+    pub fn path(&self) -> &Path {
+        match self {
+            ContentRoot::Folder(p) => p,
+            ContentRoot::Gma { path, .. } => path,
+        }
+    }
+}
+
+/// Location of a file found from a search path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentLocation {
+    /// Loose file on disk, with the index of the root it was found from, if any
+    File { path: PathBuf, root: Option<usize> },
+    /// Entry (with normalized name) inside a GMA archive, and the index of its root
+    GmaEntry {
+        root: usize,
+        gma: PathBuf,
+        name: String,
+    },
+}
+
+impl ContentLocation {
+    /// Returns whether the file is named differently on disk than the
+    /// normalized `path` it was found with, see [`case_differs`]
+    pub fn case_differs(&self, path: &str) -> bool {
+        match self {
+            ContentLocation::File { path: file, .. } => case_differs(path, file),
+            ContentLocation::GmaEntry { .. } => false,
+        }
+    }
+}
+
+/// Cache of directory listings for finding files regardless of case, like
+/// Source does on Windows. Listings are read once, so files created after a
+/// lookup in the same directory are not found
+#[derive(Default)]
+pub struct DirectoryIndex {
+    /// Directory -> lowercase name -> name on disk
+    listings: RefCell<HashMap<PathBuf, HashMap<String, OsString>>>,
+}
+
+impl DirectoryIndex {
+    pub fn new() -> DirectoryIndex {
+        DirectoryIndex::default()
+    }
+
+    fn read_listing(dir: &Path) -> HashMap<String, OsString> {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let mut listing = HashMap::new();
+        for name in fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name())
+        {
+            let lowercase = name.to_string_lossy().to_lowercase();
+            // an exact match wins if names only differ in case
+            if !listing.contains_key(&lowercase) || name.to_string_lossy() == lowercase {
+                listing.insert(lowercase, name);
+            }
+        }
+        listing
+    }
+
+    /// Finds a file below `folder` whose path matches given game-relative path,
+    /// ignoring case of each component
+    pub fn find(&self, folder: &Path, path: &str) -> Option<PathBuf> {
+        let mut file = folder.to_path_buf();
+        for component in path
+            .split(['/', '\\'])
+            .filter(|c| !c.is_empty() && *c != ".")
+        {
+            if component == ".." {
+                file.push(component);
+                continue;
+            }
+            let name = self
+                .listings
+                .borrow_mut()
+                .entry(file.to_path_buf())
+                .or_insert_with(|| DirectoryIndex::read_listing(&file))
+                .get(&component.to_lowercase())?
+                .clone();
+            file.push(name);
+        }
+        if file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns whether the trailing components of `file` differ from the normalized
+/// `path` it was found with, e.g. "Materials/Foo.vmt" for "materials/foo.vmt".
+/// Case-sensitive filesystems, like Linux servers have, won't find such files
+pub fn case_differs(path: &str, file: &Path) -> bool {
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    let on_disk = file.iter().rev().take(components.len());
+    !on_disk
+        .zip(components.iter().rev())
+        .all(|(a, b)| a.to_string_lossy() == *b)
+}
+
+/// Ordered list of content roots (folders containing e.g. "materials" and "models",
+/// or GMA archives) that game-relative asset paths are resolved against.
+///
+/// Earlier roots take precedence over later ones, like Source's mount order.
+#[derive(Default)]
+pub struct SearchPath {
+    roots: Vec<ContentRoot>,
+    index: DirectoryIndex,
+}
+
+impl SearchPath {
+    pub fn new() -> SearchPath {
+        SearchPath::default()
+    }
+
+    pub fn add_folder(&mut self, path: &Path) {
+        self.roots.push(ContentRoot::Folder(path.to_path_buf()));
+    }
+
+    pub fn add_gma(&mut self, path: &Path) -> Result<(), io::Error> {
+        let gma = gma::read_gma_file(path, |_| true)?;
+        let entries = gma
+            .entries
+            .into_iter()
+            .filter_map(|e| Some((normalize_path(&e.name), e.contents?)))
+            .collect();
+        self.roots.push(ContentRoot::Gma {
+            path: path.to_path_buf(),
+            entries,
+        });
+        Ok(())
+    }
+
+    /// Adds a GMA root if given path is a .gma file and a folder root otherwise
+    pub fn add(&mut self, path: &Path) -> Result<(), io::Error> {
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("gma"))
+        {
+            self.add_gma(path)
+        } else {
+            self.add_folder(path);
+            Ok(())
+        }
+    }
+
+    pub fn roots(&self) -> &[ContentRoot] {
+        &self.roots
+    }
+
+    /// Returns location of given game-relative path in the first root containing it.
+    /// Files in folders are matched regardless of case
+    pub fn find(&self, path: &str) -> Option<ContentLocation> {
+        let path = normalize_path(path);
+        self.roots
+            .iter()
+            .enumerate()
+            .find_map(|(i, root)| match root {
+                ContentRoot::Folder(folder) => {
+                    self.index
+                        .find(folder, &path)
+                        .map(|file| ContentLocation::File {
+                            path: file,
+                            root: Some(i),
+                        })
+                }
+                ContentRoot::Gma { path: gma, entries } => {
+                    if entries.contains_key(&path) {
+                        Some(ContentLocation::GmaEntry {
+                            root: i,
+                            gma: gma.to_path_buf(),
+                            name: path.clone(),
+                        })
+                    } else {
+                        None
+                    }
+                }
+            })
+    }
+
+    /// Finds a file below a folder that isn't one of the roots (e.g. the assets
+    /// folder of a model), sharing the directory index of the search path
+    pub fn find_in_folder(&self, folder: &Path, path: &str) -> Option<PathBuf> {
+        self.index.find(folder, &normalize_path(path))
+    }
+
+    /// Returns index of the root given location was found from. Files that weren't
+    /// found from a root, e.g. the map itself, have none
+    pub fn root_index(&self, location: &ContentLocation) -> Option<usize> {
+        match location {
+            ContentLocation::File { root, .. } => *root,
+            ContentLocation::GmaEntry { root, .. } => Some(*root),
+        }
+    }
+
+    /// Lists normalized paths of the files in the archive containing given location
+    /// that start with given prefix
+    pub fn archive_files_with_prefix(
+        &self,
+        location: &ContentLocation,
+        prefix: &str,
+    ) -> Vec<String> {
+        let entries = match self.root_index(location).map(|i| &self.roots[i]) {
+            Some(ContentRoot::Gma { entries, .. }) => entries,
+            _ => return vec![],
+        };
+
+        let mut names: Vec<String> = entries
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn read(&self, location: &ContentLocation) -> Result<Vec<u8>, io::Error> {
+        match location {
+            ContentLocation::File { path, .. } => fs::read(path),
+            ContentLocation::GmaEntry { gma, name, .. } => self
+                .roots
+                .iter()
+                .find_map(|root| match root {
+                    ContentRoot::Gma { path, entries } if path == gma => entries.get(name),
+                    _ => None,
+                })
+                .cloned()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} not found in {}", name, gma.display()),
+                    )
+                }),
+        }
+    }
+}
+
+/// Normalizes game-relative path the way Source does: lowercase, forward slashes
+/// and no leading slash
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+        .trim_start_matches('/')
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_files_regardless_of_case() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Materials/Models")).unwrap();
+        fs::write(dir.path().join("Materials/Models/Crate.vmt"), "").unwrap();
+
+        let index = DirectoryIndex::new();
+        let file = index
+            .find(dir.path(), "materials/models/crate.vmt")
+            .unwrap();
+        assert_eq!(file, dir.path().join("Materials/Models/Crate.vmt"));
+        assert!(case_differs("materials/models/crate.vmt", &file));
+        assert!(!case_differs("Models/Crate.vmt", &file));
+        assert_eq!(index.find(dir.path(), "materials/models/box.vmt"), None);
+    }
+}
@@ -11,9 +11,17 @@ use std::iter::FromIterator;
 #[derive(Debug)]
 pub struct VMT {
     pub textures: Vec<String>,
+    /// Value of $basetexture, if any
+    pub base_texture: Option<String>,
 }
 
 pub fn read(path: &Path) -> Result<VMT, Box<dyn std::error::Error>> {
+    let string = fs::read_to_string(path)?;
+    Ok(parse(&string))
+}
+
+/// Parses VMT contents, e.g. one read from a BSP pakfile
+pub fn parse(string: &str) -> VMT {
     lazy_static! {
         static ref RE: Regex = RegexBuilder::new(r#""?\$(?P<key>\w+)"?\s+(?P<value>.*)$"#)
             .multi_line(true)
@@ -31,17 +39,28 @@ pub fn read(path: &Path) -> Result<VMT, Box<dyn std::error::Error>> {
             m.insert("lightwarptexture");
             m.insert("envmap");
             m.insert("corneatexture");
+            // world materials
+            m.insert("basetexture2");
+            m.insert("bumpmap2");
+            m.insert("blendmodulatetexture");
+            m.insert("envmapmask");
             m
         };
     }
-    let string = fs::read_to_string(path)?;
-
+    let mut base_texture = None;
     let textures: HashSet<String> = RE
-        .captures_iter(&string)
+        .captures_iter(string)
         .filter(|c| KEYS.contains(&c["key"].to_lowercase() as &str))
-        .map(|c| c["value"].trim().trim_matches('"').trim().to_owned())
+        .map(|c| {
+            let value = c["value"].trim().trim_matches('"').trim().to_owned();
+            if base_texture.is_none() && c["key"].eq_ignore_ascii_case("basetexture") {
+                base_texture = Some(value.clone());
+            }
+            value
+        })
         .collect();
-    Ok(VMT {
+    VMT {
         textures: Vec::from_iter(textures),
-    })
+        base_texture,
+    }
 }